@@ -22,18 +22,33 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
  */
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     env,
-    fs::{create_dir_all, File},
-    io::Write,
+    fs::create_dir_all,
     path::PathBuf,
 };
 
 use eyre::{ContextCompat, Result, WrapErr};
-use lettre::{transport::smtp::authentication::Credentials, Address, SmtpTransport};
+use lettre::{message::Mailbox, transport::smtp::authentication::Credentials, Address};
 use magic_crypt::MagicCryptTrait;
 use serde::{Deserialize, Serialize};
 
+use crate::notify::diagnostics::Lookup;
+use crate::notify::digest::{self, DigestOptions};
+use crate::notify::email::{self, smtp_transport, AuthMechanism, Delivery, SmtpOptions, TlsMode};
+use crate::notify::escalation::Escalation;
+use crate::notify::heartbeat::HeartbeatOptions;
+use crate::notify::locale::Locale;
+use crate::notify::oauth::OAuth2;
+use crate::notify::outbox::OutboxOptions;
+use crate::notify::problem::ErrorOptions;
+use crate::notify::recipient::{self, Recipient, TAGS_KEY};
+use crate::notify::report::ReportOptions;
+use crate::notify::security::{self, SecurityOptions};
+use crate::notify::state_file;
+use crate::notify::template::HostMetadata;
+use crate::notify::{Channel, Event};
+
 use super::current_ip;
 
 pub type State = HashMap<String, String>;
@@ -42,21 +57,84 @@ pub type State = HashMap<String, String>;
 #[serde(rename_all = "camelCase")]
 pub struct Configuration {
     pub from_server: String,
+    #[serde(flatten)]
+    pub smtp: SmtpOptions,
     pub from_address: String,
+    /// The display name of the sender, if any.
+    #[serde(default)] // allow older configs missing this value
+    pub from_name: String,
+    /// The address that replies should go to, if not the sender.
+    #[serde(default)] // allow older configs missing this value
+    pub reply_to: String,
     encrypted_password: String,
-    pub to_addresses: Vec<String>,
+    #[serde(default)] // allow older configs missing this value
+    pub locale: Locale,
+    #[serde(alias = "toAddresses")] // older configs only have addresses
+    pub recipients: Vec<Recipient>,
+    #[serde(default)] // allow older configs missing this value
+    pub channels: Vec<Channel>,
+    #[serde(default)] // allow older configs missing this value
+    pub digest: DigestOptions,
+    #[serde(default)] // allow older configs missing this value
+    pub errors: ErrorOptions,
+    #[serde(default)] // allow older configs missing this value
+    pub heartbeat: HeartbeatOptions,
+    #[serde(default)] // allow older configs missing this value
+    pub reports: ReportOptions,
+    /// Whether to add diagnostics to notifications.
+    #[serde(default)] // allow older configs missing this value
+    pub diagnostics: bool,
+    #[serde(default)] // allow older configs missing this value
+    pub outbox: OutboxOptions,
+    #[serde(default)] // allow older configs missing this value
+    pub escalations: Vec<Escalation>,
+    #[serde(default)] // allow older configs missing this value
+    pub security: SecurityOptions,
+    /// A URL that addresses in emails link to, with `{address}` standing for the
+    /// address (empty for no links).
+    #[serde(default)] // allow older configs missing this value
+    pub address_lookup_url: String,
+    /// A directory of templates that override the built-in ones (empty for none).
+    #[serde(default)] // allow older configs missing this value
+    pub template_dir: String,
+    /// Metadata for each host, such as its tags, that templates can refer to.
+    #[serde(default)] // allow older configs missing this value
+    pub host_metadata: HostMetadata,
     #[serde(default)] // allow older configs missing this value
     pub last_update: i64,
+    /// When each host's address last changed, in millis.
+    #[serde(default)] // allow older configs missing this value
+    pub last_changed: BTreeMap<String, i64>,
+    /// When the last daily summary was sent, in millis.
+    #[serde(default)] // allow older configs missing this value
+    pub last_summary: i64,
+    /// When the last stability report was sent, in millis.
+    #[serde(default)] // allow older configs missing this value
+    pub last_report: i64,
+    /// The Message-ID that each host's emails refer to, so they are threaded together.
+    #[serde(default)] // allow older configs missing this value
+    pub threads: BTreeMap<String, String>,
+    /// The hosts whose thread root has been sent as the Message-ID of an email.
+    #[serde(default)] // allow older configs missing this value
+    pub started_threads: BTreeSet<String>,
     pub state: State,
     #[serde(skip, default = "serde_aux::field_attributes::bool_true")]
     pub is_file_based: bool,
+    /// The most recent lookup of each host, for diagnostics.
+    #[serde(skip)]
+    pub lookups: BTreeMap<String, Lookup>,
+    /// Events waiting to be sent in the next digest, which are saved in their own file.
+    #[serde(skip)]
+    pub digest_queue: Vec<Event>,
 }
 
 impl Configuration {
     pub fn new_from_config_file() -> Result<Self> {
         let path = config_path()?;
         let config_text = std::fs::read_to_string(path)?;
-        let config: Configuration = serde_json::from_str(&config_text)?;
+        let mut config: Configuration = serde_json::from_str(&config_text)?;
+        config.digest_queue = digest::load_queue(&digest::queue_path()?)?;
+        Escalation::name_unnamed(&mut config.escalations);
         Ok(config)
     }
 
@@ -70,7 +148,7 @@ impl Configuration {
         let from_address = env::var("DDNS_FROM_ADDRESS").expect("from address");
         let from_password = env::var("DDNS_FROM_PASSWORD").expect("from password");
         let to_address = env::var("DDNS_TO_ADDRESS").expect("to address");
-        let to_addresses = vec![to_address.clone(), from_address.clone()];
+        let recipients = vec![Recipient::new(&to_address), Recipient::new(&from_address)];
         let mut state = State::new();
         for i in 1..10 {
             let name = format!("DDNS_HOST_{i}");
@@ -89,30 +167,64 @@ impl Configuration {
         }
         Self {
             from_server: "smtp.gmail.com".to_string(),
+            smtp: SmtpOptions::default(),
             from_address,
+            from_name: String::new(),
+            reply_to: String::new(),
             encrypted_password: encrypt_password(&from_password).expect("encryption"),
-            to_addresses,
+            locale: Locale::default(),
+            recipients,
+            channels: vec![],
+            digest: DigestOptions::default(),
+            errors: ErrorOptions::default(),
+            heartbeat: HeartbeatOptions::default(),
+            reports: ReportOptions::default(),
+            diagnostics: false,
+            outbox: OutboxOptions::default(),
+            escalations: vec![],
+            security: SecurityOptions::default(),
+            address_lookup_url: String::new(),
+            template_dir: String::new(),
+            host_metadata: HostMetadata::new(),
             last_update: last_lookup,
+            last_changed: BTreeMap::new(),
+            last_summary: 0,
+            last_report: 0,
+            threads: BTreeMap::new(),
+            started_threads: BTreeSet::new(),
             state,
             is_file_based: false,
+            lookups: BTreeMap::new(),
+            digest_queue: vec![],
         }
     }
 
-    pub fn save_to_config_file(&self) -> Result<PathBuf> {
+    pub fn save_to_config_file(&mut self) -> Result<PathBuf> {
+        self.update_refresh_token()?;
+        self.started_threads.extend(email::started_threads());
         let path = config_path()?;
-        let config_text =
-            serde_json::to_string(self).wrap_err("Configuration cannot be serialized")?;
-        let mut file = File::create(&path).wrap_err(format!(
-            "Config file ({}) cannot be created",
-            path.display()
-        ))?;
-        file.write_all(config_text.as_bytes()).wrap_err(format!(
-            "Config file ({}) cannot be written",
-            path.display()
-        ))?;
+        state_file::save_atomic(&path, self, "Config")?;
         Ok(path)
     }
 
+    /// Keep any refresh token that the OAuth2 provider rotated while we were sending,
+    /// and return whether it changed.
+    fn update_refresh_token(&mut self) -> Result<bool> {
+        match self.smtp.oauth2.as_mut() {
+            Some(oauth2) => oauth2.update_refresh_token(),
+            None => Ok(false),
+        }
+    }
+
+    /// Save the configuration if the OAuth2 provider has rotated its refresh token,
+    /// because the provider may already have revoked the one in the config file.
+    pub fn save_refresh_token(&mut self) -> Result<()> {
+        if self.update_refresh_token()? && self.is_file_based {
+            self.save_to_config_file()?;
+        }
+        Ok(())
+    }
+
     pub fn password(&self) -> Result<String> {
         decrypt_password(&self.encrypted_password)
     }
@@ -125,19 +237,34 @@ impl Configuration {
 
     pub fn update_from_interview(&mut self) -> Result<()> {
         self.interview_from()?;
+        self.locale = self.locale.interview("Language of notifications")?;
         self.interview_to_addresses()?;
+        self.interview_channels()?;
+        self.digest = self.digest.interview()?;
+        self.errors = self.errors.interview()?;
+        self.heartbeat = self.heartbeat.interview()?;
+        self.reports = self.reports.interview()?;
+        self.diagnostics = dialoguer::Confirm::new()
+            .with_prompt("Add diagnostics (resolver, lookup times, versions) to notifications?")
+            .default(self.diagnostics)
+            .interact()
+            .wrap_err("Input error")?;
+        self.outbox = self.outbox.interview()?;
+        self.interview_escalations()?;
+        self.interview_security()?;
         self.interview_state()?;
         self.last_update = 0;
         Ok(())
     }
 
     fn interview_from(&mut self) -> Result<()> {
-        eprintln!("Sending notifications requires an SMTP server, email account, and password.");
+        eprintln!("Sending notifications requires a way to send email and a sender address.");
         let mut server = if self.from_server.is_empty() {
             "smtp.gmail.com".to_string()
         } else {
             self.from_server.clone()
         };
+        let mut smtp = self.smtp.clone();
         let mut account = self.from_address.clone();
         let mut password = if self.encrypted_password.is_empty() {
             String::new()
@@ -145,21 +272,36 @@ impl Configuration {
             decrypt_password(self.encrypted_password.as_str())?
         };
         loop {
-            server = dialoguer::Input::new()
-                .with_prompt("Sending SMTP server")
-                .with_initial_text(&server)
-                .allow_empty(false)
-                .validate_with(|host: &String| -> std::result::Result<(), String> {
-                    if current_ip(host).is_ok() {
-                        Ok(())
-                    } else {
-                        Err(format!("{host} is not a valid hostname"))
-                    }
-                })
-                .interact()
-                .wrap_err("Input error")?;
+            let old_delivery = smtp.delivery;
+            smtp.interview_delivery()?;
+            if smtp.delivery == Delivery::Relay && old_delivery != Delivery::Relay {
+                server = "localhost".to_string();
+                smtp.tls_mode = TlsMode::None;
+                smtp.smtp_port = 0;
+            }
+            if smtp.delivery != Delivery::Sendmail {
+                server = dialoguer::Input::new()
+                    .with_prompt("Sending SMTP server")
+                    .with_initial_text(&server)
+                    .allow_empty(false)
+                    .validate_with(|host: &String| -> std::result::Result<(), String> {
+                        if current_ip(host).is_ok() {
+                            Ok(())
+                        } else {
+                            Err(format!("{host} is not a valid hostname"))
+                        }
+                    })
+                    .interact()
+                    .wrap_err("Input error")?;
+                smtp.interview_connection()?;
+            }
+            let account_prompt = if smtp.delivery == Delivery::Smtp {
+                "Sender account email"
+            } else {
+                "Sender email address"
+            };
             account = dialoguer::Input::new()
-                .with_prompt("Sender account email")
+                .with_prompt(account_prompt)
                 .with_initial_text(&account)
                 .allow_empty(false)
                 .validate_with(|email: &String| -> std::result::Result<(), String> {
@@ -171,41 +313,122 @@ impl Configuration {
                 })
                 .interact()
                 .wrap_err("Input error")?;
-            password = dialoguer::Input::new()
-                .with_prompt("Sender account password")
-                .with_initial_text(&password)
-                .allow_empty(false)
-                .validate_with(|pw: &String| -> std::result::Result<(), String> {
-                    if pw.eq(pw.trim()) {
-                        Ok(())
-                    } else {
-                        Err(format!("'{pw}' cannot have leading or trailing spaces"))
+            let creds = match smtp.delivery {
+                Delivery::Smtp if smtp.auth_mechanism == AuthMechanism::XOAuth2 => {
+                    let token = OAuth2::interview(&server, smtp.oauth2.take())
+                        .and_then(|oauth2| Ok((oauth2.access_token()?, oauth2)));
+                    match token {
+                        Ok((token, oauth2)) => {
+                            smtp.oauth2 = Some(oauth2);
+                            Some(Credentials::new(account.clone(), token))
+                        }
+                        Err(err) => {
+                            eprintln!("{err:#}");
+                            continue;
+                        }
                     }
-                })
-                .interact()
-                .wrap_err("Input error")?;
-            let creds = Credentials::new(account.clone(), password.clone());
-            let mailer = SmtpTransport::relay(&server)
-                .wrap_err(format!("Couldn't lookup {server}"))?
-                .credentials(creds)
-                .build();
-            if mailer.test_connection().is_ok() {
-                break;
-            } else {
-                eprintln!("Couldn't connect to that server with those credentials")
+                }
+                Delivery::Smtp => {
+                    password = dialoguer::Input::new()
+                        .with_prompt("Sender account password")
+                        .with_initial_text(&password)
+                        .allow_empty(false)
+                        .validate_with(|pw: &String| -> std::result::Result<(), String> {
+                            if pw.eq(pw.trim()) {
+                                Ok(())
+                            } else {
+                                Err(format!("'{pw}' cannot have leading or trailing spaces"))
+                            }
+                        })
+                        .interact()
+                        .wrap_err("Input error")?;
+                    Some(Credentials::new(account.clone(), password.clone()))
+                }
+                Delivery::Relay => None,
+                // there's no connection to test, and the program was checked on input
+                Delivery::Sendmail => break,
+            };
+            match smtp_transport(&server, &smtp, creds) {
+                Ok(mailer) if mailer.test_connection().is_ok() => break,
+                Ok(_) if smtp.delivery == Delivery::Relay => {
+                    eprintln!("Couldn't connect to that server")
+                }
+                Ok(_) => eprintln!("Couldn't connect to that server with those credentials"),
+                Err(err) => eprintln!("{err}"),
             }
         }
+        if smtp.delivery == Delivery::Sendmail {
+            server = String::new();
+        }
         self.from_server = server;
         self.from_address = account;
-        self.encrypted_password = encrypt_password(&password)?;
+        let uses_oauth2 =
+            smtp.delivery == Delivery::Smtp && smtp.auth_mechanism == AuthMechanism::XOAuth2;
+        if !uses_oauth2 {
+            smtp.oauth2 = None;
+        }
+        self.encrypted_password = if smtp.delivery == Delivery::Smtp && !uses_oauth2 {
+            encrypt_password(&password)?
+        } else {
+            // don't keep a password we don't need
+            String::new()
+        };
+        self.smtp = smtp;
+        self.from_name = dialoguer::Input::<String>::new()
+            .with_prompt("Sender display name (leave empty for none)")
+            .with_initial_text(&self.from_name)
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Input error")?
+            .trim()
+            .to_string();
+        self.reply_to = dialoguer::Input::<String>::new()
+            .with_prompt("Reply-To address (leave empty for replies to go to the sender)")
+            .with_initial_text(&self.reply_to)
+            .allow_empty(true)
+            .validate_with(|email: &String| -> std::result::Result<(), String> {
+                if email.trim().is_empty() || email.trim().parse::<Mailbox>().is_ok() {
+                    Ok(())
+                } else {
+                    Err(format!("{email} is not a valid email"))
+                }
+            })
+            .interact_text()
+            .wrap_err("Input error")?
+            .trim()
+            .to_string();
+        eprintln!("Addresses in emails can link to a page that describes them, such as https://ipinfo.io/{{address}}.");
+        self.address_lookup_url = dialoguer::Input::<String>::new()
+            .with_prompt(
+                "Address lookup URL, with {address} for the address (leave empty for no links)",
+            )
+            .with_initial_text(&self.address_lookup_url)
+            .allow_empty(true)
+            .validate_with(|url: &String| -> std::result::Result<(), String> {
+                let url = url.trim();
+                if url.is_empty() {
+                    Ok(())
+                } else if !url.starts_with("https://") && !url.starts_with("http://") {
+                    Err(format!("{url} is not an http or https URL"))
+                } else if !url.contains("{address}") {
+                    Err(format!("{url} doesn't contain {{address}}"))
+                } else {
+                    Ok(())
+                }
+            })
+            .interact_text()
+            .wrap_err("Input error")?
+            .trim()
+            .to_string();
         Ok(())
     }
 
     fn interview_to_addresses(&mut self) -> Result<()> {
-        let mut old_emails = self.to_addresses.iter();
+        let mut old_emails = self.recipients.iter().map(|r| &r.address);
         let mut new_emails = vec![];
-        if self.to_addresses.is_empty() {
+        if self.recipients.is_empty() {
             eprintln!("Please specify emails to notify, one per line.");
+            eprintln!("An email can have a display name, as in: Jane Doe <jane@example.com>");
             eprintln!("When done, enter just a dot ('.') alone on the line.");
         } else {
             eprintln!("Please update emails to notify, one per line.");
@@ -240,7 +463,7 @@ impl Configuration {
                 if !prompt.is_empty() && name.ne(prompt) {
                     eprintln!("{prompt} will not be notified.")
                 }
-                if name.parse::<Address>().is_ok() {
+                if name.parse::<Mailbox>().is_ok() {
                     new_emails.push(name.clone());
                     if name.ne(prompt) {
                         eprintln!("{name} will be notified")
@@ -250,7 +473,97 @@ impl Configuration {
                 }
             }
         }
-        self.to_addresses = new_emails;
+        // keep the subscriptions of retained recipients
+        let mut recipients: Vec<Recipient> = new_emails
+            .iter()
+            .map(|email| {
+                self.recipients
+                    .iter()
+                    .find(|r| r.address.eq(email))
+                    .cloned()
+                    .unwrap_or_else(|| Recipient::new(email))
+            })
+            .collect();
+        eprintln!("Each recipient gets every notification unless you limit what they get.");
+        loop {
+            let mut choices: Vec<String> = recipients
+                .iter()
+                .map(|r| format!("Change what is sent to {r}"))
+                .collect();
+            choices.push(String::from("Done"));
+            let choice = dialoguer::Select::new()
+                .with_prompt("Recipients")
+                .items(&choices)
+                .default(choices.len() - 1)
+                .interact()
+                .wrap_err("Input error")?;
+            if choice == recipients.len() {
+                break;
+            }
+            recipients[choice].interview()?;
+        }
+        self.recipients = recipients;
+        Ok(())
+    }
+
+    fn interview_channels(&mut self) -> Result<()> {
+        eprintln!("Notifications can also be sent to channels other than email.");
+        interview_channel_list(&mut self.channels, "Other notification channels")
+    }
+
+    fn interview_escalations(&mut self) -> Result<()> {
+        eprintln!("Problems that go on too long can be escalated to other emails and channels.");
+        loop {
+            let mut choices: Vec<String> = self
+                .escalations
+                .iter()
+                .map(|rule| format!("Change the {rule}"))
+                .collect();
+            let count = choices.len();
+            choices.extend(
+                self.escalations
+                    .iter()
+                    .map(|rule| format!("Remove the {rule}")),
+            );
+            choices.push(String::from("Add an escalation"));
+            choices.push(String::from("Done"));
+            let choice = dialoguer::Select::new()
+                .with_prompt("Escalations")
+                .items(&choices)
+                .default(choices.len() - 1)
+                .interact()
+                .wrap_err("Input error")?;
+            if choice < count {
+                let mut others = self.escalations.clone();
+                let rule = others.remove(choice);
+                self.escalations[choice] = Escalation::interview(Some(&rule), &others)?;
+            } else if choice < 2 * count {
+                let rule = self.escalations.remove(choice - count);
+                eprintln!("The {rule} has been removed.");
+            } else if choice == 2 * count {
+                let rule = Escalation::interview(None, &self.escalations)?;
+                self.escalations.push(rule);
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn interview_security(&mut self) -> Result<()> {
+        let mut addresses: Vec<String> = vec![];
+        let all = self.recipients.iter().chain(
+            self.escalations
+                .iter()
+                .flat_map(|rule| rule.recipients.iter()),
+        );
+        for recipient in all {
+            let address = security::key_address(&recipient.address);
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        self.security = self.security.interview(&addresses)?;
         Ok(())
     }
 
@@ -309,11 +622,109 @@ impl Configuration {
             }
         }
         self.state = new_state;
-        Ok(())
+        let state = &self.state;
+        self.last_changed.retain(|host, _| state.contains_key(host));
+        self.update_threads();
+        self.interview_host_tags()
+    }
+
+    /// Give each monitored host an email thread, and forget the threads of other hosts.
+    pub fn update_threads(&mut self) {
+        let state = &self.state;
+        self.threads.retain(|host, _| state.contains_key(host));
+        let threads = &self.threads;
+        self.started_threads
+            .retain(|host| threads.contains_key(host));
+        for host in self.state.keys() {
+            if !self.threads.contains_key(host) {
+                let root = email::thread_root(host, &self.from_address);
+                self.threads.insert(host.clone(), root);
+            }
+        }
+    }
+
+    fn interview_host_tags(&mut self) -> Result<()> {
+        let has_tags = self
+            .state
+            .keys()
+            .any(|h| !recipient::host_tags(self, h).is_empty());
+        if !has_tags
+            && !dialoguer::Confirm::new()
+                .with_prompt("Tag hosts, so recipients can choose them by tag?")
+                .default(false)
+                .interact()
+                .wrap_err("Input error")?
+        {
+            return Ok(());
+        }
+        let mut hosts: Vec<String> = self.state.keys().cloned().collect();
+        hosts.sort();
+        loop {
+            let mut choices: Vec<String> = hosts
+                .iter()
+                .map(|host| match recipient::host_tags(self, host) {
+                    tags if tags.is_empty() => format!("Tag {host}"),
+                    tags => format!("Tag {host} (now {})", tags.join(", ")),
+                })
+                .collect();
+            choices.push(String::from("Done"));
+            let choice = dialoguer::Select::new()
+                .with_prompt("Host tags")
+                .items(&choices)
+                .default(choices.len() - 1)
+                .interact()
+                .wrap_err("Input error")?;
+            if choice == hosts.len() {
+                return Ok(());
+            }
+            let host = &hosts[choice];
+            let tags = recipient::interview_list(
+                &format!("Tags for {host} (separated by commas)"),
+                &recipient::host_tags(self, host),
+            )?;
+            let metadata = self.host_metadata.entry(host.clone()).or_default();
+            if tags.is_empty() {
+                metadata.remove(TAGS_KEY);
+            } else {
+                metadata.insert(TAGS_KEY.to_string(), tags.join(","));
+            }
+        }
+    }
+}
+
+/// Add and remove channels from a list of them.
+pub(crate) fn interview_channel_list(channels: &mut Vec<Channel>, prompt: &str) -> Result<()> {
+    loop {
+        let mut choices: Vec<String> = channels
+            .iter()
+            .map(|channel| format!("Remove the {channel}"))
+            .collect();
+        let removals = choices.len();
+        for kind in Channel::KINDS {
+            choices.push(format!("Add a {kind}"));
+        }
+        choices.push(String::from("Done"));
+        let choice = dialoguer::Select::new()
+            .with_prompt(prompt)
+            .items(&choices)
+            .default(choices.len() - 1)
+            .interact()
+            .wrap_err("Input error")?;
+        if choice < removals {
+            let channel = channels.remove(choice);
+            eprintln!("The {channel} will not be notified.");
+        } else if choice < choices.len() - 1 {
+            let channel = Channel::interview(Channel::KINDS[choice - removals])?;
+            eprintln!("The {channel} will be notified.");
+            channels.push(channel);
+        } else {
+            break;
+        }
     }
+    Ok(())
 }
 
-fn config_path() -> Result<PathBuf> {
+pub(crate) fn config_path() -> Result<PathBuf> {
     let project_dirs = directories::ProjectDirs::from("io", "ClickOneTwo", "ddns-monitor")
         .wrap_err("Can't find project directories for ddns-monitor.clickonetwo.io")?;
     // we create the local data dir to make sure there's a place for logs
@@ -328,7 +739,7 @@ fn config_path() -> Result<PathBuf> {
 
 const FALLBACK_MAC_ADDRESS: &str = env!("BUILD_MACHINE_MAC_ADDRESS");
 
-fn encrypt_password(pw: &str) -> Result<String> {
+pub(crate) fn encrypt_password(pw: &str) -> Result<String> {
     let key = match mac_address::get_mac_address() {
         Ok(Some(addr)) => addr.to_string(),
         _ => FALLBACK_MAC_ADDRESS.to_string(),
@@ -338,7 +749,7 @@ fn encrypt_password(pw: &str) -> Result<String> {
     Ok(base64)
 }
 
-fn decrypt_password(base64: &str) -> Result<String> {
+pub(crate) fn decrypt_password(base64: &str) -> Result<String> {
     let key = match mac_address::get_mac_address() {
         Ok(Some(addr)) => addr.to_string(),
         _ => FALLBACK_MAC_ADDRESS.to_string(),
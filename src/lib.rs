@@ -21,19 +21,60 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
  */
+use std::fmt::{Display, Formatter};
+
 use eyre::{eyre, Result, WrapErr};
 
 pub use configure::{Configuration, State};
-pub use monitor::{initialize_state, monitor_loop, monitor_once};
+pub use monitor::{initialize_state, monitor_loop, monitor_once, retry_outbox};
+pub use notify::escalation::{Escalation, EscalationState};
+pub use notify::outbox::{Outbox, PendingDelivery};
+pub use notify::problem::{ErrorOptions, Problems};
+pub use notify::{Channel, Event, EventKind};
+
+pub mod logging;
 
 mod configure;
 mod monitor;
+mod notify;
+
+/// A failed lookup of a monitored host, which is attached to the error
+/// so that repeats of the same problem can be recognized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LookupFailure {
+    Failed(String),
+    NoAddress(String),
+}
+
+impl LookupFailure {
+    pub fn host(&self) -> &str {
+        match self {
+            LookupFailure::Failed(host) | LookupFailure::NoAddress(host) => host,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LookupFailure::Failed(_) => "lookup",
+            LookupFailure::NoAddress(_) => "no-address",
+        }
+    }
+}
+
+impl Display for LookupFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupFailure::Failed(host) => write!(f, "DNS lookup failed on {host}"),
+            LookupFailure::NoAddress(host) => write!(f, "No DNS address entry for {host}"),
+        }
+    }
+}
 
 fn current_ip(host: &str) -> Result<String> {
-    let ips = dns_lookup::lookup_host(host).wrap_err(format!("DNS lookup failed on {host}"))?;
+    let ips = dns_lookup::lookup_host(host).wrap_err(LookupFailure::Failed(host.to_string()))?;
     let ip = ips
         .first()
-        .ok_or(eyre!("No DNS address entry for {}", host))?;
+        .ok_or_else(|| eyre!(LookupFailure::NoAddress(host.to_string())))?;
     Ok(ip.to_string())
 }
 
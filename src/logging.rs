@@ -0,0 +1,292 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::env;
+use std::io::Write;
+use std::net::UdpSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+
+use chrono::{Local, SecondsFormat};
+use eyre::{eyre, Result, WrapErr};
+
+/// The syslog severity of a log message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Info = 6,
+}
+
+/// A destination for log messages.
+pub enum Sink {
+    /// Plain lines on stdout, with RFC 2822 timestamps.
+    Stdout,
+    /// The native systemd journal protocol, with structured fields.
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+    /// RFC 5424 syslog messages to a local socket, with structured data.
+    #[cfg(unix)]
+    SyslogUnix(UnixDatagram),
+    /// RFC 5424 syslog messages to a remote server, with structured data.
+    SyslogUdp(UdpSocket),
+}
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// The syslog facility for system daemons.
+const FACILITY_DAEMON: u8 = 3;
+/// The enterprise number reserved for documentation (RFC 5612), used to name our structured data.
+const SD_ID: &str = "ddns@32473";
+
+static SINKS: OnceLock<Vec<Sink>> = OnceLock::new();
+
+/// Choose where log messages go, based on the environment.
+///
+/// `DDNS_LOG` is a comma-separated list of `stdout`, `journald` and `syslog`
+/// (default `stdout`).  `DDNS_SYSLOG_ADDRESS` is the path of a local syslog
+/// socket, or a `host:port` for UDP syslog (default `/dev/log`).
+pub fn init_from_environment() -> Result<()> {
+    let names = env::var("DDNS_LOG").unwrap_or_else(|_| String::from("stdout"));
+    let address = env::var("DDNS_SYSLOG_ADDRESS").unwrap_or_else(|_| SYSLOG_SOCKET.to_string());
+    let mut sinks = vec![];
+    for name in names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        sinks.push(Sink::open(name, &address)?);
+    }
+    SINKS
+        .set(sinks)
+        .map_err(|_| eyre!("Logging has already been initialized"))
+}
+
+impl Sink {
+    pub fn open(name: &str, syslog_address: &str) -> Result<Self> {
+        match name {
+            "stdout" => Ok(Sink::Stdout),
+            #[cfg(unix)]
+            "journald" => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(JOURNALD_SOCKET)
+                    .wrap_err("Can't connect to the systemd journal")?;
+                Ok(Sink::Journald(socket))
+            }
+            #[cfg(unix)]
+            "syslog" if syslog_address.starts_with('/') => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(syslog_address)
+                    .wrap_err(format!("Can't connect to syslog at {syslog_address}"))?;
+                Ok(Sink::SyslogUnix(socket))
+            }
+            "syslog" => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket
+                    .connect(syslog_address)
+                    .wrap_err(format!("Can't connect to syslog at {syslog_address}"))?;
+                Ok(Sink::SyslogUdp(socket))
+            }
+            _ => Err(eyre!("Unknown log destination: {name}")),
+        }
+    }
+
+    fn write(&self, priority: Priority, message: &str, fields: &[(&str, &str)]) -> Result<()> {
+        match self {
+            Sink::Stdout => {
+                let timestamp = Local::now().to_rfc2822();
+                println!("{timestamp}: {message}");
+                Ok(())
+            }
+            #[cfg(unix)]
+            Sink::Journald(socket) => {
+                socket.send(&journald_entry(priority, message, fields))?;
+                Ok(())
+            }
+            #[cfg(unix)]
+            Sink::SyslogUnix(socket) => {
+                socket.send(syslog_message(priority, message, fields).as_bytes())?;
+                Ok(())
+            }
+            Sink::SyslogUdp(socket) => {
+                socket.send(syslog_message(priority, message, fields).as_bytes())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Log a message with structured fields, named as journal fields (such as `DDNS_HOST`).
+pub fn event(priority: Priority, message: &str, fields: &[(&str, &str)]) {
+    let sinks = SINKS.get_or_init(|| vec![Sink::Stdout]);
+    for sink in sinks.iter() {
+        if let Err(err) = sink.write(priority, message, fields) {
+            let timestamp = Local::now().to_rfc2822();
+            eprintln!("{timestamp}: Couldn't log ({err}): {message}");
+        }
+    }
+}
+
+pub fn error(message: &str) {
+    event(Priority::Error, message, &[])
+}
+
+pub fn warning(message: &str) {
+    event(Priority::Warning, message, &[])
+}
+
+pub fn notice(message: &str) {
+    event(Priority::Notice, message, &[])
+}
+
+pub fn info(message: &str) {
+    event(Priority::Info, message, &[])
+}
+
+/// An entry in the journal's native protocol.
+fn journald_entry(priority: Priority, message: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let priority = (priority as u8).to_string();
+    let mut entry = vec![];
+    let standard = [
+        ("MESSAGE", message),
+        ("PRIORITY", priority.as_str()),
+        ("SYSLOG_IDENTIFIER", "ddns-monitor"),
+    ];
+    for (name, value) in standard.iter().chain(fields.iter()) {
+        entry.write_all(name.as_bytes()).unwrap();
+        if value.contains('\n') {
+            // multi-line values are sent as a little-endian length and the raw bytes
+            entry.push(b'\n');
+            entry
+                .write_all(&(value.len() as u64).to_le_bytes())
+                .unwrap();
+        } else {
+            entry.push(b'=');
+        }
+        entry.write_all(value.as_bytes()).unwrap();
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// An RFC 5424 syslog message.
+///
+/// Fields become parameters of our structured data element,
+/// named without their `DDNS_` prefix and in lower case.
+fn syslog_message(priority: Priority, message: &str, fields: &[(&str, &str)]) -> String {
+    let pri = FACILITY_DAEMON * 8 + priority as u8;
+    let timestamp = Local::now().to_rfc3339_opts(SecondsFormat::Micros, false);
+    let hostname = crate::notify::monitor_host();
+    let hostname = if hostname.is_empty() { "-" } else { &hostname };
+    let pid = std::process::id();
+    let msgid = fields
+        .iter()
+        .find(|(name, _)| *name == "DDNS_EVENT")
+        .map(|(_, value)| *value)
+        .unwrap_or("-");
+    let structured_data = if fields.is_empty() {
+        String::from("-")
+    } else {
+        let mut element = format!("[{SD_ID}");
+        for (name, value) in fields.iter() {
+            let name = name.trim_start_matches("DDNS_").to_lowercase();
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace(']', "\\]");
+            element.push_str(&format!(" {name}=\"{value}\""));
+        }
+        element.push(']');
+        element
+    };
+    format!(
+        "<{pri}>1 {timestamp} {hostname} ddns-monitor {pid} {msgid} {structured_data} {message}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: &[(&str, &str)] = &[
+        ("DDNS_EVENT", "change"),
+        ("DDNS_HOST", "some.host"),
+        ("DDNS_OLD", "1.2.3.4"),
+        ("DDNS_NEW", "5.6.7.8"),
+    ];
+
+    #[test]
+    fn test_journald_entry() {
+        let entry = journald_entry(Priority::Notice, "Address changed", FIELDS);
+        let entry = String::from_utf8(entry).unwrap();
+        assert_eq!(
+            entry,
+            "MESSAGE=Address changed\nPRIORITY=5\nSYSLOG_IDENTIFIER=ddns-monitor\n\
+             DDNS_EVENT=change\nDDNS_HOST=some.host\nDDNS_OLD=1.2.3.4\nDDNS_NEW=5.6.7.8\n"
+        );
+        let entry = journald_entry(Priority::Error, "two\nlines", &[]);
+        assert!(entry.starts_with(b"MESSAGE\n\x09\0\0\0\0\0\0\0two\nlines\nPRIORITY=3\n"));
+    }
+
+    #[test]
+    fn test_syslog_message() {
+        let message = syslog_message(Priority::Notice, "Address changed", FIELDS);
+        assert!(message.starts_with("<29>1 "));
+        let pid = std::process::id();
+        assert!(message.ends_with(&format!(
+            "ddns-monitor {pid} change [ddns@32473 event=\"change\" host=\"some.host\" \
+             old=\"1.2.3.4\" new=\"5.6.7.8\"] Address changed"
+        )));
+        let message = syslog_message(
+            Priority::Error,
+            "Failed \"badly\"",
+            &[("DDNS_ERROR", "a]b")],
+        );
+        assert!(message.starts_with("<27>1 "));
+        assert!(message.ends_with("- [ddns@32473 error=\"a\\]b\"] Failed \"badly\""));
+        let message = syslog_message(Priority::Info, "Hello", &[]);
+        assert!(message.ends_with(&format!("ddns-monitor {pid} - - Hello")));
+    }
+
+    #[test]
+    fn test_udp_syslog() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let sink = Sink::open("syslog", &address).expect("can't open UDP syslog");
+        sink.write(Priority::Warning, "Testing", FIELDS).unwrap();
+        let mut buffer = [0u8; 1024];
+        let length = server.recv(&mut buffer).unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]);
+        assert!(message.starts_with("<28>1 "));
+        assert!(message.ends_with("Testing"));
+    }
+
+    #[test]
+    fn test_unknown_sink() {
+        assert!(Sink::open("carrier-pigeon", SYSLOG_SOCKET).is_err());
+    }
+}
@@ -27,17 +27,40 @@ use chrono::Local;
 use clap::Parser;
 use eyre::{Result, WrapErr};
 
-use ddns_monitor::{initialize_state, monitor_loop, Configuration};
+use ddns_monitor::{
+    initialize_state, logging, monitor_loop, retry_outbox, Configuration, EscalationState, Outbox,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 enum Command {
     Configure,
     Monitor,
+    /// List notifications that are waiting to be delivered
+    Outbox {
+        /// Retry every delivery that isn't stale now
+        #[clap(long)]
+        retry: bool,
+        /// Remove the stale deliveries
+        #[clap(long)]
+        purge_stale: bool,
+    },
+    /// Acknowledge address changes, so they aren't escalated
+    Acknowledge {
+        /// Only acknowledge changes to this host
+        host: Option<String>,
+    },
 }
 
 fn main() -> Result<()> {
     let command: Command = Command::parse();
+    if let Command::Monitor = command {
+        if let Err(err) = logging::init_from_environment() {
+            let timestamp = Local::now().to_rfc2822();
+            eprintln!("{timestamp}: Fatal error: can't initialize logging: {err}");
+            std::process::exit(1);
+        }
+    }
     let result = Configuration::new_from_config_file();
     match command {
         Command::Monitor => match result {
@@ -49,10 +72,7 @@ fn main() -> Result<()> {
             }
         },
         Command::Configure => {
-            let mut config = match result {
-                Ok(config) => config,
-                Err(_) => Configuration::default(),
-            };
+            let mut config = result.unwrap_or_default();
             config
                 .update_from_interview()
                 .wrap_err("Failed to update configuration")?;
@@ -61,7 +81,40 @@ fn main() -> Result<()> {
                 .wrap_err("Failed to save configuration")?;
             Ok(())
         }
+        Command::Outbox { retry, purge_stale } => {
+            let mut config = result.wrap_err("Failed to load configuration")?;
+            show_outbox(&mut config, retry, purge_stale)
+        }
+        Command::Acknowledge { host } => acknowledge(host.as_deref()),
+    }
+}
+
+fn acknowledge(host: Option<&str>) -> Result<()> {
+    let mut state = EscalationState::load(EscalationState::path()?)?;
+    let count = state.acknowledge(host);
+    state.save()?;
+    println!("Acknowledged {count} address changes.");
+    Ok(())
+}
+
+fn show_outbox(config: &mut Configuration, retry: bool, purge_stale: bool) -> Result<()> {
+    if retry {
+        retry_outbox(config, true)?;
+        config.save_refresh_token()?;
+    }
+    let mut outbox = Outbox::load(Outbox::path()?)?;
+    if purge_stale {
+        let count = outbox.purge_stale();
+        outbox.save()?;
+        println!("Removed {count} stale deliveries.");
+    }
+    if outbox.deliveries().is_empty() {
+        println!("There are no undelivered notifications.");
+    }
+    for delivery in outbox.deliveries() {
+        println!("{delivery}");
     }
+    Ok(())
 }
 
 fn monitor(config: &mut Configuration) -> Result<()> {
@@ -71,10 +124,9 @@ fn monitor(config: &mut Configuration) -> Result<()> {
         .parse()
         .unwrap_or(300);
     if start_wait > 0 {
-        println!(
-            "{}: DDNS Monitor starting (delay {start_wait} seconds)...",
-            Local::now().to_rfc2822()
-        );
+        logging::info(&format!(
+            "DDNS Monitor starting (delay {start_wait} seconds)..."
+        ));
         std::thread::sleep(std::time::Duration::from_secs(start_wait));
     }
     initialize_state(config).wrap_err("Initialization error")?;
@@ -21,138 +21,710 @@ LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
  */
-use chrono::{Local, LocalResult, TimeZone};
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
 use eyre::{Report, Result, WrapErr};
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
 
+use crate::logging::{self, Priority};
+use crate::notify::diagnostics::{Diagnostics, Lookup};
+use crate::notify::digest;
+use crate::notify::email::send_notification;
+use crate::notify::escalation::EscalationState;
+use crate::notify::locale::fill;
+use crate::notify::outbox::Outbox;
+use crate::notify::problem::Problems;
+use crate::notify::recipient::Recipient;
+use crate::notify::report::{History, ReportPeriod, StabilityReport};
+use crate::notify::telegram::{status_html, MAX_POLL_SECS};
+use crate::notify::template::Templates;
+use crate::notify::{escape_html, BotCommand, Channel, Event, EventKind, Telegram};
 use crate::Configuration;
 
-use super::{current_ip, State};
+use super::State;
 
 pub fn send_initial_notification(config: &Configuration) -> Result<()> {
-    let subject = "Dynamic DNS monitoring status".to_string();
-    let mut body = vec![];
-    let hostname = gethostname::gethostname().to_string_lossy().to_string();
-    let first = config.last_update <= 0;
-    let action = if first { "starting" } else { "restarting" };
-    body.push(format!(
-        "Dynamic DNS monitoring from {hostname} is {action} for the following hosts:"
-    ));
-    let address_type = if first { "Initial" } else { "Last known" };
-    for (host, addr) in config.state.iter() {
-        body.push(format!("-- Host: {host}, {address_type} address: {addr}"))
-    }
-    if !first {
-        if let LocalResult::Single(last_check) = Local.timestamp_millis_opt(config.last_update) {
-            body.push(format!("Last check was at {}", last_check.to_rfc2822()))
-        }
-    }
-    body.push(String::from(
-        "You will be notified if any of these addresses change.",
-    ));
-    send_notification(config, subject, body)
+    notify(config, &Event::initial(config))
 }
 
 pub fn send_change_notification(
-    config: &Configuration,
+    config: &mut Configuration,
     name: &str,
     old_address: &str,
     new_address: &str,
 ) -> Result<()> {
-    let subject = format!("DNS change for {name}");
-    let body = vec![
-        format!("The IP address of {name} has changed."),
-        format!("-- The old IP address was: {old_address}."),
-        format!("-- The new IP address is: {new_address}."),
-        String::from("You must reconfigure any services that had the old IP address."),
-    ];
-    send_notification(config, subject, body)
-}
-
-pub fn send_error_notification(config: &Configuration, err: Report) -> Result<()> {
-    let subject = "DNS monitoring temporary failure".to_string();
-    let body = vec![
-        format!("DNS monitoring reported an error: {err}"),
-        format!("A retry will be performed on the normal schedule."),
-    ];
-    send_notification(config, subject, body)
-}
-
-pub fn send_notification(config: &Configuration, subject: String, body: Vec<String>) -> Result<()> {
-    let mut builder = Message::builder();
-    let from = config.from_address.as_str();
-    builder = builder.from(
-        from.parse()
-            .wrap_err(format!("Illegal from address: {from}"))?,
-    );
-    for to in config.to_addresses.iter() {
-        builder = builder.to(to.parse().wrap_err(format!("Illegal to address: {to}"))?)
-    }
-    builder = builder.subject(subject);
-    let email = builder
-        .body(body.join("\n"))
-        .wrap_err("E-mail message creation failed.")?;
-    let password = config.password()?;
-    let creds = Credentials::new(from.to_string(), password.to_string());
-    let server = config.from_server.as_str();
-    let mailer = SmtpTransport::relay(server)
-        .wrap_err(format!("Couldn't lookup {server}"))?
-        .credentials(creds)
-        .build();
-    let _response = mailer.send(&email).wrap_err("E-mail send failed")?;
+    let event = Event::change(name, old_address, new_address);
+    if let Err(err) = await_acknowledgement(config, &event) {
+        logging::error(&format!("Couldn't save the change for escalation: {err:#}"))
+    }
+    report(config, event)
+}
+
+/// Notify of an error, unless it's a repeat of one that has already been notified.
+pub fn send_error_notification(config: &mut Configuration, err: Report) -> Result<()> {
+    if !config.is_file_based {
+        return report(config, Event::error(&err));
+    }
+    let mut problems = Problems::load(Problems::path()?)?;
+    let event = problems.failed(&config.errors, &err, Local::now());
+    problems.save()?;
+    match event {
+        Some(event) => report(config, event),
+        None => {
+            logging::info("Not notifying of an error that was already notified");
+            Ok(())
+        }
+    }
+}
+
+/// After a successful check, notify of the end of any errors.
+pub fn send_recovery_notifications(config: &mut Configuration) -> Result<()> {
+    if !config.is_file_based {
+        return Ok(());
+    }
+    let mut problems = Problems::load(Problems::path()?)?;
+    if problems.problems().is_empty() {
+        return Ok(());
+    }
+    let events = problems.succeeded(&config.errors);
+    problems.save()?;
+    for event in events {
+        logging::notice(&event.subject);
+        report(config, event)?;
+    }
+    Ok(())
+}
+
+/// Send the event now or, in digest mode, collect it for the next digest.
+pub fn report(config: &mut Configuration, event: Event) -> Result<()> {
+    if config.digest.enabled {
+        config.digest_queue.push(event);
+        // the event is collected even if it can't be saved, so it mustn't be reported again
+        if let Err(err) = save_digest_queue(config) {
+            logging::error(&format!("{err:#}"))
+        }
+        Ok(())
+    } else {
+        notify(config, &event)
+    }
+}
+
+/// Send the collected events, if they are due, as a single digest.
+///
+/// If sending fails, the events are kept for the next digest.
+pub fn flush_digest(config: &mut Configuration) -> Result<()> {
+    if !config.digest.is_due(&config.digest_queue) {
+        return Ok(());
+    }
+    let mut events = std::mem::take(&mut config.digest_queue);
+    let event = if events.len() == 1 {
+        events[0].clone()
+    } else {
+        Event::digest(events.clone())
+    };
+    if let Err(err) = notify(config, &event) {
+        events.append(&mut config.digest_queue);
+        config.digest_queue = events;
+        return Err(err);
+    }
+    save_digest_queue(config)
+}
+
+/// Save the collected events, so they are sent even if the monitor restarts first.
+fn save_digest_queue(config: &Configuration) -> Result<()> {
+    if !config.is_file_based {
+        return Ok(());
+    }
+    digest::save_queue(&digest::queue_path()?, &config.digest_queue)
+        .wrap_err("Failed to save the events for the next digest")
+}
+
+/// Email the daily summary, if it's due.
+pub fn send_daily_summary(config: &mut Configuration) -> Result<()> {
+    let now = Local::now();
+    if !config.heartbeat.is_summary_due(config.last_summary, now) {
+        return Ok(());
+    }
+    let addresses = config
+        .state
+        .iter()
+        .map(|(host, addr)| (host.clone(), addr.clone()))
+        .collect();
+    let event = Event::heartbeat(addresses, &config.last_changed, config.last_update);
+    notify_targets(config, &config.recipients, &[], &event)?;
+    config.last_summary = now.timestamp_millis();
+    if config.is_file_based {
+        config.save_to_config_file()?;
+    }
+    Ok(())
+}
+
+/// Email the stability report for the last period, if it's due.
+pub fn send_stability_report(config: &mut Configuration) -> Result<()> {
+    if !config.is_file_based || config.reports.period == ReportPeriod::Never {
+        return Ok(());
+    }
+    let now = Local::now();
+    if config.last_report <= 0 {
+        // the first report is sent at the end of the current period
+        config.last_report = now.timestamp_millis();
+        config.save_to_config_file()?;
+        return Ok(());
+    }
+    let Some((start, end)) = config.reports.due_period(config.last_report, now) else {
+        return Ok(());
+    };
+    let history = History::load(History::path()?)?;
+    let report = StabilityReport::new(&history, &config.state, config.reports.period, start, end);
+    let event = Event::report(&report);
+    notify_targets(config, &config.recipients, &[], &event)?;
+    config.last_report = now.timestamp_millis();
+    config.save_to_config_file()?;
+    Ok(())
+}
+
+/// Remember a check's changes or failure for stability reports.
+fn record_history(config: &Configuration, update: impl FnOnce(&mut History)) {
+    if !config.is_file_based {
+        return;
+    }
+    let result = History::path()
+        .and_then(History::load)
+        .and_then(|mut history| {
+            update(&mut history);
+            history.save()
+        });
+    if let Err(err) = result {
+        logging::error(&format!("Couldn't save history: {err:#}"))
+    }
+}
+
+/// Send the event by email and to every configured channel.
+pub fn notify(config: &Configuration, event: &Event) -> Result<()> {
+    notify_targets(config, &config.recipients, &config.channels, event)
+}
+
+/// Send the event to the given email recipients and channels.
+///
+/// Every destination is tried even if an earlier one fails.
+/// For file-based configurations, failed deliveries are put in
+/// the outbox to be retried, and only a failure to do that is returned.
+/// Otherwise, the first failure is returned and any others are logged.
+fn notify_targets(
+    config: &Configuration,
+    recipients: &[Recipient],
+    channels: &[Channel],
+    event: &Event,
+) -> Result<()> {
+    let mut event = event.clone();
+    if config.diagnostics && event.diagnostics.is_none() {
+        event.diagnostics = Some(Box::new(Diagnostics::new(config, &event)));
+    }
+    let event = &event;
+    let templates = Templates::new(config);
+    let mut failures = vec![];
+    for destination in Destination::all(config, recipients, channels, event) {
+        if let Err(err) = deliver(config, &templates, event, &destination) {
+            failures.push((destination.name(), destination.id(), err));
+        }
+    }
+    if failures.is_empty() {
+        return Ok(());
+    }
+    if config.is_file_based {
+        let mut outbox = Outbox::load(Outbox::path()?)?;
+        for (destination, id, err) in failures {
+            logging::warning(&format!("{err:#} (will retry)"));
+            outbox.add(destination, id, event.clone(), &err);
+        }
+        return outbox
+            .save()
+            .wrap_err("Failed to save undelivered notifications");
+    }
+    let mut failures = failures.into_iter().map(|(_, _, err)| err);
+    match failures.next() {
+        None => Ok(()),
+        Some(first) => {
+            for err in failures {
+                logging::error(&format!("{err:#}"));
+            }
+            Err(first)
+        }
+    }
+}
+
+/// A place that notifications are sent.
+enum Destination<'a> {
+    /// Recipients who all want the same part of the event.
+    Email {
+        recipients: Vec<Recipient>,
+        event: Box<Event>,
+    },
+    Channel(Cow<'a, Channel>),
+}
+
+impl<'a> Destination<'a> {
+    fn all(
+        config: &Configuration,
+        recipients: &[Recipient],
+        channels: &'a [Channel],
+        event: &Event,
+    ) -> Vec<Self> {
+        let mut all: Vec<Self> = vec![];
+        for recipient in recipients.iter() {
+            let Some(wanted) = recipient.filter(config, event) else {
+                continue;
+            };
+            let wanted = wanted.localized(recipient.locale.unwrap_or(config.locale));
+            let group = all.iter_mut().find_map(|destination| match destination {
+                Destination::Email { recipients, event } if **event == wanted => Some(recipients),
+                _ => None,
+            });
+            match group {
+                Some(recipients) => recipients.push(recipient.clone()),
+                None => all.push(Destination::Email {
+                    recipients: vec![recipient.clone()],
+                    event: Box::new(wanted),
+                }),
+            }
+        }
+        all.extend(channels.iter().flat_map(Self::channel));
+        all
+    }
+
+    /// The destinations of a channel: one for each chat of a Telegram bot,
+    /// so that only the chats a notification didn't reach are retried.
+    fn channel(channel: &'a Channel) -> Vec<Self> {
+        match channel {
+            Channel::Telegram(telegram) if telegram.chat_ids.len() > 1 => telegram
+                .chat_ids
+                .iter()
+                .map(|chat_id| {
+                    let telegram = telegram.for_chat(*chat_id);
+                    Destination::Channel(Cow::Owned(Channel::Telegram(telegram)))
+                })
+                .collect(),
+            _ => vec![Destination::Channel(Cow::Borrowed(channel))],
+        }
+    }
+
+    /// The destinations with the given ID that are still configured, either for every
+    /// notification or for an escalation. The recipients of an email who are still
+    /// configured and still want the event may now need more than one email.
+    fn find(config: &'a Configuration, event: &Event, id: &str) -> Vec<Self> {
+        let mut targets: Vec<(&[Recipient], &[Channel])> =
+            vec![(&config.recipients, &config.channels)];
+        targets.extend(
+            config
+                .escalations
+                .iter()
+                .map(|rule| (rule.recipients.as_slice(), rule.channels.as_slice())),
+        );
+        if let Some(addresses) = id.strip_prefix("email:") {
+            let addresses: Vec<&str> = addresses.split(',').collect();
+            let mut recipients: Vec<Recipient> = vec![];
+            for recipient in targets.iter().flat_map(|(recipients, _)| recipients.iter()) {
+                if addresses.contains(&recipient.address.as_str())
+                    && !recipients.iter().any(|r| r.address == recipient.address)
+                {
+                    recipients.push(recipient.clone());
+                }
+            }
+            return Self::all(config, &recipients, &[], event);
+        }
+        targets
+            .into_iter()
+            .flat_map(|(_, channels)| channels.iter().flat_map(Self::channel))
+            .find(|destination| destination.id() == id)
+            .into_iter()
+            .collect()
+    }
+
+    /// What the outbox finds the destination by: the addresses of an email's recipients,
+    /// or the ID of a channel.
+    fn id(&self) -> String {
+        match self {
+            Destination::Email { recipients, .. } => {
+                let mut addresses: Vec<&str> =
+                    recipients.iter().map(|r| r.address.as_str()).collect();
+                addresses.sort_unstable();
+                format!("email:{}", addresses.join(","))
+            }
+            Destination::Channel(channel) => channel.id(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Destination::Email { recipients, .. } => {
+                let addresses: Vec<&str> = recipients.iter().map(|r| r.address.as_str()).collect();
+                format!("email to {}", addresses.join(", "))
+            }
+            Destination::Channel(channel) => channel.to_string(),
+        }
+    }
+}
+
+/// Send the event to one destination, with the text from any templates that apply.
+fn deliver(
+    config: &Configuration,
+    templates: &Templates,
+    event: &Event,
+    destination: &Destination,
+) -> Result<()> {
+    match destination {
+        Destination::Email { recipients, event } => {
+            let email = templates.render(config, event, "email");
+            send_notification(config, &email, recipients).wrap_err("E-mail notification failed")
+        }
+        Destination::Channel(channel) => {
+            let event = event.localized(config.locale);
+            let mut event = templates.render(config, &event, channel.type_name());
+            if let Some(diagnostics) = &event.diagnostics {
+                event.body.push(String::new());
+                event.body.extend(diagnostics.lines());
+            }
+            channel
+                .send(&event)
+                .wrap_err(format!("Notification of the {channel} failed"))
+        }
+    }
+}
+
+/// Retry the deliveries in the outbox that are due (or all of them, if forced),
+/// and return when the next retry will be due.
+pub fn retry_outbox(config: &Configuration, force: bool) -> Result<Option<DateTime<Local>>> {
+    if !config.is_file_based {
+        return Ok(None);
+    }
+    let mut outbox = Outbox::load(Outbox::path()?)?;
+    if outbox.deliveries().is_empty() {
+        return Ok(None);
+    }
+    if retry_deliveries(config, &mut outbox, force) {
+        outbox.save()?;
+    }
+    Ok(outbox.next_attempt())
+}
+
+/// Retry the deliveries that are due, and return whether the outbox changed.
+fn retry_deliveries(config: &Configuration, outbox: &mut Outbox, force: bool) -> bool {
+    let now = Local::now();
+    let stale = outbox.mark_stale(config.outbox.stale_after(), now);
+    for delivery in stale.iter() {
+        logging::warning(&format!(
+            "Giving up on delivery to {} of \"{}\" after {} attempts",
+            delivery.destination, delivery.event.subject, delivery.attempts
+        ));
+    }
+    let mut changed = !stale.is_empty();
+    let templates = Templates::new(config);
+    // the other destinations that a delivery turned out to need, which also failed
+    let mut split = vec![];
+    outbox.deliveries_mut().retain_mut(|delivery| {
+        if delivery.stale || !(force || delivery.is_due(now)) {
+            return true;
+        }
+        changed = true;
+        let destinations = Destination::find(config, &delivery.event, &delivery.destination_id);
+        if destinations.is_empty() {
+            logging::warning(&format!(
+                "Can't deliver \"{}\" because {} is no longer configured",
+                delivery.event.subject, delivery.destination
+            ));
+            delivery.stale = true;
+            delivery.last_error = String::from("The destination is no longer configured");
+            return true;
+        }
+        let mut failures = vec![];
+        for destination in destinations {
+            match deliver(config, &templates, &delivery.event, &destination) {
+                Ok(()) => logging::notice(&format!(
+                    "Delivered \"{}\" to {} after {} failed attempts",
+                    delivery.event.subject,
+                    destination.name(),
+                    delivery.attempts
+                )),
+                Err(err) => {
+                    logging::warning(&format!("{err:#} (will retry)"));
+                    failures.push((destination.name(), destination.id(), err));
+                }
+            }
+        }
+        let mut failures = failures.into_iter();
+        let Some((name, id, err)) = failures.next() else {
+            return false;
+        };
+        for (name, id, err) in failures {
+            let mut other = delivery.clone();
+            (other.destination, other.destination_id) = (name, id);
+            other.failed(&err, now);
+            split.push(other);
+        }
+        (delivery.destination, delivery.destination_id) = (name, id);
+        delivery.failed(&err, now);
+        true
+    });
+    outbox.deliveries_mut().append(&mut split);
+    changed
+}
+
+/// Remember an address change, so it can be escalated if nobody acknowledges it.
+fn await_acknowledgement(config: &Configuration, event: &Event) -> Result<()> {
+    if config.escalations.is_empty() || !config.is_file_based {
+        return Ok(());
+    }
+    let mut state = EscalationState::load(EscalationState::path()?)?;
+    state.changed(&config.escalations, event);
+    state.save()
+}
+
+/// Record the outcome of a check, and escalate any problems that have gone on too long.
+fn escalate(config: &Configuration, failure: Option<&Report>) -> Result<()> {
+    if config.escalations.is_empty() || !config.is_file_based {
+        return Ok(());
+    }
+    let mut state = EscalationState::load(EscalationState::path()?)?;
+    let now = Local::now();
+    let mut due = match failure {
+        Some(err) => state.check_failed(&config.escalations, &Event::error(err), now),
+        None => {
+            state.check_succeeded();
+            vec![]
+        }
+    };
+    due.extend(state.unacknowledged_due(&config.escalations, now));
+    state.save()?;
+    // release the state before sending, which may take a while
+    drop(state);
+    for (rule, event) in due {
+        logging::warning(&format!("Escalating \"{}\" with the {rule}", event.subject));
+        notify_targets(config, &rule.recipients, &rule.channels, &event)?;
+    }
     Ok(())
 }
 
 pub fn initialize_state(config: &Configuration) -> Result<()> {
-    let timestamp = Local::now().to_rfc2822();
-    println!("{timestamp}: Initializing state monitoring...");
+    logging::info("Initializing state monitoring...");
     for (host, ip) in config.state.iter() {
-        let timestamp = Local::now().to_rfc2822();
-        println!("{timestamp}: The remembered address for {host} is {ip}",);
+        logging::event(
+            Priority::Info,
+            &format!("The remembered address for {host} is {ip}"),
+            &[("DDNS_HOST", host), ("DDNS_NEW", ip)],
+        );
     }
     send_initial_notification(config)
 }
 
 pub fn monitor_once(config: &mut Configuration) -> Result<u32> {
-    let mut change_count = 0;
+    // older configurations don't have threads for their hosts
+    config.update_threads();
+    let mut changes = vec![];
     let mut new_state = State::new();
     for (name, old_address) in config.state.iter() {
-        let new_address = current_ip(name)?;
+        let (new_address, lookup) = Lookup::run(name, config.diagnostics);
+        config.lookups.insert(name.clone(), lookup);
+        let new_address = new_address?;
         new_state.insert(name.to_string(), new_address.to_string());
         if !new_address.eq_ignore_ascii_case(old_address) {
-            change_count += 1;
-            let time = Local::now().to_rfc2822();
-            println!("{time}: New address for {name} is {new_address} (was {old_address})");
-            send_change_notification(config, name, old_address, &new_address)
-                .wrap_err("Failed to send email")?;
+            logging::event(
+                Priority::Notice,
+                &format!("New address for {name} is {new_address} (was {old_address})"),
+                &[
+                    ("DDNS_EVENT", EventKind::Change.as_str()),
+                    ("DDNS_HOST", name),
+                    ("DDNS_OLD", old_address),
+                    ("DDNS_NEW", &new_address),
+                ],
+            );
+            changes.push((name.clone(), old_address.clone(), new_address));
         }
     }
+    let change_count = changes.len() as u32;
+    for (name, old_address, new_address) in changes.iter() {
+        send_change_notification(config, name, old_address, new_address)
+            .wrap_err("Failed to send notification")?;
+    }
     if change_count == 0 {
-        let time = Local::now().to_rfc2822();
-        println!("{time}: No address changes");
+        logging::info("No address changes");
     }
-    config.last_update = Local::now().timestamp_millis();
+    let now = Local::now();
+    config.last_update = now.timestamp_millis();
     config.state = new_state;
+    for (name, _, _) in changes.iter() {
+        config.last_changed.insert(name.clone(), config.last_update);
+    }
     if config.is_file_based {
         config.save_to_config_file()?;
     } else {
-        let time = Local::now().to_rfc2822();
-        println!("{time}: Config is not file-based, so can't save {change_count} changes")
+        logging::warning(&format!(
+            "Config is not file-based, so can't save {change_count} changes"
+        ))
+    }
+    // changes are only recorded once they're saved, so they're never found and recorded twice
+    if !changes.is_empty() {
+        record_history(config, |history| {
+            for (name, old_address, new_address) in changes.iter() {
+                history.changed(name, old_address, new_address, now);
+            }
+        });
     }
     Ok(change_count)
 }
 
 pub fn monitor_loop(config: &mut Configuration, interval_secs: u64) -> ! {
     loop {
-        if let Err(err) = monitor_once(config) {
-            let timestamp = Local::now().to_rfc2822();
-            println!("{timestamp}: Monitor failure: {err}");
+        let result = monitor_once(config);
+        let ping = match &result {
+            Ok(count) => config
+                .heartbeat
+                .ping_success(&format!("{count} address changes")),
+            Err(err) => config.heartbeat.ping_failure(err),
+        };
+        if let Err(err) = ping {
+            logging::warning(&format!("{err:#}"))
+        }
+        if let Err(err) = escalate(config, result.as_ref().err()) {
+            logging::error(&format!("Couldn't escalate: {err:#}"))
+        }
+        if let Err(err) = result {
+            record_history(config, |history| history.failed(&err, Local::now()));
+            logging::event(
+                Priority::Error,
+                &format!("Monitor failure: {err}"),
+                &[
+                    ("DDNS_EVENT", EventKind::Error.as_str()),
+                    ("DDNS_ERROR", &err.to_string()),
+                ],
+            );
             if let Err(err) = send_error_notification(config, err) {
-                println!("{timestamp}: Couldn't send error notification: {err}")
+                logging::error(&format!("Couldn't send error notification: {err}"))
             }
+        } else if let Err(err) = send_recovery_notifications(config) {
+            logging::error(&format!("Couldn't send recovery notification: {err:#}"))
+        }
+        if let Err(err) = flush_digest(config) {
+            logging::error(&format!("Couldn't send digest: {err:#}"))
+        }
+        if let Err(err) = send_daily_summary(config) {
+            logging::error(&format!("Couldn't send daily summary: {err:#}"))
+        }
+        if let Err(err) = send_stability_report(config) {
+            logging::error(&format!("Couldn't send stability report: {err:#}"))
         }
-        std::thread::sleep(std::time::Duration::from_secs(interval_secs));
+        wait_for_next_cycle(config, Duration::from_secs(interval_secs));
+    }
+}
+
+/// Wait until the next monitoring cycle is due, answering any bot
+/// commands that arrive and retrying undelivered notifications in the meantime.
+fn wait_for_next_cycle(config: &mut Configuration, interval: Duration) {
+    let deadline = Instant::now() + interval;
+    // the outbox is only loaded when a retry is due, or after notifications
+    // have been sent (which happens just before the first pass)
+    let mut sent = true;
+    let mut next_retry = None;
+    loop {
+        if sent || next_retry.is_some_and(|at| at <= Local::now()) {
+            sent = false;
+            next_retry = match retry_outbox(config, false) {
+                Ok(next_retry) => next_retry,
+                Err(err) => {
+                    logging::error(&format!(
+                        "Couldn't retry undelivered notifications: {err:#}"
+                    ));
+                    None
+                }
+            };
+            // the notifications and retries may have rotated the refresh token
+            if let Err(err) = config.save_refresh_token() {
+                logging::error(&format!("Couldn't save the OAuth2 refresh token: {err:#}"));
+            }
+        }
+        let mut remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        if let Some(next_retry) = next_retry {
+            let until_retry = (next_retry - Local::now()).to_std().unwrap_or_default();
+            // wake up for the retry, but don't busy-wait for it
+            remaining = remaining.min(until_retry.max(Duration::from_secs(1)));
+        }
+        let bots: Vec<usize> = config
+            .channels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, channel)| match channel {
+                Channel::Telegram(telegram) if telegram.accepts_commands() => Some(i),
+                _ => None,
+            })
+            .collect();
+        if bots.is_empty() {
+            std::thread::sleep(remaining);
+            continue;
+        }
+        // share the wait among the bots, rounding up so we never busy-wait
+        let timeout = (remaining.as_millis() as u64).div_ceil(1000 * bots.len() as u64);
+        for i in bots {
+            let Channel::Telegram(telegram) = &mut config.channels[i] else {
+                continue;
+            };
+            let offset = telegram.update_offset;
+            match telegram.poll_commands(timeout) {
+                Ok(commands) => {
+                    let telegram = telegram.clone();
+                    // save the offset right away, so no update is answered twice
+                    if telegram.update_offset != offset && config.is_file_based {
+                        if let Err(err) = config.save_to_config_file() {
+                            logging::error(&format!(
+                                "Couldn't save the bot's update offset: {err:#}"
+                            ));
+                        }
+                    }
+                    for (chat_id, command) in commands {
+                        sent |= command == BotCommand::Check;
+                        answer_command(config, &telegram, chat_id, command);
+                    }
+                }
+                Err(err) => {
+                    logging::warning(&format!("Couldn't poll for bot commands: {err:#}"));
+                    // don't retry a failing poll any faster than a succeeding one
+                    let pause = Duration::from_secs(timeout.min(MAX_POLL_SECS));
+                    std::thread::sleep(
+                        pause.min(deadline.saturating_duration_since(Instant::now())),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn answer_command(
+    config: &mut Configuration,
+    telegram: &Telegram,
+    chat_id: i64,
+    command: BotCommand,
+) {
+    logging::info(&format!(
+        "Answering {command:?} command from Telegram chat {chat_id}"
+    ));
+    if command == BotCommand::Check {
+        if let Err(err) = monitor_once(config) {
+            let reply = escape_html(&fill(
+                config.locale.catalog().check_failed,
+                &[("error", &err.to_string())],
+            ));
+            if let Err(err) = telegram.send_message(chat_id, &reply) {
+                logging::warning(&format!("Couldn't answer bot command: {err:#}"));
+            }
+        }
+        if let Err(err) = flush_digest(config) {
+            logging::error(&format!("Couldn't send digest: {err:#}"))
+        }
+    }
+    if let Err(err) = telegram.send_message(chat_id, &status_html(config)) {
+        logging::warning(&format!("Couldn't answer bot command: {err:#}"));
     }
 }
 
@@ -160,7 +732,18 @@ pub fn monitor_loop(config: &mut Configuration, interval_secs: u64) -> ! {
 mod tests {
     use crate::{get_test_config, Configuration};
 
-    use super::{current_ip, initialize_state, monitor_once, send_change_notification};
+    use crate::notify::digest::{self, DigestOptions};
+    use crate::notify::email::{Delivery, SmtpOptions, TlsMode};
+    use crate::notify::stub::SmtpStub;
+
+    use super::super::current_ip;
+    use super::{flush_digest, retry_deliveries, send_error_notification, Destination};
+    use super::{initialize_state, monitor_once, send_change_notification};
+    use crate::notify::outbox::Outbox;
+    use crate::notify::recipient::Recipient;
+    use crate::notify::state_file::tests::TempFile;
+    use crate::notify::{Channel, Telegram};
+    use crate::Event;
 
     #[test]
     fn test_lookup() {
@@ -175,8 +758,8 @@ mod tests {
 
     #[test]
     fn test_change_notification() {
-        let config = Configuration::new_from_environment(false);
-        send_change_notification(&config, "Some host", "old", "new")
+        let mut config = Configuration::new_from_environment(false);
+        send_change_notification(&mut config, "Some host", "old", "new")
             .expect("Failed to send email notification of address change");
     }
 
@@ -208,4 +791,181 @@ mod tests {
         assert_eq!(monitor_once(&mut config).expect("Monitor state failed"), 1);
         assert_ne!(config.last_update, last_update);
     }
+
+    fn digest_config(smtp: SmtpOptions) -> Configuration {
+        let mut config = Configuration::default();
+        config.from_server = String::from("127.0.0.1");
+        config.from_address = String::from("monitor@example.com");
+        config.recipients = vec![Recipient::new("admin@example.com")];
+        config.smtp = smtp;
+        config.digest = DigestOptions {
+            enabled: true,
+            window_secs: 0,
+        };
+        config
+    }
+
+    #[test]
+    fn test_digest() {
+        let server = SmtpStub::start();
+        let mut config = digest_config(SmtpOptions {
+            delivery: Delivery::Relay,
+            smtp_port: server.port,
+            tls_mode: TlsMode::None,
+            ..Default::default()
+        });
+        send_change_notification(&mut config, "a.host", "1.1.1.1", "2.2.2.2").unwrap();
+        send_change_notification(&mut config, "b.host", "3.3.3.3", "4.4.4.4").unwrap();
+        send_error_notification(&mut config, eyre::eyre!("lookup failed")).unwrap();
+        assert_eq!(config.digest_queue.len(), 3);
+        flush_digest(&mut config).expect("Digest send failed");
+        assert!(config.digest_queue.is_empty());
+        let transcript = server.transcript();
+        assert!(transcript.contains(&String::from(
+            "Subject: DNS monitoring digest: 2 address changes, 1 error"
+        )));
+        // nothing is sent when nothing was collected
+        flush_digest(&mut config).expect("Empty digest send failed");
+    }
+
+    #[test]
+    fn test_failed_digest_is_kept() {
+        let mut config = digest_config(SmtpOptions {
+            delivery: Delivery::Sendmail,
+            sendmail_command: String::from("/nonexistent/sendmail"),
+            ..Default::default()
+        });
+        send_change_notification(&mut config, "a.host", "1.1.1.1", "2.2.2.2").unwrap();
+        let err = flush_digest(&mut config).expect_err("Digest sent without a mailer");
+        assert!(err.to_string().contains("E-mail notification failed"));
+        assert_eq!(config.digest_queue.len(), 1);
+    }
+
+    #[test]
+    fn test_saved_digest_is_sent() {
+        let file = TempFile::new("digest");
+        let mut config = digest_config(SmtpOptions::default());
+        send_change_notification(&mut config, "a.host", "1.1.1.1", "2.2.2.2").unwrap();
+        send_error_notification(&mut config, eyre::eyre!("lookup failed")).unwrap();
+        digest::save_queue(&file.path(), &config.digest_queue).unwrap();
+        // a restarted monitor sends the events collected before it stopped
+        let server = SmtpStub::start();
+        let mut config = digest_config(SmtpOptions {
+            delivery: Delivery::Relay,
+            smtp_port: server.port,
+            tls_mode: TlsMode::None,
+            ..Default::default()
+        });
+        config.digest_queue = digest::load_queue(&file.path()).unwrap();
+        assert_eq!(config.digest_queue.len(), 2);
+        flush_digest(&mut config).expect("Digest send failed");
+        assert!(config.digest_queue.is_empty());
+        assert!(server.transcript().contains(&String::from(
+            "Subject: DNS monitoring digest: 1 address change, 1 error"
+        )));
+    }
+
+    #[test]
+    fn test_retry_deliveries() {
+        let server = SmtpStub::start();
+        let config = digest_config(SmtpOptions {
+            delivery: Delivery::Relay,
+            smtp_port: server.port,
+            tls_mode: TlsMode::None,
+            ..Default::default()
+        });
+        let file = TempFile::new("retry");
+        let mut outbox = Outbox::load(file.path()).unwrap();
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let err = eyre::eyre!("refused");
+        outbox.add(
+            String::from("email to admin@example.com"),
+            String::from("email:admin@example.com"),
+            event.clone(),
+            &err,
+        );
+        outbox.add(
+            String::from("Slack webhook to hooks.slack.com"),
+            String::from("slack:0123456789abcdef"),
+            event,
+            &err,
+        );
+        // nothing is due yet
+        assert!(!retry_deliveries(&config, &mut outbox, false));
+        assert_eq!(outbox.deliveries().len(), 2);
+        // a forced retry delivers the email, and gives up on the removed channel
+        assert!(retry_deliveries(&config, &mut outbox, true));
+        assert!(server
+            .transcript()
+            .contains(&String::from("Subject: DNS change for some.host")));
+        assert_eq!(outbox.deliveries().len(), 1);
+        assert!(outbox.deliveries()[0].stale);
+        assert!(outbox.deliveries()[0]
+            .last_error
+            .contains("no longer configured"));
+    }
+
+    #[test]
+    fn test_destination_for_each_telegram_chat() {
+        let mut config = Configuration::default();
+        let telegram = Telegram::new("123:abc", vec![42, 43]).unwrap();
+        config.channels = vec![Channel::Telegram(telegram)];
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let names: Vec<String> =
+            Destination::all(&config, &config.recipients, &config.channels, &event)
+                .iter()
+                .map(Destination::name)
+                .collect();
+        assert_eq!(
+            names,
+            vec!["Telegram bot for [42]", "Telegram bot for [43]"]
+        );
+        let ids: Vec<String> =
+            Destination::all(&config, &config.recipients, &config.channels, &event)
+                .iter()
+                .map(Destination::id)
+                .collect();
+        assert!(ids[0].starts_with("telegram:"));
+        assert_ne!(ids[0], ids[1]);
+        let Some(Destination::Channel(channel)) = Destination::find(&config, &event, &ids[1]).pop()
+        else {
+            panic!("No destination for the second chat");
+        };
+        assert!(matches!(&*channel, Channel::Telegram(telegram) if telegram.chat_ids == [43]));
+    }
+
+    #[test]
+    fn test_destinations_by_subscription() {
+        let mut config = Configuration::default();
+        let mut changes_only = Recipient::new("net@example.com");
+        changes_only.events = vec![crate::EventKind::Change];
+        let mut reports_only = Recipient::new("boss@example.com");
+        reports_only.events = vec![crate::EventKind::Report];
+        config.recipients = vec![
+            Recipient::new("a@example.com"),
+            changes_only,
+            Recipient::new("b@example.com"),
+            reports_only,
+        ];
+        let names = |event: &Event| -> Vec<String> {
+            Destination::all(&config, &config.recipients, &config.channels, event)
+                .iter()
+                .map(Destination::name)
+                .collect()
+        };
+        assert_eq!(
+            names(&Event::change("some.host", "1.2.3.4", "5.6.7.8")),
+            vec!["email to a@example.com, net@example.com, b@example.com"]
+        );
+        assert_eq!(
+            names(&Event::error(&eyre::eyre!("lookup failed"))),
+            vec!["email to a@example.com, b@example.com"]
+        );
+        let mut report = Event::error(&eyre::eyre!("lookup failed"));
+        report.event = crate::EventKind::Report;
+        assert_eq!(
+            names(&report),
+            vec!["email to a@example.com, b@example.com, boss@example.com"]
+        );
+    }
 }
@@ -0,0 +1,787 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, LocalResult, TimeZone};
+use eyre::{eyre, Report, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::configure::{decrypt_password, encrypt_password};
+use crate::{Configuration, LookupFailure};
+
+use diagnostics::Diagnostics;
+use email::Attachment;
+use locale::{fill, Catalog, Locale, Translation};
+use report::{ReportPeriod, StabilityReport};
+
+pub use chat::{ChatWebhook, Platform};
+pub use hook::CommandHook;
+pub use matrix::Matrix;
+pub use mqtt::Mqtt;
+pub use push::{Gotify, Ntfy, Pushover};
+pub use telegram::{BotCommand, Telegram};
+pub use webhook::Webhook;
+
+pub mod chat;
+pub mod diagnostics;
+pub mod digest;
+pub mod email;
+pub mod escalation;
+pub mod heartbeat;
+pub mod hook;
+pub mod locale;
+pub mod matrix;
+pub mod mqtt;
+pub mod oauth;
+pub mod outbox;
+pub mod problem;
+pub mod push;
+pub mod recipient;
+pub mod report;
+pub mod security;
+pub mod state_file;
+pub mod telegram;
+pub mod template;
+pub mod webhook;
+
+#[cfg(test)]
+pub mod stub;
+
+/// The kinds of event that produce notifications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EventKind {
+    Initial,
+    Change,
+    Error,
+    /// A collection of other events, sent as one.
+    Digest,
+    /// The end of an error.
+    Recovery,
+    /// The daily summary that shows monitoring is still running.
+    Heartbeat,
+    /// The weekly or monthly stability report.
+    Report,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Initial => "initial",
+            EventKind::Change => "change",
+            EventKind::Error => "error",
+            EventKind::Digest => "digest",
+            EventKind::Recovery => "recovery",
+            EventKind::Heartbeat => "heartbeat",
+            EventKind::Report => "report",
+        }
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A monitoring event, with both its data and its default text rendering.
+///
+/// This is also the JSON payload that is posted to webhooks,
+/// so changes to its fields must be reflected in the documentation.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub event: EventKind,
+    pub monitor_host: String,
+    pub timestamp: DateTime<Local>,
+    pub host: Option<String>,
+    pub old_address: Option<String>,
+    pub new_address: Option<String>,
+    pub addresses: BTreeMap<String, String>,
+    pub error: Option<String>,
+    /// The language of the subject and body.
+    #[serde(default)]
+    pub locale: Locale,
+    pub subject: String,
+    pub body: Vec<String>,
+    /// The subject and body in every language, until the event is localized.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub translations: BTreeMap<Locale, Translation>,
+    /// Files to attach to emails of the event.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// Details of the monitor and its lookups, if they are wanted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Box<Diagnostics>>,
+    /// The events collected into this one, if it's a digest.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<Event>,
+}
+
+impl Event {
+    fn new(event: EventKind, text: impl Fn(Locale, &Catalog) -> Translation) -> Self {
+        let translations: BTreeMap<Locale, Translation> = Locale::ALL
+            .iter()
+            .map(|locale| (*locale, text(*locale, locale.catalog())))
+            .collect();
+        let Translation { subject, body } = translations[&Locale::default()].clone();
+        Self {
+            event,
+            monitor_host: monitor_host(),
+            timestamp: Local::now(),
+            host: None,
+            old_address: None,
+            new_address: None,
+            addresses: BTreeMap::new(),
+            error: None,
+            locale: Locale::default(),
+            subject,
+            body,
+            translations,
+            attachments: vec![],
+            diagnostics: None,
+            events: vec![],
+        }
+    }
+
+    pub fn initial(config: &Configuration) -> Self {
+        let addresses = config
+            .state
+            .iter()
+            .map(|(host, addr)| (host.clone(), addr.clone()))
+            .collect();
+        Self::initial_for(addresses, config.last_update)
+    }
+
+    /// The initial event for just the given hosts and addresses.
+    pub fn initial_for(addresses: BTreeMap<String, String>, last_update: i64) -> Self {
+        let hostname = monitor_host();
+        let first = last_update <= 0;
+        let last_check = match Local.timestamp_millis_opt(last_update) {
+            LocalResult::Single(last_check) if !first => Some(last_check),
+            _ => None,
+        };
+        let mut event = Self::new(EventKind::Initial, |locale, catalog| {
+            let intro = if first {
+                catalog.initial_starting
+            } else {
+                catalog.initial_restarting
+            };
+            let mut body = vec![fill(intro, &[("monitor", &hostname)])];
+            let line = if first {
+                catalog.initial_address
+            } else {
+                catalog.known_address
+            };
+            for (host, addr) in addresses.iter() {
+                body.push(fill(line, &[("host", host), ("address", addr)]))
+            }
+            if let Some(last_check) = last_check {
+                let time = locale.format_time(last_check);
+                body.push(fill(catalog.last_check, &[("time", &time)]))
+            }
+            body.push(catalog.initial_outro.to_string());
+            Translation {
+                subject: catalog.initial_subject.to_string(),
+                body,
+            }
+        });
+        event.addresses = addresses;
+        event
+    }
+
+    /// The daily summary of the given hosts and addresses, with when each last changed.
+    pub fn heartbeat(
+        addresses: BTreeMap<String, String>,
+        last_changed: &BTreeMap<String, i64>,
+        last_update: i64,
+    ) -> Self {
+        let hostname = monitor_host();
+        let mut event = Self::new(EventKind::Heartbeat, |locale, catalog| {
+            let mut body = vec![fill(catalog.heartbeat_intro, &[("monitor", &hostname)])];
+            for (host, addr) in addresses.iter() {
+                let changed = match last_changed.get(host) {
+                    Some(millis) => match Local.timestamp_millis_opt(*millis) {
+                        LocalResult::Single(changed) => locale.format_time(changed),
+                        _ => catalog.unknown_time.to_string(),
+                    },
+                    None => catalog.never_changed.to_string(),
+                };
+                body.push(fill(
+                    catalog.heartbeat_address,
+                    &[("host", host), ("address", addr), ("changed", &changed)],
+                ))
+            }
+            if let LocalResult::Single(last_check) = Local.timestamp_millis_opt(last_update) {
+                if last_update > 0 {
+                    let time = locale.format_time(last_check);
+                    body.push(fill(catalog.last_check, &[("time", &time)]))
+                }
+            }
+            body.push(catalog.heartbeat_outro.to_string());
+            Translation {
+                subject: catalog.heartbeat_subject.to_string(),
+                body,
+            }
+        });
+        event.addresses = addresses;
+        event
+    }
+
+    pub fn change(name: &str, old_address: &str, new_address: &str) -> Self {
+        let mut event = Self::new(EventKind::Change, |_, catalog| Translation {
+            subject: fill(catalog.change_subject, &[("host", name)]),
+            body: vec![
+                fill(catalog.change_intro, &[("host", name)]),
+                fill(catalog.change_old, &[("address", old_address)]),
+                fill(catalog.change_new, &[("address", new_address)]),
+                catalog.change_outro.to_string(),
+            ],
+        });
+        event.host = Some(name.to_string());
+        event.old_address = Some(old_address.to_string());
+        event.new_address = Some(new_address.to_string());
+        event
+    }
+
+    pub fn error(err: &Report) -> Self {
+        let error = err.to_string();
+        let mut event = Self::new(EventKind::Error, |_, catalog| Translation {
+            subject: catalog.error_subject.to_string(),
+            body: vec![
+                fill(catalog.error_intro, &[("error", &error)]),
+                catalog.error_outro.to_string(),
+            ],
+        });
+        event.error = Some(error);
+        event.host = err
+            .downcast_ref::<LookupFailure>()
+            .map(|failure| failure.host().to_string());
+        event
+    }
+
+    /// The end of an error that started at the given time and failed the given number of checks.
+    pub fn recovery(
+        error: &str,
+        host: Option<&str>,
+        started: DateTime<Local>,
+        failed_checks: u32,
+    ) -> Self {
+        let now = Local::now();
+        let mut event = Self::new(EventKind::Recovery, |locale, catalog| Translation {
+            subject: match host {
+                Some(host) => fill(catalog.recovery_host_subject, &[("host", host)]),
+                None => catalog.recovery_subject.to_string(),
+            },
+            body: vec![
+                fill(catalog.recovery_intro, &[("error", error)]),
+                fill(
+                    catalog.recovery_started,
+                    &[("time", &locale.format_time(started))],
+                ),
+                fill(
+                    catalog.recovery_lasted,
+                    &[
+                        ("duration", &locale.describe_duration(now - started)),
+                        ("count", &failed_checks.to_string()),
+                    ],
+                ),
+            ],
+        });
+        event.timestamp = now;
+        event.host = host.map(String::from);
+        event.error = Some(error.to_string());
+        event
+    }
+
+    /// The stability report, with its records attached as a CSV file.
+    pub fn report(report: &StabilityReport) -> Self {
+        let hostname = monitor_host();
+        let mut event = Self::new(EventKind::Report, |locale, catalog| {
+            let subject = match report.period {
+                ReportPeriod::Monthly => catalog.report_monthly_subject,
+                _ => catalog.report_weekly_subject,
+            };
+            let mut body = vec![fill(
+                catalog.report_intro,
+                &[
+                    ("monitor", &hostname),
+                    ("start", &locale.format_time(report.start)),
+                    ("end", &locale.format_time(report.end)),
+                ],
+            )];
+            for stability in report.hosts.iter() {
+                let changes = stability.changes.to_string();
+                let failures = stability.failed_lookups.to_string();
+                let line = match (stability.longest, stability.shortest) {
+                    (Some(longest), Some(shortest)) => fill(
+                        catalog.report_host,
+                        &[
+                            ("host", &stability.host),
+                            ("changes", &changes),
+                            ("longest", &locale.describe_duration(longest)),
+                            ("shortest", &locale.describe_duration(shortest)),
+                            ("failures", &failures),
+                        ],
+                    ),
+                    _ => fill(
+                        catalog.report_host_without_times,
+                        &[
+                            ("host", &stability.host),
+                            ("changes", &changes),
+                            ("failures", &failures),
+                        ],
+                    ),
+                };
+                body.push(line);
+            }
+            let count = report.failed_checks.to_string();
+            body.push(fill(catalog.report_failures, &[("count", &count)]));
+            if report.errors.is_empty() {
+                body.push(catalog.report_no_errors.to_string());
+            }
+            for error in report.errors.iter() {
+                let (one, many) = catalog.times;
+                let times = if error.count == 1 { one } else { many };
+                body.push(fill(
+                    catalog.report_error,
+                    &[
+                        ("error", &error.error),
+                        ("count", &format!("{} {times}", error.count)),
+                        ("time", &locale.format_time(error.first)),
+                    ],
+                ));
+            }
+            body.push(catalog.report_outro.to_string());
+            Translation {
+                subject: subject.to_string(),
+                body,
+            }
+        });
+        event.attachments = vec![Attachment {
+            filename: report.csv_name(),
+            content_type: String::from("text/csv"),
+            content: report.csv(),
+        }];
+        event
+    }
+
+    /// A digest of the events.  If they have all been localized,
+    /// then so is the digest.
+    pub fn digest(events: Vec<Event>) -> Self {
+        let hostname = monitor_host();
+        let localized = events
+            .first()
+            .filter(|event| event.translations.is_empty())
+            .map(|event| event.locale);
+        let mut digest = Self::new(EventKind::Digest, |locale, catalog| {
+            let mut counts = vec![];
+            for (kind, one, many) in catalog.counts {
+                match events.iter().filter(|event| event.event == kind).count() {
+                    0 => {}
+                    1 => counts.push(format!("1 {one}")),
+                    n => counts.push(format!("{n} {many}")),
+                }
+            }
+            let mut body = vec![fill(
+                catalog.digest_intro,
+                &[("monitor", &hostname), ("count", &events.len().to_string())],
+            )];
+            for event in events.iter() {
+                let text = event.text_in(locale);
+                body.push(String::new());
+                body.push(fill(
+                    catalog.digest_item,
+                    &[
+                        ("subject", &text.subject),
+                        ("time", &locale.format_time(event.timestamp)),
+                    ],
+                ));
+                body.extend(text.body);
+            }
+            Translation {
+                subject: fill(catalog.digest_subject, &[("counts", &counts.join(", "))]),
+                body,
+            }
+        });
+        digest.events = events;
+        match localized {
+            Some(locale) => digest.localized(locale),
+            None => digest,
+        }
+    }
+
+    /// The subject and body in the given language, if the event has them,
+    /// or else in its own language.
+    pub fn text_in(&self, locale: Locale) -> Translation {
+        match self.translations.get(&locale) {
+            Some(translation) => translation.clone(),
+            None => Translation {
+                subject: self.subject.clone(),
+                body: self.body.clone(),
+            },
+        }
+    }
+
+    /// The event with its subject and body (and those of its parts) in the given language.
+    pub fn localized(&self, locale: Locale) -> Self {
+        let mut event = self.clone();
+        if let Some(translation) = event.translations.remove(&locale) {
+            event.locale = locale;
+            event.subject = translation.subject;
+            event.body = translation.body;
+        }
+        event.translations.clear();
+        event.events = self
+            .events
+            .iter()
+            .map(|part| part.localized(locale))
+            .collect();
+        event
+    }
+
+    /// Change the subject and body in every language.
+    pub fn edit_text(&mut self, edit: impl Fn(Locale, &Catalog, &mut Translation)) {
+        for (locale, translation) in self.translations.iter_mut() {
+            edit(*locale, locale.catalog(), translation);
+        }
+        let mut text = Translation {
+            subject: std::mem::take(&mut self.subject),
+            body: std::mem::take(&mut self.body),
+        };
+        edit(self.locale, self.locale.catalog(), &mut text);
+        self.subject = text.subject;
+        self.body = text.body;
+    }
+
+    /// The events collected into a digest, or else just this event.
+    pub fn parts(&self) -> &[Event] {
+        if self.event == EventKind::Digest {
+            &self.events
+        } else {
+            std::slice::from_ref(self)
+        }
+    }
+
+    /// The most urgent kind of event among the parts of this one,
+    /// which determines how urgently it's presented.
+    pub fn severity(&self) -> EventKind {
+        self.parts()
+            .iter()
+            .map(|part| part.event)
+            .max_by_key(|kind| match kind {
+                EventKind::Initial
+                | EventKind::Recovery
+                | EventKind::Heartbeat
+                | EventKind::Report => 0,
+                EventKind::Change | EventKind::Digest => 1,
+                EventKind::Error => 2,
+            })
+            .unwrap_or(EventKind::Change)
+    }
+}
+
+/// A non-email destination for notifications.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Channel {
+    Webhook(Webhook),
+    Slack(ChatWebhook),
+    Discord(ChatWebhook),
+    Teams(ChatWebhook),
+    Ntfy(Ntfy),
+    Gotify(Gotify),
+    Pushover(Pushover),
+    Telegram(Telegram),
+    Matrix(Matrix),
+    Mqtt(Mqtt),
+    Command(CommandHook),
+}
+
+impl Channel {
+    /// The kinds of channel that can be added during configuration.
+    pub const KINDS: &'static [&'static str] = &[
+        "webhook",
+        "Slack webhook",
+        "Discord webhook",
+        "Microsoft Teams webhook",
+        "ntfy topic",
+        "Gotify application",
+        "Pushover user",
+        "Telegram bot",
+        "Matrix room",
+        "MQTT broker",
+        "command hook",
+    ];
+
+    pub fn interview(kind: &str) -> Result<Self> {
+        match kind {
+            "webhook" => Ok(Channel::Webhook(Webhook::interview()?)),
+            "Slack webhook" => Ok(Channel::Slack(ChatWebhook::interview(Platform::Slack)?)),
+            "Discord webhook" => Ok(Channel::Discord(ChatWebhook::interview(Platform::Discord)?)),
+            "Microsoft Teams webhook" => {
+                Ok(Channel::Teams(ChatWebhook::interview(Platform::Teams)?))
+            }
+            "ntfy topic" => Ok(Channel::Ntfy(Ntfy::interview()?)),
+            "Gotify application" => Ok(Channel::Gotify(Gotify::interview()?)),
+            "Pushover user" => Ok(Channel::Pushover(Pushover::interview()?)),
+            "Telegram bot" => Ok(Channel::Telegram(Telegram::interview()?)),
+            "Matrix room" => Ok(Channel::Matrix(Matrix::interview()?)),
+            "MQTT broker" => Ok(Channel::Mqtt(Mqtt::interview()?)),
+            "command hook" => Ok(Channel::Command(CommandHook::interview()?)),
+            _ => Err(eyre!("Unknown notification channel type: {kind}")),
+        }
+    }
+
+    /// The name of this type of channel, as used in the configuration.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Channel::Webhook(_) => "webhook",
+            Channel::Slack(_) => "slack",
+            Channel::Discord(_) => "discord",
+            Channel::Teams(_) => "teams",
+            Channel::Ntfy(_) => "ntfy",
+            Channel::Gotify(_) => "gotify",
+            Channel::Pushover(_) => "pushover",
+            Channel::Telegram(_) => "telegram",
+            Channel::Matrix(_) => "matrix",
+            Channel::Mqtt(_) => "mqtt",
+            Channel::Command(_) => "command",
+        }
+    }
+
+    /// An identifier for where the channel sends, which stays the same when its other
+    /// settings change. It's a hash, so it doesn't give away any secret in a URL.
+    pub fn id(&self) -> String {
+        let target = match self {
+            Channel::Webhook(webhook) => webhook.url.clone(),
+            Channel::Slack(webhook) | Channel::Discord(webhook) | Channel::Teams(webhook) => {
+                webhook.url.clone()
+            }
+            Channel::Ntfy(ntfy) => ntfy.topic_url.clone(),
+            Channel::Gotify(gotify) => gotify.server_url.clone(),
+            Channel::Pushover(pushover) => {
+                format!("{} {}", pushover.api_url, pushover.encrypted_user_key())
+            }
+            Channel::Telegram(telegram) => format!("{} {:?}", telegram.api_url, telegram.chat_ids),
+            Channel::Matrix(matrix) => format!("{} {:?}", matrix.homeserver_url, matrix.room_ids),
+            Channel::Mqtt(mqtt) => format!("{}:{} {}", mqtt.host, mqtt.port, mqtt.topic_prefix),
+            Channel::Command(hook) => format!("{} {:?}", hook.program, hook.args),
+        };
+        let hash = Sha256::digest(target.as_bytes());
+        format!("{}:{}", self.type_name(), hex::encode(&hash[..8]))
+    }
+
+    pub fn send(&self, event: &Event) -> Result<()> {
+        match self {
+            Channel::Webhook(webhook) => webhook.post(event),
+            Channel::Slack(webhook) => webhook.post(Platform::Slack, event),
+            Channel::Discord(webhook) => webhook.post(Platform::Discord, event),
+            Channel::Teams(webhook) => webhook.post(Platform::Teams, event),
+            Channel::Ntfy(ntfy) => ntfy.post(event),
+            Channel::Gotify(gotify) => gotify.post(event),
+            Channel::Pushover(pushover) => pushover.post(event),
+            Channel::Telegram(telegram) => telegram.send(event),
+            Channel::Matrix(matrix) => matrix.send(event),
+            Channel::Mqtt(mqtt) => mqtt.publish(event),
+            Channel::Command(hook) => hook.run(event),
+        }
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Webhook(webhook) => write!(f, "webhook to {}", redact_url(&webhook.url)),
+            Channel::Slack(webhook) => write!(f, "Slack webhook to {}", redact_url(&webhook.url)),
+            Channel::Discord(webhook) => {
+                write!(f, "Discord webhook to {}", redact_url(&webhook.url))
+            }
+            Channel::Teams(webhook) => write!(f, "Teams webhook to {}", redact_url(&webhook.url)),
+            Channel::Ntfy(ntfy) => write!(f, "ntfy topic at {}", redact_url(&ntfy.topic_url)),
+            Channel::Gotify(gotify) => {
+                write!(
+                    f,
+                    "Gotify application at {}",
+                    redact_url(&gotify.server_url)
+                )
+            }
+            Channel::Pushover(_) => write!(f, "Pushover user"),
+            Channel::Telegram(telegram) => write!(f, "Telegram bot for {:?}", telegram.chat_ids),
+            Channel::Matrix(matrix) => write!(f, "Matrix rooms {:?}", matrix.room_ids),
+            Channel::Mqtt(mqtt) => write!(f, "MQTT broker {}:{}", mqtt.host, mqtt.port),
+            Channel::Command(hook) => write!(f, "command hook {}", hook.program),
+        }
+    }
+}
+
+/// The agent used for all outgoing HTTP requests.
+pub fn http_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .user_agent(concat!("ddns-monitor/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+/// Send a JSON payload, treating any non-2xx response as an error.
+pub fn send_json(request: ureq::Request, payload: &str) -> Result<()> {
+    check_response(
+        request
+            .set("Content-Type", "application/json")
+            .send_string(payload),
+    )
+}
+
+/// Treat any non-2xx response as an error.
+pub fn check_response(result: std::result::Result<ureq::Response, ureq::Error>) -> Result<()> {
+    let response = result?;
+    if (200..300).contains(&response.status()) {
+        Ok(())
+    } else {
+        Err(eyre!("Unexpected response status {}", response.status()))
+    }
+}
+
+/// Encrypt a channel secret for storage, leaving an empty secret empty.
+pub fn encrypt_secret(secret: &str) -> Result<String> {
+    if secret.is_empty() {
+        Ok(String::new())
+    } else {
+        encrypt_password(secret)
+    }
+}
+
+/// Decrypt a channel secret that was encrypted by [encrypt_secret].
+pub fn decrypt_secret(encrypted: &str) -> Result<String> {
+    if encrypted.is_empty() {
+        Ok(String::new())
+    } else {
+        decrypt_password(encrypted)
+    }
+}
+
+pub fn monitor_host() -> String {
+    gethostname::gethostname().to_string_lossy().to_string()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The text, cut short with an ellipsis if it has more than the given number of characters.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        None => text.to_string(),
+        Some(_) => {
+            let mut cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+    }
+}
+
+/// Percent-encode everything but the RFC 3986 unreserved characters.
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Channel URLs often embed credentials, so we only ever log their origin.
+fn redact_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let origin = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let origin = origin.rsplit('@').next().unwrap_or_default();
+    if scheme.is_empty() {
+        origin.to_string()
+    } else {
+        format!("{scheme}://{origin}/...")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("https://user:pw@hooks.example.com/services/T0/B0/secret?x=1"),
+            "https://hooks.example.com/..."
+        );
+        assert_eq!(redact_url("localhost:8080/hook"), "localhost:8080");
+    }
+
+    #[test]
+    fn test_change_event_payload() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let payload: serde_json::Value = serde_json::to_value(&event).expect("serialize");
+        assert_eq!(payload["event"], "change");
+        assert_eq!(payload["host"], "some.host");
+        assert_eq!(payload["oldAddress"], "1.2.3.4");
+        assert_eq!(payload["newAddress"], "5.6.7.8");
+        assert_eq!(payload["monitorHost"], monitor_host());
+        assert!(payload["error"].is_null());
+        let timestamp = payload["timestamp"].as_str().expect("timestamp");
+        DateTime::parse_from_rfc3339(timestamp).expect("timestamp is not RFC 3339");
+    }
+
+    #[test]
+    fn test_error_and_recovery() {
+        let err =
+            eyre!(LookupFailure::NoAddress(String::from("some.host"))).wrap_err("Check failed");
+        let event = Event::error(&err);
+        assert_eq!(event.host.as_deref(), Some("some.host"));
+        let started = event.timestamp - chrono::Duration::minutes(62);
+        let recovery = Event::recovery("lookup failed", Some("some.host"), started, 2);
+        assert_eq!(recovery.subject, "DNS monitoring of some.host recovered");
+        assert_eq!(
+            recovery.body[2],
+            "-- It lasted 1 hour and 2 minutes, over 2 failed checks."
+        );
+        let spanish = recovery.localized(Locale::Spanish);
+        assert_eq!(spanish.locale, Locale::Spanish);
+        assert_eq!(
+            spanish.subject,
+            "La supervisión de DNS de some.host se ha recuperado"
+        );
+        assert_eq!(
+            spanish.body[2],
+            "-- Duró 1 hora y 2 minutos, a lo largo de 2 comprobaciones fallidas."
+        );
+        assert!(spanish.translations.is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::locale::fill;
+use super::{http_agent, send_json, truncate, Event, EventKind};
+
+/// The chat platforms whose incoming webhooks we know how to format for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Slack,
+    Discord,
+    Teams,
+}
+
+/// An incoming webhook for a chat platform.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatWebhook {
+    pub url: String,
+}
+
+impl ChatWebhook {
+    pub fn post(&self, platform: Platform, event: &Event) -> Result<()> {
+        let message = match platform {
+            Platform::Slack => slack_message(event),
+            Platform::Discord => discord_message(event),
+            Platform::Teams => teams_message(event),
+        };
+        let request = http_agent().post(&self.url);
+        send_json(request, &message.to_string())
+            .wrap_err(format!("{platform:?} webhook post failed"))
+    }
+
+    pub fn interview(platform: Platform) -> Result<Self> {
+        let url: String = dialoguer::Input::new()
+            .with_prompt(format!("{platform:?} incoming webhook URL"))
+            .allow_empty(false)
+            .validate_with(|url: &String| -> std::result::Result<(), String> {
+                if url.starts_with("https://") {
+                    Ok(())
+                } else {
+                    Err(format!("{url} is not an https URL"))
+                }
+            })
+            .interact()
+            .wrap_err("Input error")?;
+        Ok(Self {
+            url: url.trim().to_string(),
+        })
+    }
+}
+
+/// The color of the message for each type of event, as RGB.
+fn color(kind: EventKind) -> u32 {
+    match kind {
+        EventKind::Initial | EventKind::Recovery | EventKind::Heartbeat | EventKind::Report => {
+            0x2EB886
+        }
+        EventKind::Change | EventKind::Digest => 0xDAA038,
+        EventKind::Error => 0xA30200,
+    }
+}
+
+/// The labelled values that are shown as fields of the message.
+fn facts(event: &Event) -> Vec<(String, String)> {
+    let catalog = event.locale.catalog();
+    let mut facts = vec![];
+    if let Some(host) = &event.host {
+        facts.push((catalog.host_label.to_string(), host.clone()));
+    }
+    if let Some(old_address) = &event.old_address {
+        facts.push((catalog.old_address_label.to_string(), old_address.clone()));
+    }
+    if let Some(new_address) = &event.new_address {
+        facts.push((catalog.new_address_label.to_string(), new_address.clone()));
+    }
+    for (host, address) in event.addresses.iter() {
+        facts.push((host.clone(), address.clone()));
+    }
+    facts
+}
+
+fn footer(event: &Event) -> String {
+    fill(
+        event.locale.catalog().footer,
+        &[
+            ("monitor", &event.monitor_host),
+            ("time", &event.locale.format_time(event.timestamp)),
+        ],
+    )
+}
+
+/// Slack text in its mrkdwn format, in which `&`, `<` and `>` are escaped,
+/// cut short if it's longer than the given number of characters.
+fn mrkdwn(text: &str, max_chars: usize) -> String {
+    let escaped: Vec<String> = text
+        .chars()
+        .map(|c| match c {
+            '&' => String::from("&amp;"),
+            '<' => String::from("&lt;"),
+            '>' => String::from("&gt;"),
+            c => c.to_string(),
+        })
+        .collect();
+    let length: usize = escaped.iter().map(|part| part.chars().count()).sum();
+    if length <= max_chars {
+        return escaped.concat();
+    }
+    // cut between characters, so that no escape is cut in half
+    let mut cut = String::new();
+    let mut cut_length = 0;
+    for part in escaped {
+        let part_length = part.chars().count();
+        if cut_length + part_length >= max_chars {
+            break;
+        }
+        cut.push_str(&part);
+        cut_length += part_length;
+    }
+    cut.push('…');
+    cut
+}
+
+/// A Slack message with a colored attachment built from Block Kit blocks.
+pub fn slack_message(event: &Event) -> Value {
+    // Slack limits header text to 150 characters, and section text to 3000
+    let header = truncate(&event.subject, 150);
+    let text = mrkdwn(&event.body.join("\n"), 3000);
+    let mut blocks = vec![
+        json!({"type": "header", "text": {"type": "plain_text", "text": header}}),
+        json!({"type": "section", "text": {"type": "mrkdwn", "text": text}}),
+    ];
+    let fields: Vec<Value> = facts(event)
+        .into_iter()
+        .map(|(name, value)| {
+            let text = format!("*{}*\n{}", mrkdwn(&name, 100), mrkdwn(&value, 1800));
+            json!({"type": "mrkdwn", "text": text})
+        })
+        .collect();
+    // Slack allows at most 10 fields in a section
+    for chunk in fields.chunks(10) {
+        blocks.push(json!({"type": "section", "fields": chunk}));
+    }
+    let footer = mrkdwn(&footer(event), 3000);
+    blocks.push(json!({"type": "context", "elements": [{"type": "mrkdwn", "text": footer}]}));
+    json!({
+        "text": event.subject,
+        "attachments": [{
+            "color": format!("#{:06X}", color(event.severity())),
+            "blocks": blocks,
+        }],
+    })
+}
+
+/// A Discord message with a single colored embed.
+pub fn discord_message(event: &Event) -> Value {
+    let fields: Vec<Value> = facts(event)
+        .into_iter()
+        .take(25) // Discord allows at most 25 fields in an embed
+        .map(|(name, value)| {
+            json!({"name": truncate(&name, 256), "value": truncate(&value, 1024), "inline": true})
+        })
+        .collect();
+    // Discord limits embed titles to 256 characters, and descriptions to 4096
+    json!({
+        "embeds": [{
+            "title": truncate(&event.subject, 256),
+            "description": truncate(&event.body.join("\n"), 4096),
+            "color": color(event.severity()),
+            "fields": fields,
+            "footer": {"text": footer(event)},
+            "timestamp": event.timestamp.to_rfc3339(),
+        }],
+    })
+}
+
+/// A Teams message with a single Adaptive Card.
+pub fn teams_message(event: &Event) -> Value {
+    let (style, severity) = match event.severity() {
+        EventKind::Initial | EventKind::Recovery | EventKind::Heartbeat | EventKind::Report => {
+            ("good", "Good")
+        }
+        EventKind::Change | EventKind::Digest => ("warning", "Warning"),
+        EventKind::Error => ("attention", "Attention"),
+    };
+    let facts: Vec<Value> = facts(event)
+        .into_iter()
+        .map(|(title, value)| json!({"title": title, "value": value}))
+        .collect();
+    let mut body = vec![
+        json!({
+            "type": "Container",
+            "style": style,
+            "bleed": true,
+            "items": [{
+                "type": "TextBlock",
+                "text": event.subject,
+                "size": "Large",
+                "weight": "Bolder",
+                "color": severity,
+                "wrap": true,
+            }],
+        }),
+        json!({"type": "TextBlock", "text": event.body.join("\n\n"), "wrap": true}),
+    ];
+    if !facts.is_empty() {
+        body.push(json!({"type": "FactSet", "facts": facts}));
+    }
+    body.push(json!({
+        "type": "TextBlock",
+        "text": footer(event),
+        "size": "Small",
+        "isSubtle": true,
+        "wrap": true,
+    }));
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": body,
+            },
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::eyre;
+
+    use super::super::stub::StubServer;
+    use super::*;
+
+    fn post_to_stub(platform: Platform, event: &Event) -> Value {
+        let server = StubServer::start(vec![(200, "ok")]);
+        let webhook = ChatWebhook {
+            url: server.url.clone(),
+        };
+        webhook.post(platform, event).expect("chat post failed");
+        let request = server.next_request();
+        assert_eq!(request.headers["content-type"], "application/json");
+        serde_json::from_str(&request.body).expect("chat message is not JSON")
+    }
+
+    #[test]
+    fn test_slack_message() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let message = post_to_stub(Platform::Slack, &event);
+        assert_eq!(message["text"], "DNS change for some.host");
+        let attachment = &message["attachments"][0];
+        assert_eq!(attachment["color"], "#DAA038");
+        assert_eq!(attachment["blocks"][0]["type"], "header");
+        let fields = attachment["blocks"][2]["fields"].as_array().unwrap();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[2]["text"], "*New address*\n5.6.7.8");
+    }
+
+    #[test]
+    fn test_discord_message() {
+        let event = Event::error(&eyre!("DNS lookup failed on some.host"));
+        let message = post_to_stub(Platform::Discord, &event);
+        let embed = &message["embeds"][0];
+        assert_eq!(embed["title"], "DNS monitoring temporary failure");
+        assert_eq!(embed["color"], 0xA30200);
+        assert!(embed["fields"].as_array().unwrap().is_empty());
+        assert_eq!(embed["footer"]["text"], footer(&event));
+        assert!(embed["description"]
+            .as_str()
+            .unwrap()
+            .contains("DNS lookup failed on some.host"));
+    }
+
+    #[test]
+    fn test_slack_escaping_and_limits() {
+        assert_eq!(mrkdwn("<b> & <c>", 100), "&lt;b&gt; &amp; &lt;c&gt;");
+        // an escape is never cut in half
+        assert_eq!(mrkdwn("a<b", 5), "a…");
+        assert_eq!(mrkdwn("a<bc", 6), "a&lt;…");
+        let mut event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        event.body = vec!["x".repeat(5000)];
+        let message = slack_message(&event);
+        let text = message["attachments"][0]["blocks"][1]["text"]["text"]
+            .as_str()
+            .unwrap();
+        assert_eq!(text.chars().count(), 3000);
+        assert!(text.ends_with('…'));
+        event.body = vec![String::from("<!channel> & friends")];
+        let message = slack_message(&event);
+        assert_eq!(
+            message["attachments"][0]["blocks"][1]["text"]["text"],
+            "&lt;!channel&gt; &amp; friends"
+        );
+    }
+
+    #[test]
+    fn test_discord_limits() {
+        let mut event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        event.subject = "s".repeat(300);
+        event.body = vec!["x".repeat(5000)];
+        let message = discord_message(&event);
+        let embed = &message["embeds"][0];
+        let description = embed["description"].as_str().unwrap();
+        assert_eq!(description.chars().count(), 4096);
+        assert!(description.ends_with('…'));
+        assert_eq!(embed["title"].as_str().unwrap().chars().count(), 256);
+        event.body = vec![String::from("short")];
+        assert_eq!(discord_message(&event)["embeds"][0]["description"], "short");
+    }
+
+    #[test]
+    fn test_teams_message() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let message = post_to_stub(Platform::Teams, &event);
+        let card = &message["attachments"][0]["content"];
+        assert_eq!(card["type"], "AdaptiveCard");
+        assert_eq!(card["body"][0]["style"], "warning");
+        assert_eq!(card["body"][2]["facts"][0]["value"], "some.host");
+    }
+
+    #[test]
+    fn test_post_failure() {
+        let server = StubServer::start(vec![(404, "no such hook")]);
+        let webhook = ChatWebhook {
+            url: server.url.clone(),
+        };
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let err = webhook
+            .post(Platform::Slack, &event)
+            .expect_err("post succeeded");
+        assert!(err.to_string().contains("Slack"));
+    }
+}
@@ -0,0 +1,306 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::Configuration;
+
+use super::Event;
+
+/// How long a lookup of a monitored host took, and how long its answer can be cached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lookup {
+    pub millis: u64,
+    /// The TTL of the host's address record, in seconds, if the name server was asked.
+    pub ttl: Option<u32>,
+}
+
+impl Lookup {
+    /// Look up a host's address, timing the lookup, and ask the
+    /// name server for the TTL of the answer if that's wanted.
+    pub fn run(host: &str, want_ttl: bool) -> (Result<String>, Self) {
+        let start = Instant::now();
+        let result = crate::current_ip(host);
+        let millis = start.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+        let ttl = match name_servers().first() {
+            Some(server) if want_ttl && result.is_ok() => query_ttl(host, *server).ok(),
+            _ => None,
+        };
+        (result, Self { millis, ttl })
+    }
+}
+
+impl std::fmt::Display for Lookup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ms", self.millis)?;
+        match self.ttl {
+            Some(ttl) => write!(f, ", TTL {ttl} s"),
+            None => f.write_str(", TTL unknown"),
+        }
+    }
+}
+
+/// Details of the monitor and its lookups that help explain a notification.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub resolver: String,
+    /// The most recent lookup of each host that the event is about.
+    pub lookups: BTreeMap<String, Lookup>,
+    pub monitor_host: String,
+    pub version: String,
+    pub config_path: String,
+}
+
+impl Diagnostics {
+    pub fn new(config: &Configuration, event: &Event) -> Self {
+        let mut hosts = vec![];
+        collect_hosts(event, &mut hosts);
+        let lookups = hosts
+            .into_iter()
+            .filter_map(|host| config.lookups.get(host).map(|l| (host.to_string(), *l)))
+            .collect();
+        let config_path = match crate::configure::config_path() {
+            Ok(path) if config.is_file_based => path.display().to_string(),
+            _ => String::from("(none)"),
+        };
+        Self {
+            resolver: resolver(),
+            lookups,
+            monitor_host: super::monitor_host(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config_path,
+        }
+    }
+
+    /// The footer lines that show the diagnostics.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "Monitor: ddns-monitor {} on {}",
+                self.version, self.monitor_host
+            ),
+            format!("Configuration: {}", self.config_path),
+            format!("Resolver: {}", self.resolver),
+        ];
+        for (host, lookup) in self.lookups.iter() {
+            lines.push(format!("Lookup of {host}: {lookup}"));
+        }
+        lines
+    }
+
+    /// The email headers that carry the diagnostics.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("X-DDNS-Monitor", self.monitor_host.clone()),
+            ("X-DDNS-Version", self.version.clone()),
+            ("X-DDNS-Config", self.config_path.clone()),
+            ("X-DDNS-Resolver", self.resolver.clone()),
+        ];
+        if !self.lookups.is_empty() {
+            let lookups: Vec<String> = self
+                .lookups
+                .iter()
+                .map(|(host, lookup)| format!("{host}={lookup}"))
+                .collect();
+            headers.push(("X-DDNS-Lookup", lookups.join("; ")));
+        }
+        headers
+    }
+}
+
+fn collect_hosts<'a>(event: &'a Event, hosts: &mut Vec<&'a str>) {
+    let mut add = |host: &'a str| {
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    };
+    if let Some(host) = &event.host {
+        add(host);
+    }
+    event.addresses.keys().for_each(|host| add(host));
+    for part in event.events.iter() {
+        collect_hosts(part, hosts);
+    }
+}
+
+/// A description of the resolver that looks up hosts.
+pub fn resolver() -> String {
+    let servers: Vec<String> = name_servers().iter().map(|s| s.ip().to_string()).collect();
+    if servers.is_empty() {
+        String::from("system resolver")
+    } else {
+        format!("system resolver (name servers {})", servers.join(", "))
+    }
+}
+
+/// The name servers that the system resolver uses, if they can be found.
+pub fn name_servers() -> Vec<SocketAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .map(|text| parse_resolv_conf(&text))
+        .unwrap_or_default()
+}
+
+fn parse_resolv_conf(text: &str) -> Vec<SocketAddr> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| {
+            // IPv6 link-local servers can have a zone, which we can't use
+            let address = rest.trim().split('%').next()?;
+            address.parse::<IpAddr>().ok()
+        })
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect()
+}
+
+/// Ask a name server for a host's address record, and return its TTL.
+///
+/// This is a separate query from the lookup, made only for diagnostics,
+/// so it's a single UDP exchange that gives up quickly.
+pub fn query_ttl(host: &str, server: SocketAddr) -> Result<u32> {
+    let local = match server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(local).wrap_err("Can't open a socket for a DNS query")?;
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .wrap_err("Can't set a timeout for a DNS query")?;
+    let id: u16 = rand::random();
+    socket
+        .send_to(&query_packet(host, id)?, server)
+        .wrap_err(format!("Can't send a DNS query to {server}"))?;
+    let mut buffer = [0u8; 1500];
+    let (len, _) = socket
+        .recv_from(&mut buffer)
+        .wrap_err(format!("No DNS answer from {server}"))?;
+    answer_ttl(&buffer[..len], id)
+}
+
+/// A recursive query for the A record of the host.
+fn query_packet(host: &str, id: u16) -> Result<Vec<u8>> {
+    let mut packet = id.to_be_bytes().to_vec();
+    // recursion desired, one question, no other records
+    packet.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(eyre!("{host} is not a DNS name"));
+        }
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+    // type A, class IN
+    packet.extend([0, 1, 0, 1]);
+    Ok(packet)
+}
+
+/// The smallest TTL of the records in the answer to a query, which includes
+/// any aliases that were followed to reach the address.
+fn answer_ttl(answer: &[u8], id: u16) -> Result<u32> {
+    let bad = || eyre!("Malformed DNS answer");
+    let header = answer.get(..12).ok_or_else(bad)?;
+    if header[..2] != id.to_be_bytes() || header[2] & 0x80 == 0 {
+        return Err(eyre!("DNS answer doesn't match the query"));
+    }
+    let rcode = header[3] & 0x0F;
+    if rcode != 0 {
+        return Err(eyre!("DNS query failed with response code {rcode}"));
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(answer, pos).ok_or_else(bad)? + 4;
+    }
+    let mut ttl = None;
+    for _ in 0..answers {
+        pos = skip_name(answer, pos).ok_or_else(bad)?;
+        let record = answer.get(pos..pos + 10).ok_or_else(bad)?;
+        let record_ttl = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+        let length = u16::from_be_bytes([record[8], record[9]]) as usize;
+        ttl = Some(ttl.map_or(record_ttl, |ttl: u32| ttl.min(record_ttl)));
+        pos += 10 + length;
+    }
+    ttl.ok_or_else(|| eyre!("DNS answer has no records"))
+}
+
+/// The position after the (possibly compressed) name at the given position.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xC0 == 0xC0 => return Some(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let text = "# comment\nnameserver 10.0.0.53\nsearch example.com\nnameserver fe80::1%eth0\n";
+        let servers = parse_resolv_conf(text);
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].to_string(), "10.0.0.53:53");
+        assert_eq!(servers[1].ip().to_string(), "fe80::1");
+    }
+
+    #[test]
+    fn test_query_ttl() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        let responder = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (len, from) = server.recv_from(&mut buffer).unwrap();
+            let query = &buffer[..len];
+            let mut answer = query.to_vec();
+            // a response with two answers: an alias and its address
+            answer[2] = 0x81;
+            answer[3] = 0x80;
+            answer[7] = 2;
+            answer.extend([0xC0, 12, 0, 5, 0, 1, 0, 0, 0x0E, 0x10, 0, 2, 0xC0, 12]);
+            answer.extend([0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+            server.send_to(&answer, from).unwrap();
+            query.to_vec()
+        });
+        let ttl = query_ttl("some.host", address).expect("TTL query failed");
+        assert_eq!(ttl, 60);
+        let query = responder.join().unwrap();
+        assert_eq!(&query[12..23], b"\x04some\x04host\x00");
+        assert!(query_packet("bad..host", 1).is_err());
+        // the query itself isn't an answer
+        let id = u16::from_be_bytes([query[0], query[1]]);
+        assert!(answer_ttl(&query, id).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Local};
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use super::{state_file, Event};
+
+/// Whether events are sent as they happen or collected into digests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigestOptions {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long to collect events before sending them,
+    /// where 0 means until the end of each check.
+    #[serde(default)]
+    pub window_secs: u64,
+}
+
+impl DigestOptions {
+    /// Whether the collected events should be sent now.
+    pub fn is_due(&self, queue: &[Event]) -> bool {
+        let Some(first) = queue.first() else {
+            return false;
+        };
+        let window = Duration::seconds(self.window_secs.try_into().unwrap_or(i64::MAX));
+        self.window_secs == 0 || Local::now() >= first.timestamp + window
+    }
+
+    pub fn interview(&self) -> Result<Self> {
+        let enabled = dialoguer::Confirm::new()
+            .with_prompt("Collect notifications into digests, rather than sending each one?")
+            .default(self.enabled)
+            .interact()
+            .wrap_err("Input error")?;
+        if !enabled {
+            return Ok(Self::default());
+        }
+        let window_secs = dialoguer::Input::new()
+            .with_prompt("Seconds to collect notifications for (0 for one digest per check)")
+            .default(self.window_secs)
+            .interact_text()
+            .wrap_err("Input error")?;
+        Ok(Self {
+            enabled,
+            window_secs,
+        })
+    }
+}
+
+/// The events collected for the next digest are kept in a file next to the
+/// configuration, so that they aren't lost if the monitor stops before sending them.
+pub fn queue_path() -> Result<PathBuf> {
+    state_file::path("digest.json")
+}
+
+/// The events stored at the given path, which are none if there's no file there.
+pub fn load_queue(path: &Path) -> Result<Vec<Event>> {
+    Ok(state_file::load(path, "Digest queue")?.unwrap_or_default())
+}
+
+pub fn save_queue(path: &Path, queue: &[Event]) -> Result<()> {
+    state_file::save_atomic(path, queue, "Digest queue")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::EventKind;
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let per_cycle = DigestOptions {
+            enabled: true,
+            window_secs: 0,
+        };
+        let hourly = DigestOptions {
+            enabled: true,
+            window_secs: 3600,
+        };
+        let mut queue = vec![];
+        assert!(!per_cycle.is_due(&queue));
+        queue.push(Event::change("some.host", "1.2.3.4", "5.6.7.8"));
+        assert!(per_cycle.is_due(&queue));
+        assert!(!hourly.is_due(&queue));
+        queue[0].timestamp -= Duration::hours(2);
+        assert!(hourly.is_due(&queue));
+    }
+
+    #[test]
+    fn test_digest_event() {
+        let digest = Event::digest(vec![
+            Event::change("a.host", "1.1.1.1", "2.2.2.2"),
+            Event::error(&eyre::eyre!("lookup failed")),
+            Event::change("b.host", "3.3.3.3", "4.4.4.4"),
+        ]);
+        assert_eq!(digest.event, EventKind::Digest);
+        assert_eq!(
+            digest.subject,
+            "DNS monitoring digest: 2 address changes, 1 error"
+        );
+        assert!(digest.body[0].ends_with("reported 3 events:"));
+        assert!(digest
+            .body
+            .iter()
+            .any(|line| line.starts_with("DNS change for b.host (at ")));
+        assert!(digest
+            .body
+            .contains(&String::from("-- The new IP address is: 2.2.2.2.")));
+        assert_eq!(digest.parts().len(), 3);
+        assert_eq!(digest.severity(), EventKind::Error);
+        let payload = serde_json::to_value(&digest).unwrap();
+        assert_eq!(payload["events"][2]["host"], "b.host");
+    }
+}
@@ -0,0 +1,1061 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local};
+use eyre::{eyre, Result, WrapErr};
+use lettre::address::Envelope;
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Certificate, Tls, TlsParameters};
+use lettre::{Address, Message, SendmailTransport, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+
+use crate::Configuration;
+
+use super::locale::fill;
+use super::oauth::OAuth2;
+use super::recipient::{Header, Recipient};
+use super::security::SecureBody;
+use super::{escape_html, percent_encode, Event};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TlsMode {
+    /// TLS from the start of the connection (sometimes called SMTPS).
+    #[default]
+    Implicit,
+    /// Upgrade to TLS with STARTTLS, failing if the server doesn't support it.
+    StartTlsRequired,
+    /// Upgrade to TLS with STARTTLS if the server supports it.
+    StartTlsOpportunistic,
+    /// Never use TLS.
+    None,
+}
+
+impl TlsMode {
+    pub const ALL: [TlsMode; 4] = [
+        TlsMode::Implicit,
+        TlsMode::StartTlsRequired,
+        TlsMode::StartTlsOpportunistic,
+        TlsMode::None,
+    ];
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            TlsMode::Implicit => 465,
+            TlsMode::StartTlsRequired | TlsMode::StartTlsOpportunistic => 587,
+            TlsMode::None => 25,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TlsMode::Implicit => "Implicit TLS (usually port 465)",
+            TlsMode::StartTlsRequired => "STARTTLS, required (usually port 587)",
+            TlsMode::StartTlsOpportunistic => "STARTTLS, if available (usually port 587)",
+            TlsMode::None => "No TLS (usually port 25)",
+        }
+    }
+}
+
+/// The SASL mechanism used to log in to the SMTP server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthMechanism {
+    /// Whichever of PLAIN and LOGIN the server offers.
+    #[default]
+    Automatic,
+    Plain,
+    Login,
+    /// An OAuth2 access token, as required by Gmail and Microsoft 365.
+    #[serde(rename = "xoauth2")]
+    XOAuth2,
+}
+
+impl AuthMechanism {
+    pub const ALL: [AuthMechanism; 4] = [
+        AuthMechanism::Automatic,
+        AuthMechanism::Plain,
+        AuthMechanism::Login,
+        AuthMechanism::XOAuth2,
+    ];
+
+    fn mechanisms(&self) -> Vec<Mechanism> {
+        match self {
+            AuthMechanism::Automatic => vec![Mechanism::Plain, Mechanism::Login],
+            AuthMechanism::Plain => vec![Mechanism::Plain],
+            AuthMechanism::Login => vec![Mechanism::Login],
+            AuthMechanism::XOAuth2 => vec![Mechanism::Xoauth2],
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            AuthMechanism::Automatic => "Automatic",
+            AuthMechanism::Plain => "PLAIN",
+            AuthMechanism::Login => "LOGIN",
+            AuthMechanism::XOAuth2 => "XOAUTH2 (OAuth2 sign-in, for Gmail and Microsoft 365)",
+        }
+    }
+}
+
+/// How messages are handed off for delivery.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Delivery {
+    /// An SMTP server, logging in with the sender's account and password.
+    #[default]
+    Smtp,
+    /// An SMTP relay that accepts mail without authentication, typically on localhost.
+    Relay,
+    /// A local sendmail-compatible program.
+    Sendmail,
+}
+
+impl Delivery {
+    pub const ALL: [Delivery; 3] = [Delivery::Smtp, Delivery::Relay, Delivery::Sendmail];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Delivery::Smtp => "SMTP server with account and password",
+            Delivery::Relay => "SMTP relay without authentication (such as a local MTA)",
+            Delivery::Sendmail => "Local sendmail program",
+        }
+    }
+}
+
+/// How to connect to the SMTP server, beyond its name.
+///
+/// These are flattened into the configuration, and all of them
+/// default to the behavior of configurations that predate them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SmtpOptions {
+    #[serde(default)]
+    pub delivery: Delivery,
+    /// The sendmail program, where empty means the system default.
+    #[serde(default)]
+    pub sendmail_command: String,
+    /// The server port, where 0 means the default port for the TLS mode.
+    #[serde(default)]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub tls_mode: TlsMode,
+    /// A PEM file of CA certificates to trust in addition to the system's.
+    #[serde(default)]
+    pub ca_bundle_path: String,
+    #[serde(default)]
+    pub auth_mechanism: AuthMechanism,
+    /// The OAuth2 client and refresh token, for XOAUTH2 authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth2: Option<OAuth2>,
+}
+
+pub const DEFAULT_SENDMAIL_COMMAND: &str = "/usr/sbin/sendmail";
+
+impl SmtpOptions {
+    pub fn port(&self) -> u16 {
+        if self.smtp_port == 0 {
+            self.tls_mode.default_port()
+        } else {
+            self.smtp_port
+        }
+    }
+
+    pub fn sendmail_command(&self) -> &str {
+        if self.sendmail_command.is_empty() {
+            DEFAULT_SENDMAIL_COMMAND
+        } else {
+            &self.sendmail_command
+        }
+    }
+
+    pub fn interview_delivery(&mut self) -> Result<()> {
+        let deliveries: Vec<&str> = Delivery::ALL.iter().map(|d| d.description()).collect();
+        let delivery = dialoguer::Select::new()
+            .with_prompt("How should email be sent")
+            .items(&deliveries)
+            .default(
+                Delivery::ALL
+                    .iter()
+                    .position(|d| *d == self.delivery)
+                    .unwrap_or(0),
+            )
+            .interact()
+            .wrap_err("Input error")?;
+        self.delivery = Delivery::ALL[delivery];
+        if self.delivery == Delivery::Sendmail {
+            self.sendmail_command = dialoguer::Input::<String>::new()
+                .with_prompt("Sendmail program")
+                .with_initial_text(self.sendmail_command())
+                .allow_empty(false)
+                .validate_with(|path: &String| -> std::result::Result<(), String> {
+                    if std::path::Path::new(path.trim()).is_file() {
+                        Ok(())
+                    } else {
+                        Err(format!("{path} is not a file"))
+                    }
+                })
+                .interact_text()
+                .wrap_err("Input error")?
+                .trim()
+                .to_string();
+        }
+        Ok(())
+    }
+
+    /// Ask how to connect to an SMTP server (for either SMTP or relay delivery).
+    pub fn interview_connection(&mut self) -> Result<()> {
+        let modes: Vec<&str> = TlsMode::ALL.iter().map(|m| m.description()).collect();
+        let mode = dialoguer::Select::new()
+            .with_prompt("Connection security")
+            .items(&modes)
+            .default(
+                TlsMode::ALL
+                    .iter()
+                    .position(|m| *m == self.tls_mode)
+                    .unwrap_or(0),
+            )
+            .interact()
+            .wrap_err("Input error")?;
+        self.tls_mode = TlsMode::ALL[mode];
+        self.smtp_port = dialoguer::Input::new()
+            .with_prompt("SMTP server port")
+            .default(self.port())
+            .interact_text()
+            .wrap_err("Input error")?;
+        if self.tls_mode != TlsMode::None {
+            self.ca_bundle_path = dialoguer::Input::<String>::new()
+                .with_prompt("Extra CA certificates file (leave empty for none)")
+                .with_initial_text(&self.ca_bundle_path)
+                .allow_empty(true)
+                .validate_with(|path: &String| -> std::result::Result<(), String> {
+                    if path.trim().is_empty() || std::path::Path::new(path.trim()).is_file() {
+                        Ok(())
+                    } else {
+                        Err(format!("{path} is not a file"))
+                    }
+                })
+                .interact_text()
+                .wrap_err("Input error")?
+                .trim()
+                .to_string();
+        }
+        if self.delivery == Delivery::Smtp {
+            let mechanisms: Vec<&str> =
+                AuthMechanism::ALL.iter().map(|m| m.description()).collect();
+            let mechanism = dialoguer::Select::new()
+                .with_prompt("Authentication mechanism")
+                .items(&mechanisms)
+                .default(
+                    AuthMechanism::ALL
+                        .iter()
+                        .position(|m| *m == self.auth_mechanism)
+                        .unwrap_or(0),
+                )
+                .interact()
+                .wrap_err("Input error")?;
+            self.auth_mechanism = AuthMechanism::ALL[mechanism];
+        }
+        Ok(())
+    }
+}
+
+/// A way of sending messages.
+pub enum Mailer {
+    Smtp(SmtpTransport),
+    Sendmail(SendmailTransport),
+}
+
+impl Mailer {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let server = config.from_server.as_str();
+        match config.smtp.delivery {
+            Delivery::Smtp => {
+                let secret = match (&config.smtp.auth_mechanism, &config.smtp.oauth2) {
+                    (AuthMechanism::XOAuth2, Some(oauth2)) => oauth2.access_token()?,
+                    (AuthMechanism::XOAuth2, None) => {
+                        return Err(eyre!(
+                            "OAuth2 has not been set up; run the configure command"
+                        ))
+                    }
+                    _ => config.password()?,
+                };
+                let creds = Credentials::new(config.from_address.clone(), secret);
+                Ok(Mailer::Smtp(smtp_transport(
+                    server,
+                    &config.smtp,
+                    Some(creds),
+                )?))
+            }
+            Delivery::Relay => Ok(Mailer::Smtp(smtp_transport(server, &config.smtp, None)?)),
+            Delivery::Sendmail => Ok(Mailer::Sendmail(SendmailTransport::new_with_command(
+                config.smtp.sendmail_command(),
+            ))),
+        }
+    }
+
+    pub fn send(&self, email: &Message) -> Result<()> {
+        match self {
+            Mailer::Smtp(mailer) => {
+                mailer.send(email)?;
+            }
+            Mailer::Sendmail(mailer) => {
+                mailer.send(email)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A transport to the given server that authenticates with the given credentials, if any.
+pub fn smtp_transport(
+    server: &str,
+    options: &SmtpOptions,
+    credentials: Option<Credentials>,
+) -> Result<SmtpTransport> {
+    let tls = match options.tls_mode {
+        TlsMode::None => Tls::None,
+        mode => {
+            let mut builder = TlsParameters::builder(server.to_string());
+            if !options.ca_bundle_path.is_empty() {
+                for certificate in read_ca_bundle(&options.ca_bundle_path)? {
+                    builder = builder.add_root_certificate(certificate);
+                }
+            }
+            let parameters = builder
+                .build()
+                .wrap_err(format!("Couldn't set up TLS for {server}"))?;
+            match mode {
+                TlsMode::Implicit => Tls::Wrapper(parameters),
+                TlsMode::StartTlsRequired => Tls::Required(parameters),
+                _ => Tls::Opportunistic(parameters),
+            }
+        }
+    };
+    let mut builder = SmtpTransport::builder_dangerous(server)
+        .port(options.port())
+        .tls(tls);
+    if let Some(credentials) = credentials {
+        builder = builder
+            .credentials(credentials)
+            .authentication(options.auth_mechanism.mechanisms());
+    }
+    Ok(builder.build())
+}
+
+/// The certificates in a PEM bundle.
+fn read_ca_bundle(path: &str) -> Result<Vec<Certificate>> {
+    let bundle = std::fs::read_to_string(path).wrap_err(format!("Can't read CA bundle {path}"))?;
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certificates = vec![];
+    for block in bundle
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+    {
+        let certificate = Certificate::from_pem(block.trim().as_bytes())
+            .wrap_err(format!("Invalid certificate in CA bundle {path}"))?;
+        certificates.push(certificate);
+    }
+    if certificates.is_empty() {
+        return Err(eyre!("No certificates found in CA bundle {path}"));
+    }
+    Ok(certificates)
+}
+
+/// A file attached to an email.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: String,
+}
+
+/// Email the event, with its text as both plain text and HTML.
+pub fn send_notification(
+    config: &Configuration,
+    event: &Event,
+    recipients: &[Recipient],
+) -> Result<()> {
+    let mut builder = Message::builder();
+    let from = config.from_address.as_str();
+    let name = Some(config.from_name.clone()).filter(|name| !name.is_empty());
+    let from_address: Address = from
+        .parse()
+        .wrap_err(format!("Illegal from address: {from}"))?;
+    builder = builder.from(Mailbox::new(name, from_address.clone()));
+    if !config.reply_to.is_empty() {
+        let reply_to = config.reply_to.as_str();
+        builder = builder.reply_to(
+            reply_to
+                .parse()
+                .wrap_err(format!("Illegal reply-to address: {reply_to}"))?,
+        );
+    }
+    for recipient in recipients {
+        let mailbox = recipient.mailbox()?;
+        builder = match recipient.header {
+            Header::To => builder.to(mailbox),
+            Header::Cc => builder.cc(mailbox),
+            Header::Bcc => builder.bcc(mailbox),
+        };
+    }
+    // the headers of an encrypted email mustn't give away what it's about
+    let encrypting = config.security.is_encrypting();
+    if encrypting {
+        builder = builder.subject(event.locale.catalog().encrypted_subject);
+    } else {
+        builder = builder.subject(event.subject.clone());
+    }
+    let thread = event
+        .host
+        .as_ref()
+        .and_then(|host| Some((host, config.threads.get(host)?)))
+        .filter(|_| !encrypting);
+    if let Some((host, root)) = thread {
+        // mail clients group the emails about a host under the first of them
+        if is_thread_started(config, host, event) {
+            builder = builder.in_reply_to(root.clone()).references(root.clone());
+        } else {
+            builder = builder.message_id(Some(root.clone()));
+        }
+    }
+    let mut text = event.body.join("\n");
+    if encrypting {
+        text = format!("{}\n\n{text}", event.subject);
+    }
+    if let Some(diagnostics) = &event.diagnostics {
+        // the standard separator of a signature, which mail programs may de-emphasize
+        text.push_str("\n\n-- \n");
+        text.push_str(&diagnostics.lines().join("\n"));
+    }
+    let mut content =
+        MultiPart::alternative_plain_html(text, html_body(event, &config.address_lookup_url));
+    if !event.attachments.is_empty() {
+        content = MultiPart::mixed().multipart(content);
+        for attachment in event.attachments.iter() {
+            let content_type = ContentType::parse(&attachment.content_type)
+                .wrap_err(format!("Illegal content type: {}", attachment.content_type))?;
+            content = content.singlepart(
+                lettre::message::Attachment::new(attachment.filename.clone())
+                    .body(attachment.content.clone(), content_type),
+            );
+        }
+    }
+    let mut headers = vec![];
+    if let Some(diagnostics) = event.diagnostics.as_ref().filter(|_| !encrypting) {
+        headers.push(("X-DDNS-Event", event.event.to_string()));
+        if let Some(host) = &event.host {
+            headers.push(("X-DDNS-Host", host.clone()));
+        }
+        headers.extend(diagnostics.headers());
+    }
+    let mailer = Mailer::new(config)?;
+    for readers in copies(config, recipients) {
+        let addresses = readers
+            .iter()
+            .map(|recipient| Ok(recipient.mailbox()?.email))
+            .collect::<Result<Vec<Address>>>()?;
+        let envelope = Envelope::new(Some(from_address.clone()), addresses)
+            .wrap_err("E-mail envelope creation failed.")?;
+        let builder = builder.clone().envelope(envelope);
+        let email = match config.security.protect(content.clone(), &readers)? {
+            SecureBody::Multi(part) => builder.multipart(part),
+            SecureBody::Single(part) => builder.singlepart(part),
+        };
+        let mut email = email.wrap_err("E-mail message creation failed.")?;
+        for (name, value) in headers.iter() {
+            let name = HeaderName::new_from_ascii_str(name);
+            email
+                .headers_mut()
+                .insert_raw(HeaderValue::new(name, value.clone()));
+        }
+        mailer.send(&email).wrap_err("E-mail send failed")?;
+        if let Some((host, _)) = thread {
+            start_thread(host, event);
+        }
+    }
+    Ok(())
+}
+
+/// The hosts whose thread root was sent as a Message-ID since the configuration was
+/// last saved, with the time of the event that was sent with it.
+static STARTED_THREADS: Mutex<BTreeMap<String, DateTime<Local>>> = Mutex::new(BTreeMap::new());
+
+/// Whether an email about the event should refer to the host's thread root, rather than
+/// carry it as its Message-ID. Other recipients of the event that started the thread,
+/// and retries of it, get the root as their Message-ID too.
+fn is_thread_started(config: &Configuration, host: &str, event: &Event) -> bool {
+    let started = STARTED_THREADS
+        .lock()
+        .expect("Started threads lock poisoned");
+    match started.get(host) {
+        Some(timestamp) => *timestamp != event.timestamp,
+        None => config.started_threads.contains(host),
+    }
+}
+
+/// Remember that the event's email started the host's thread.
+fn start_thread(host: &str, event: &Event) {
+    let mut started = STARTED_THREADS
+        .lock()
+        .expect("Started threads lock poisoned");
+    started.entry(host.to_string()).or_insert(event.timestamp);
+}
+
+/// The hosts whose threads have been started, to be saved in the configuration.
+pub fn started_threads() -> Vec<String> {
+    let started = STARTED_THREADS
+        .lock()
+        .expect("Started threads lock poisoned");
+    started.keys().cloned().collect()
+}
+
+/// The recipients of each copy of an email, who are all the recipients unless it's
+/// encrypted. An encrypted email names the keys it's encrypted for, so then each
+/// Bcc recipient gets a copy of their own, which keeps them hidden from the others.
+fn copies(config: &Configuration, recipients: &[Recipient]) -> Vec<Vec<Recipient>> {
+    if !config.security.is_encrypting() {
+        return vec![recipients.to_vec()];
+    }
+    let (hidden, shown): (Vec<Recipient>, Vec<Recipient>) = recipients
+        .iter()
+        .cloned()
+        .partition(|recipient| recipient.header == Header::Bcc);
+    let mut copies = vec![];
+    if !shown.is_empty() {
+        copies.push(shown);
+    }
+    copies.extend(hidden.into_iter().map(|recipient| vec![recipient]));
+    copies
+}
+
+/// A new Message-ID for the thread of emails about a host.
+///
+/// The first email about the host is sent with this ID, and the later ones refer to it.
+pub fn thread_root(host: &str, from_address: &str) -> String {
+    let domain = match from_address.rsplit_once('@') {
+        Some((_, domain)) if !domain.is_empty() => domain,
+        _ => "localhost",
+    };
+    let nonce: u64 = rand::random();
+    format!("<ddns-monitor.{nonce:016x}.{host}@{domain}>")
+}
+
+/// The HTML version of an event: its text, followed by a table
+/// of the addresses it's about, with any new address highlighted.
+fn html_body(event: &Event, lookup_url: &str) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<body style=\"font-family: sans-serif; font-size: 15px;\">\n",
+    );
+    html.push_str(&format!(
+        "<h2 style=\"font-size: 18px;\">{}</h2>\n",
+        escape_html(&event.subject)
+    ));
+    let mut in_list = false;
+    for line in event.body.iter() {
+        // the built-in text marks details with a leading "-- "
+        if let Some(item) = line.strip_prefix("-- ") {
+            if !in_list {
+                html.push_str("<ul>\n");
+                in_list = true;
+            }
+            html.push_str(&format!("<li>{}</li>\n", escape_html(item)));
+        } else {
+            if in_list {
+                html.push_str("</ul>\n");
+                in_list = false;
+            }
+            html.push_str(&format!("<p>{}</p>\n", escape_html(line)));
+        }
+    }
+    if in_list {
+        html.push_str("</ul>\n");
+    }
+    let catalog = event.locale.catalog();
+    let mut rows = vec![];
+    if let Some(host) = &event.host {
+        rows.push((catalog.host_label, escape_html(host), false));
+    }
+    if let Some(old_address) = &event.old_address {
+        rows.push((
+            catalog.old_address_label,
+            address_html(old_address, lookup_url),
+            false,
+        ));
+    }
+    if let Some(new_address) = &event.new_address {
+        rows.push((
+            catalog.new_address_label,
+            address_html(new_address, lookup_url),
+            true,
+        ));
+    }
+    if !rows.is_empty() || !event.addresses.is_empty() {
+        html.push_str(
+            "<table style=\"border-collapse: collapse; margin: 1em 0;\" cellpadding=\"6\">\n",
+        );
+        for (label, value, changed) in rows {
+            html.push_str(&table_row(&escape_html(label), &value, changed));
+        }
+        for (host, address) in event.addresses.iter() {
+            html.push_str(&table_row(
+                &escape_html(host),
+                &address_html(address, lookup_url),
+                false,
+            ));
+        }
+        html.push_str("</table>\n");
+    }
+    let footer = fill(
+        catalog.footer,
+        &[
+            ("monitor", &event.monitor_host),
+            ("time", &event.locale.format_time(event.timestamp)),
+        ],
+    );
+    html.push_str(&format!(
+        "<p style=\"color: #666666; font-size: 12px;\">{}</p>\n",
+        escape_html(&footer)
+    ));
+    if let Some(diagnostics) = &event.diagnostics {
+        let lines: Vec<String> = diagnostics.lines().iter().map(|l| escape_html(l)).collect();
+        html.push_str(&format!(
+            "<p style=\"color: #666666; font-size: 11px; font-family: monospace;\">{}</p>\n",
+            lines.join("<br>\n")
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn table_row(label: &str, value: &str, changed: bool) -> String {
+    const CELL: &str = "border: 1px solid #dddddd; text-align: left;";
+    const CHANGED: &str = " background-color: #fff3cd; font-weight: bold;";
+    let highlight = if changed { CHANGED } else { "" };
+    format!(
+        "<tr><th style=\"{CELL}\">{label}</th><td style=\"{CELL}{highlight}\">{value}</td></tr>\n"
+    )
+}
+
+/// An address, linked to the lookup URL if there is one.
+fn address_html(address: &str, lookup_url: &str) -> String {
+    if lookup_url.is_empty() {
+        escape_html(address)
+    } else {
+        let url = lookup_url.replace("{address}", &percent_encode(address));
+        format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(&url),
+            escape_html(address)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use super::super::diagnostics::{Diagnostics, Lookup};
+    use super::super::oauth::OAuth2Flow;
+    use super::super::security::tests::{smime_decrypt, smime_options};
+    use super::super::stub::{SmtpStub, StubServer};
+    use super::*;
+
+    #[test]
+    fn test_default_options_match_relay() {
+        let options: SmtpOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options.tls_mode, TlsMode::Implicit);
+        assert_eq!(options.port(), 465);
+        assert_eq!(options.auth_mechanism, AuthMechanism::Automatic);
+        let options = SmtpOptions {
+            tls_mode: TlsMode::StartTlsRequired,
+            ..Default::default()
+        };
+        assert_eq!(options.port(), 587);
+    }
+
+    #[test]
+    fn test_bad_ca_bundle() {
+        let path = std::env::temp_dir().join(format!("ddns-ca-test-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate").unwrap();
+        let options = SmtpOptions {
+            tls_mode: TlsMode::StartTlsRequired,
+            ca_bundle_path: path.display().to_string(),
+            ..Default::default()
+        };
+        let creds = Credentials::new(String::from("user"), String::from("password"));
+        let result = smtp_transport("smtp.example.com", &options, Some(creds));
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Ok(_) => panic!("Invalid CA bundle was accepted"),
+            Err(err) => assert!(err.to_string().contains("No certificates found")),
+        }
+    }
+
+    fn test_config(delivery: Delivery, port: u16) -> Configuration {
+        let mut config = Configuration::default();
+        config.from_server = String::from("127.0.0.1");
+        config.from_address = String::from("monitor@example.com");
+        config.recipients = vec![Recipient::new("admin@example.com")];
+        config.smtp = SmtpOptions {
+            delivery,
+            smtp_port: port,
+            tls_mode: TlsMode::None,
+            ..Default::default()
+        };
+        config
+    }
+
+    fn test_event() -> Event {
+        let mut event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        event.subject = String::from("Test");
+        event.body = vec![String::from("Testing")];
+        event
+    }
+
+    #[test]
+    fn test_html_body() {
+        let event = Event::change("some.host", "1.2.3.4", "2001:db8::1");
+        let html = html_body(&event, "https://ipinfo.io/{address}");
+        assert!(html.contains("<h2 style=\"font-size: 18px;\">DNS change for some.host</h2>"));
+        assert!(html.contains("<p>The IP address of some.host has changed.</p>\n<ul>\n<li>"));
+        assert!(html.contains(
+            "<td style=\"border: 1px solid #dddddd; text-align: left;\"><a href=\"https://ipinfo.io/1.2.3.4\">1.2.3.4</a></td>"
+        ));
+        assert!(html.contains(
+            "background-color: #fff3cd; font-weight: bold;\"><a href=\"https://ipinfo.io/2001%3Adb8%3A%3A1\">2001:db8::1</a>"
+        ));
+        let html = html_body(&event, "");
+        assert!(html.contains("font-weight: bold;\">2001:db8::1</td>"));
+    }
+
+    #[test]
+    fn test_authenticated_plaintext() {
+        let server = SmtpStub::start();
+        let options = SmtpOptions {
+            smtp_port: server.port,
+            tls_mode: TlsMode::None,
+            auth_mechanism: AuthMechanism::Plain,
+            ..Default::default()
+        };
+        let creds = Credentials::new(String::from("user"), String::from("password"));
+        let mailer = smtp_transport("127.0.0.1", &options, Some(creds)).unwrap();
+        let email = Message::builder()
+            .from("monitor@example.com".parse().unwrap())
+            .to("admin@example.com".parse().unwrap())
+            .subject("Test")
+            .body(String::from("Testing"))
+            .unwrap();
+        mailer.send(&email).expect("Send to stub server failed");
+        let transcript = server.transcript();
+        assert!(transcript.iter().any(|line| line.starts_with("AUTH PLAIN")));
+        assert!(transcript.contains(&String::from("RCPT TO:<admin@example.com>")));
+        assert!(transcript.contains(&String::from("Testing")));
+    }
+
+    #[test]
+    fn test_relay_delivery() {
+        let server = SmtpStub::start();
+        let config = test_config(Delivery::Relay, server.port);
+        send_notification(&config, &test_event(), &config.recipients)
+            .expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        assert!(!transcript.iter().any(|line| line.starts_with("AUTH")));
+        assert!(transcript.contains(&String::from("MAIL FROM:<monitor@example.com>")));
+        assert!(transcript.contains(&String::from("Subject: Test")));
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("Content-Type: multipart/alternative")));
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("Content-Type: text/html")));
+        // the monitor's own headers come with diagnostics
+        assert!(!transcript.iter().any(|line| line.starts_with("X-DDNS-")));
+    }
+
+    #[test]
+    fn test_names_and_headers() {
+        let server = SmtpStub::start();
+        let mut config = test_config(Delivery::Relay, server.port);
+        config.from_name = String::from("DNS Monitor");
+        config.reply_to = String::from("Help Desk <help@example.com>");
+        let mut cc = Recipient::new("Net Ops <net@example.com>");
+        cc.header = Header::Cc;
+        let mut bcc = Recipient::new("boss@example.com");
+        bcc.header = Header::Bcc;
+        config.recipients.extend([cc, bcc]);
+        send_notification(&config, &test_event(), &config.recipients)
+            .expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        for rcpt in ["admin", "net", "boss"] {
+            assert!(transcript.contains(&format!("RCPT TO:<{rcpt}@example.com>")));
+        }
+        assert!(transcript.contains(&String::from("From: \"DNS Monitor\" <monitor@example.com>")));
+        assert!(transcript.contains(&String::from("Reply-To: \"Help Desk\" <help@example.com>")));
+        assert!(transcript.contains(&String::from("To: admin@example.com")));
+        assert!(transcript.contains(&String::from("Cc: \"Net Ops\" <net@example.com>")));
+        assert!(!transcript
+            .iter()
+            .any(|line| line.contains("boss@") && !line.starts_with("RCPT TO")));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let server = SmtpStub::start();
+        let mut config = test_config(Delivery::Relay, server.port);
+        let lookup = Lookup {
+            millis: 12,
+            ttl: Some(300),
+        };
+        config.lookups.insert(String::from("some.host"), lookup);
+        config.lookups.insert(String::from("other.host"), lookup);
+        let mut event = test_event();
+        let diagnostics = Diagnostics::new(&config, &event);
+        assert_eq!(diagnostics.lookups.len(), 1);
+        assert_eq!(diagnostics.version, env!("CARGO_PKG_VERSION"));
+        event.diagnostics = Some(Box::new(diagnostics));
+        send_notification(&config, &event, &config.recipients).expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        for line in [
+            "X-DDNS-Event: change",
+            "X-DDNS-Host: some.host",
+            "X-DDNS-Lookup: some.host=12 ms, TTL 300 s",
+            "-- ",
+            "Lookup of some.host: 12 ms, TTL 300 s",
+        ] {
+            assert!(transcript.contains(&String::from(line)), "missing {line}");
+        }
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("X-DDNS-Resolver: system resolver")));
+    }
+
+    #[test]
+    fn test_attachments() {
+        let server = SmtpStub::start();
+        let config = test_config(Delivery::Relay, server.port);
+        let mut event = test_event();
+        event.attachments = vec![Attachment {
+            filename: String::from("report.csv"),
+            content_type: String::from("text/csv"),
+            content: String::from("timestamp,event\r\n"),
+        }];
+        send_notification(&config, &event, &config.recipients).expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("Content-Type: multipart/mixed")));
+        assert!(transcript
+            .iter()
+            .any(|line| line.starts_with("Content-Type: multipart/alternative")));
+        assert!(transcript.contains(&String::from(
+            "Content-Disposition: attachment; filename=\"report.csv\""
+        )));
+    }
+
+    #[test]
+    fn test_thread_references() {
+        let root = thread_root("threaded.host", "monitor@example.com");
+        assert!(root.starts_with("<ddns-monitor."));
+        assert!(root.ends_with(".threaded.host@example.com>"));
+        assert_ne!(root, thread_root("threaded.host", "monitor@example.com"));
+        let send = |event: &Event| {
+            let server = SmtpStub::start();
+            let mut config = test_config(Delivery::Relay, server.port);
+            config
+                .threads
+                .insert(String::from("threaded.host"), root.clone());
+            send_notification(&config, event, &config.recipients)
+                .expect("Send to stub relay failed");
+            server.transcript()
+        };
+        let first = Event::change("threaded.host", "1.2.3.4", "5.6.7.8");
+        // the first email starts the thread, and so does a retry of it
+        for _ in 0..2 {
+            let transcript = send(&first);
+            assert!(transcript.contains(&format!("Message-ID: {root}")));
+            assert!(!transcript
+                .iter()
+                .any(|line| line.starts_with("In-Reply-To:") || line.starts_with("References:")));
+        }
+        assert!(started_threads().contains(&String::from("threaded.host")));
+        let mut later = Event::change("threaded.host", "5.6.7.8", "1.2.3.4");
+        later.timestamp = first.timestamp + chrono::Duration::seconds(1);
+        let transcript = send(&later);
+        assert!(!transcript.contains(&format!("Message-ID: {root}")));
+        assert!(transcript.contains(&format!("In-Reply-To: {root}")));
+        assert!(transcript.contains(&format!("References: {root}")));
+    }
+
+    #[test]
+    fn test_encrypted_delivery() {
+        let Some((options, dir)) = smime_options("smime-email") else {
+            eprintln!("Skipping encryption test: openssl isn't installed");
+            return;
+        };
+        let server = SmtpStub::start();
+        let mut config = test_config(Delivery::Relay, server.port);
+        config.security = options;
+        config.threads.insert(
+            String::from("some.host"),
+            thread_root("some.host", &config.from_address),
+        );
+        let mut event = test_event();
+        event.diagnostics = Some(Box::new(Diagnostics::new(&config, &event)));
+        send_notification(&config, &event, &config.recipients).expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        assert!(transcript.contains(&String::from(
+            "Subject: Dynamic DNS monitoring notification"
+        )));
+        assert!(!transcript.iter().any(|line| line.starts_with("X-DDNS-")));
+        assert!(transcript
+            .iter()
+            .any(|line| line.contains("application/pkcs7-mime; smime-type=enveloped-data")));
+        assert!(!transcript.iter().any(|line| line.contains("some.host")));
+        config.security.public_keys.clear();
+        let err = send_notification(&config, &event, &config.recipients).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(format!("{err:#}").contains("No public key for admin@example.com"));
+    }
+
+    /// The RCPT TO addresses and the data lines of each message in a transcript.
+    fn messages(transcript: &[String]) -> Vec<(Vec<&str>, Vec<&str>)> {
+        let mut messages = vec![];
+        for session in transcript
+            .split(|line| line.starts_with("MAIL FROM"))
+            .skip(1)
+        {
+            let rcpts = session
+                .iter()
+                .filter_map(|line| line.strip_prefix("RCPT TO:"))
+                .collect();
+            let data = session
+                .iter()
+                .skip_while(|line| !line.starts_with("DATA"))
+                .skip(1)
+                .take_while(|line| *line != ".")
+                .map(String::as_str)
+                .collect();
+            messages.push((rcpts, data));
+        }
+        messages
+    }
+
+    #[test]
+    fn test_encrypted_bcc_copies() {
+        let Some((options, dir)) = smime_options("smime-admin") else {
+            eprintln!("Skipping encryption test: openssl isn't installed");
+            return;
+        };
+        let (boss_options, boss_dir) = smime_options("smime-boss").unwrap();
+        let server = SmtpStub::start_for(2);
+        let mut config = test_config(Delivery::Relay, server.port);
+        config.security = options;
+        config.security.public_keys.insert(
+            String::from("boss@example.com"),
+            std::fs::read_to_string(&boss_options.certificate).unwrap(),
+        );
+        let mut boss = Recipient::new("boss@example.com");
+        boss.header = Header::Bcc;
+        config.recipients.push(boss);
+        send_notification(&config, &test_event(), &config.recipients)
+            .expect("Send to stub relay failed");
+        let transcript = server.transcript();
+        let messages = messages(&transcript);
+        assert_eq!(messages.len(), 2);
+        let readers = [&config.security, &boss_options];
+        for (i, (rcpts, data)) in messages.iter().enumerate() {
+            assert_eq!(rcpts, &[["<admin@example.com>", "<boss@example.com>"][i]]);
+            assert!(data.contains(&"To: admin@example.com"));
+            assert!(!data.iter().any(|line| line.contains("boss@")));
+            let body = data.iter().skip_while(|line| !line.is_empty());
+            let encrypted = STANDARD.decode(body.copied().collect::<String>()).unwrap();
+            assert!(smime_decrypt(readers[i], &encrypted).is_some());
+            assert!(smime_decrypt(readers[1 - i], &encrypted).is_none());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&boss_dir).unwrap();
+    }
+
+    #[test]
+    fn test_xoauth2_delivery() {
+        let tokens = StubServer::start(vec![(
+            200,
+            r#"{"access_token":"smtp-access","expires_in":3600}"#,
+        )]);
+        let server = SmtpStub::start();
+        let mut config = test_config(Delivery::Smtp, server.port);
+        config.smtp.auth_mechanism = AuthMechanism::XOAuth2;
+        let mut oauth2 = OAuth2::new(
+            OAuth2Flow::Loopback,
+            &format!("{}/token", tokens.url),
+            "smtp",
+            "client",
+        );
+        oauth2.set_refresh_token("smtp-refresh").unwrap();
+        config.smtp.oauth2 = Some(oauth2);
+        send_notification(&config, &test_event(), &config.recipients)
+            .expect("Send with XOAUTH2 failed");
+        assert!(tokens
+            .next_request()
+            .body
+            .contains("refresh_token=smtp-refresh"));
+        let response =
+            STANDARD.encode("user=monitor@example.com\x01auth=Bearer smtp-access\x01\x01");
+        let transcript = server.transcript();
+        assert!(transcript.contains(&format!("AUTH XOAUTH2 {response}")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sendmail_delivery() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("ddns-sendmail-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("message");
+        let program = dir.join("sendmail");
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" > {0}\ncat >> {0}\n",
+            output.display()
+        );
+        std::fs::write(&program, script).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = test_config(Delivery::Sendmail, 0);
+        config.smtp.sendmail_command = program.display().to_string();
+        let result = send_notification(&config, &test_event(), &config.recipients);
+        let message = std::fs::read_to_string(&output);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.expect("Send through sendmail failed");
+        let message = message.expect("sendmail was not run");
+        assert!(message.starts_with("-i -f monitor@example.com -- admin@example.com\n"));
+        assert!(message.contains("Subject: Test"));
+    }
+}
@@ -0,0 +1,445 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Local};
+use eyre::{Result, WrapErr};
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+
+use super::locale::fill;
+use super::recipient::{interview_list, Recipient};
+use super::{state_file, Channel, Event};
+
+/// A rule for notifying more people of a problem that isn't being resolved.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Escalation {
+    /// A unique name for the rule, which the escalation state is kept under,
+    /// so that rules can be added, removed or reordered without confusing it.
+    #[serde(default)]
+    pub name: String,
+    /// Escalate once this many checks in a row have failed (0 for never).
+    #[serde(default)]
+    pub failed_checks: u32,
+    /// Escalate address changes that haven't been acknowledged
+    /// after this many minutes (0 for never).
+    #[serde(default)]
+    pub unacknowledged_minutes: u64,
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+impl Escalation {
+    pub fn unacknowledged_after(&self) -> Option<Duration> {
+        match self.unacknowledged_minutes {
+            0 => None,
+            minutes => Some(Duration::minutes(minutes.try_into().unwrap_or(i64::MAX))),
+        }
+    }
+
+    /// Give a name to each rule that doesn't have one (from configurations
+    /// older than rule names), so its escalation state has something to be kept under.
+    pub fn name_unnamed(rules: &mut [Escalation]) {
+        for i in 0..rules.len() {
+            if rules[i].name.is_empty() {
+                rules[i].name = Self::unused_name(rules);
+            }
+        }
+    }
+
+    fn unused_name(rules: &[Escalation]) -> String {
+        (1..)
+            .map(|n| format!("escalation {n}"))
+            .find(|name| !rules.iter().any(|rule| rule.name.eq(name)))
+            .unwrap()
+    }
+
+    /// Interview for a new rule or changes to an existing one,
+    /// whose name must differ from those of the other rules.
+    pub fn interview(existing: Option<&Escalation>, others: &[Escalation]) -> Result<Self> {
+        let mut rule = existing.cloned().unwrap_or_default();
+        if rule.name.is_empty() {
+            rule.name = Self::unused_name(others);
+        }
+        loop {
+            let name: String = dialoguer::Input::new()
+                .with_prompt("Name of this escalation")
+                .default(rule.name.clone())
+                .interact_text()
+                .wrap_err("Input error")?;
+            let name = name.trim();
+            if name.is_empty() {
+                eprintln!("An escalation needs a name");
+            } else if others.iter().any(|other| other.name.eq(name)) {
+                eprintln!("There is already an escalation named \"{name}\"; try again");
+            } else {
+                rule.name = name.to_string();
+                break;
+            }
+        }
+        loop {
+            rule.failed_checks = dialoguer::Input::new()
+                .with_prompt("Escalate after how many failed checks in a row (0 for never)")
+                .default(rule.failed_checks)
+                .interact_text()
+                .wrap_err("Input error")?;
+            rule.unacknowledged_minutes = dialoguer::Input::new()
+                .with_prompt(
+                    "Escalate address changes not acknowledged within how many minutes (0 for never)",
+                )
+                .default(rule.unacknowledged_minutes)
+                .interact_text()
+                .wrap_err("Input error")?;
+            if rule.failed_checks > 0 || rule.unacknowledged_minutes > 0 {
+                break;
+            }
+            eprintln!("An escalation needs failed checks, unacknowledged changes, or both");
+        }
+        loop {
+            let current: Vec<String> = rule.recipients.iter().map(|r| r.address.clone()).collect();
+            let addresses = interview_list(
+                "Email addresses to escalate to (separated by commas)",
+                &current,
+            )?;
+            if let Some(bad) = addresses.iter().find(|a| a.parse::<Mailbox>().is_err()) {
+                eprintln!("{bad} is not a valid email address; try again");
+                continue;
+            }
+            rule.recipients = addresses
+                .iter()
+                .map(|address| {
+                    rule.recipients
+                        .iter()
+                        .find(|r| r.address.eq(address))
+                        .cloned()
+                        .unwrap_or_else(|| Recipient::new(address))
+                })
+                .collect();
+            crate::configure::interview_channel_list(
+                &mut rule.channels,
+                "Channels to escalate to",
+            )?;
+            if !rule.recipients.is_empty() || !rule.channels.is_empty() {
+                break;
+            }
+            eprintln!("An escalation needs at least one email address or channel");
+        }
+        Ok(rule)
+    }
+}
+
+impl Display for Escalation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut targets: Vec<String> = self.recipients.iter().map(|r| r.address.clone()).collect();
+        targets.extend(self.channels.iter().map(|c| format!("the {c}")));
+        let mut triggers = vec![];
+        if self.failed_checks > 0 {
+            triggers.push(format!("{} failed checks", self.failed_checks));
+        }
+        if self.unacknowledged_minutes > 0 {
+            triggers.push(format!(
+                "changes unacknowledged for {} minutes",
+                self.unacknowledged_minutes
+            ));
+        }
+        write!(
+            f,
+            "escalation \"{}\" to {} after {}",
+            self.name,
+            targets.join(", "),
+            triggers.join(" or ")
+        )
+    }
+}
+
+/// An address change that nobody has acknowledged yet.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingChange {
+    pub event: Event,
+    /// The names of the escalations that this change has been escalated to.
+    #[serde(default)]
+    pub escalated: Vec<String>,
+}
+
+/// The problems that escalations are watching, kept in a file next to
+/// the configuration so that they survive restarts.
+///
+/// The file is locked from loading until the state is dropped.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EscalationState {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    lock: Option<state_file::Lock>,
+    /// The number of checks in a row that have failed.
+    pub failed_checks: u32,
+    pub failing_since: Option<DateTime<Local>>,
+    /// The names of the escalations that the current failures have been escalated to.
+    pub escalated_failures: Vec<String>,
+    pub unacknowledged: Vec<PendingChange>,
+}
+
+impl EscalationState {
+    pub fn path() -> Result<PathBuf> {
+        state_file::path("escalation.json")
+    }
+
+    /// The state stored at the given path, which is empty if there's no file there.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let lock = state_file::lock(&path, "Escalation")?;
+        let mut state: Self = state_file::load(&path, "Escalation")?.unwrap_or_default();
+        state.path = path;
+        state.lock = Some(lock);
+        Ok(state)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        state_file::save_atomic(&self.path, self, "Escalation")
+    }
+
+    /// Record a failed check, and return the escalations
+    /// that it triggers, with the event to send them.
+    pub fn check_failed<'a>(
+        &mut self,
+        rules: &'a [Escalation],
+        error: &Event,
+        now: DateTime<Local>,
+    ) -> Vec<(&'a Escalation, Event)> {
+        self.failed_checks += 1;
+        let since = *self.failing_since.get_or_insert(now);
+        let mut due = vec![];
+        for rule in rules {
+            if rule.failed_checks == 0
+                || self.failed_checks < rule.failed_checks
+                || self.escalated_failures.contains(&rule.name)
+            {
+                continue;
+            }
+            self.escalated_failures.push(rule.name.clone());
+            let mut event = error.clone();
+            let count = self.failed_checks.to_string();
+            event.edit_text(|locale, catalog, text| {
+                text.subject = fill(catalog.escalated_subject, &[("subject", &text.subject)]);
+                let time = locale.format_time(since);
+                let line = fill(
+                    catalog.escalated_failures,
+                    &[("count", &count), ("time", &time)],
+                );
+                text.body.insert(0, line);
+            });
+            due.push((rule, event));
+        }
+        due
+    }
+
+    /// Record a successful check, and return whether it ended a run of failures.
+    pub fn check_succeeded(&mut self) -> bool {
+        let was_failing = self.failed_checks > 0;
+        self.failed_checks = 0;
+        self.failing_since = None;
+        self.escalated_failures.clear();
+        was_failing
+    }
+
+    /// Remember an address change, if any escalation is waiting for it to be acknowledged.
+    pub fn changed(&mut self, rules: &[Escalation], event: &Event) {
+        if rules
+            .iter()
+            .any(|rule| rule.unacknowledged_after().is_some())
+        {
+            self.unacknowledged.push(PendingChange {
+                event: event.clone(),
+                escalated: vec![],
+            });
+        }
+    }
+
+    /// Return the escalations of unacknowledged changes
+    /// that are now due, with the event to send them.
+    pub fn unacknowledged_due<'a>(
+        &mut self,
+        rules: &'a [Escalation],
+        now: DateTime<Local>,
+    ) -> Vec<(&'a Escalation, Event)> {
+        let mut due = vec![];
+        for pending in self.unacknowledged.iter_mut() {
+            for rule in rules {
+                let Some(after) = rule.unacknowledged_after() else {
+                    continue;
+                };
+                if pending.escalated.contains(&rule.name) || pending.event.timestamp + after > now {
+                    continue;
+                }
+                pending.escalated.push(rule.name.clone());
+                let host = pending.event.host.as_deref().unwrap_or_default();
+                let detected = pending.event.timestamp;
+                let mut event = pending.event.clone();
+                event.edit_text(|locale, catalog, text| {
+                    text.subject = fill(
+                        catalog.unacknowledged_subject,
+                        &[("subject", &text.subject)],
+                    );
+                    let time = locale.format_time(detected);
+                    let line = fill(catalog.unacknowledged_detected, &[("time", &time)]);
+                    text.body.insert(0, line);
+                    text.body
+                        .push(fill(catalog.unacknowledged_how, &[("host", host)]));
+                });
+                due.push((rule, event));
+            }
+        }
+        // once a change has been escalated as far as it can go, stop watching it
+        self.unacknowledged.retain(|pending| {
+            rules.iter().any(|rule| {
+                rule.unacknowledged_after().is_some() && !pending.escalated.contains(&rule.name)
+            })
+        });
+        due
+    }
+
+    /// Acknowledge the unacknowledged changes to the given host (or to all hosts),
+    /// and return how many there were.
+    pub fn acknowledge(&mut self, host: Option<&str>) -> usize {
+        let count = self.unacknowledged.len();
+        self.unacknowledged.retain(|pending| match host {
+            None => false,
+            Some(host) => !pending
+                .event
+                .host
+                .as_deref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(host)),
+        });
+        count - self.unacknowledged.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::state_file::tests::TempFile;
+    use super::*;
+
+    fn rules() -> Vec<Escalation> {
+        vec![
+            Escalation {
+                name: String::from("lead"),
+                failed_checks: 2,
+                unacknowledged_minutes: 30,
+                recipients: vec![Recipient::new("lead@example.com")],
+                ..Default::default()
+            },
+            Escalation {
+                name: String::from("boss"),
+                failed_checks: 4,
+                recipients: vec![Recipient::new("boss@example.com")],
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_failed_checks() {
+        let rules = rules();
+        let mut state = EscalationState::default();
+        let error = Event::error(&eyre::eyre!("lookup failed"));
+        let now = Local::now();
+        let mut fired = vec![];
+        for _ in 0..5 {
+            fired.push(state.check_failed(&rules, &error, now));
+        }
+        let counts: Vec<usize> = fired.iter().map(Vec::len).collect();
+        assert_eq!(counts, vec![0, 1, 0, 1, 0]);
+        assert_eq!(fired[1][0].0.name, "lead");
+        assert_eq!(fired[3][0].0.name, "boss");
+        assert!(fired[3][0].1.subject.starts_with("Escalated: "));
+        assert!(fired[3][0].1.body[0].contains("failed 4 checks in a row"));
+        assert!(state.check_succeeded());
+        assert!(!state.check_succeeded());
+        assert!(state.check_failed(&rules, &error, now).is_empty());
+        assert_eq!(state.check_failed(&rules, &error, now).len(), 1);
+    }
+
+    #[test]
+    fn test_rules_by_name() {
+        let mut rules = rules();
+        let mut state = EscalationState::default();
+        let error = Event::error(&eyre::eyre!("lookup failed"));
+        let now = Local::now();
+        state.check_failed(&rules, &error, now);
+        assert_eq!(state.check_failed(&rules, &error, now).len(), 1);
+        // reordering the rules doesn't escalate to the same one again
+        rules.reverse();
+        assert!(state.check_failed(&rules, &error, now).is_empty());
+        let fired = state.check_failed(&rules, &error, now);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].0.name, "boss");
+        // rules from older configurations get names that aren't taken
+        rules.push(Escalation::default());
+        rules.insert(0, Escalation::default());
+        rules[1].name = String::from("escalation 2");
+        Escalation::name_unnamed(&mut rules);
+        let names: Vec<&str> = rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["escalation 1", "escalation 2", "lead", "escalation 3"]
+        );
+    }
+
+    #[test]
+    fn test_unacknowledged_changes() {
+        let rules = rules();
+        let file = TempFile::new("escalation");
+        let path = file.path();
+        let mut state = EscalationState::load(path.clone()).unwrap();
+        let now = Local::now();
+        let mut old = Event::change("old.host", "1.2.3.4", "5.6.7.8");
+        old.timestamp = now - Duration::minutes(45);
+        state.changed(&rules, &old);
+        state.changed(&rules, &Event::change("new.host", "1.2.3.4", "5.6.7.8"));
+        state.save().unwrap();
+        drop(state);
+        let mut state = EscalationState::load(path.clone()).unwrap();
+        assert_eq!(state.unacknowledged.len(), 2);
+        let due = state.unacknowledged_due(&rules, now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].1.subject, "Unacknowledged: DNS change for old.host");
+        assert!(due[0]
+            .1
+            .body
+            .last()
+            .unwrap()
+            .ends_with("acknowledge old.host"));
+        // the old change has gone as far as it can, and only the new one is left
+        assert_eq!(state.unacknowledged.len(), 1);
+        assert!(state.unacknowledged_due(&rules, now).is_empty());
+        assert_eq!(state.acknowledge(Some("other.host")), 0);
+        assert_eq!(state.acknowledge(Some("NEW.host")), 1);
+        assert_eq!(state.acknowledge(None), 0);
+    }
+}
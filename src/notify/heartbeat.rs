@@ -0,0 +1,208 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use chrono::{DateTime, Local, LocalResult, TimeZone, Timelike};
+use eyre::{Report, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use super::{check_response, http_agent, redact_url};
+
+/// Signs of life: a daily summary email, and pings to a dead man's switch
+/// (such as healthchecks.io) after every check.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeartbeatOptions {
+    #[serde(default)]
+    pub daily_summary: bool,
+    /// The summary is sent after the first check at or after this hour of the day (0-23).
+    #[serde(default)]
+    pub summary_hour: u32,
+    /// The URL to ping after every successful check, or empty for no pings.
+    #[serde(default)]
+    pub ping_url: String,
+    /// The URL to ping after every failed check, or empty for the ping URL followed by `/fail`.
+    #[serde(default)]
+    pub fail_url: String,
+}
+
+impl HeartbeatOptions {
+    /// Whether the daily summary should be sent, given when the last one was (in millis).
+    pub fn is_summary_due(&self, last_summary: i64, now: DateTime<Local>) -> bool {
+        if !self.daily_summary || now.hour() < self.summary_hour {
+            return false;
+        }
+        match Local.timestamp_millis_opt(last_summary) {
+            LocalResult::Single(last) if last_summary > 0 => last.date_naive() < now.date_naive(),
+            _ => true,
+        }
+    }
+
+    /// Ping the success URL, if there is one, with a description of the check.
+    pub fn ping_success(&self, description: &str) -> Result<()> {
+        if self.ping_url.is_empty() {
+            return Ok(());
+        }
+        ping(&self.ping_url, description)
+    }
+
+    /// Ping the failure URL, if there is one, with the error.
+    pub fn ping_failure(&self, err: &Report) -> Result<()> {
+        if !self.fail_url.is_empty() {
+            ping(&self.fail_url, &format!("{err:#}"))
+        } else if !self.ping_url.is_empty() {
+            let url = format!("{}/fail", self.ping_url.trim_end_matches('/'));
+            ping(&url, &format!("{err:#}"))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn interview(&self) -> Result<Self> {
+        let daily_summary = dialoguer::Confirm::new()
+            .with_prompt("Send a daily summary email, to show that monitoring is still running?")
+            .default(self.daily_summary)
+            .interact()
+            .wrap_err("Input error")?;
+        let summary_hour = if daily_summary {
+            dialoguer::Input::new()
+                .with_prompt("Hour of the day (0-23) to send the summary")
+                .default(self.summary_hour)
+                .validate_with(|hour: &u32| -> Result<(), &str> {
+                    if *hour < 24 {
+                        Ok(())
+                    } else {
+                        Err("The hour must be from 0 to 23")
+                    }
+                })
+                .interact_text()
+                .wrap_err("Input error")?
+        } else {
+            0
+        };
+        eprintln!("A service such as healthchecks.io can alert you if the monitor stops checking.");
+        let ping_url: String = dialoguer::Input::new()
+            .with_prompt("URL to ping after each check (leave empty for none)")
+            .with_initial_text(&self.ping_url)
+            .allow_empty(true)
+            .validate_with(|url: &String| validate_url(url))
+            .interact_text()
+            .wrap_err("Input error")?;
+        let ping_url = ping_url.trim().to_string();
+        let fail_url = if ping_url.is_empty() {
+            String::new()
+        } else {
+            let fail_url: String = dialoguer::Input::new()
+                .with_prompt(
+                    "URL to ping after a failed check (leave empty for the ping URL plus /fail)",
+                )
+                .with_initial_text(&self.fail_url)
+                .allow_empty(true)
+                .validate_with(|url: &String| validate_url(url))
+                .interact_text()
+                .wrap_err("Input error")?;
+            fail_url.trim().to_string()
+        };
+        Ok(Self {
+            daily_summary,
+            summary_hour,
+            ping_url,
+            fail_url,
+        })
+    }
+}
+
+fn validate_url(url: &str) -> Result<(), &'static str> {
+    let url = url.trim();
+    if url.is_empty() || url.starts_with("http://") || url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err("The URL must start with http:// or https://")
+    }
+}
+
+fn ping(url: &str, body: &str) -> Result<()> {
+    check_response(
+        http_agent()
+            .post(url)
+            .set("Content-Type", "text/plain")
+            .send_string(body),
+    )
+    .wrap_err(format!("Ping of {} failed", redact_url(url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::super::stub::StubServer;
+    use super::*;
+
+    #[test]
+    fn test_is_summary_due() {
+        let options = HeartbeatOptions {
+            daily_summary: true,
+            summary_hour: 8,
+            ..Default::default()
+        };
+        let morning = Local.with_ymd_and_hms(2024, 3, 5, 9, 30, 0).unwrap();
+        let early = Local.with_ymd_and_hms(2024, 3, 5, 7, 30, 0).unwrap();
+        let yesterday = (morning - Duration::days(1)).timestamp_millis();
+        assert!(options.is_summary_due(0, morning));
+        assert!(options.is_summary_due(yesterday, morning));
+        assert!(!options.is_summary_due(yesterday, early));
+        assert!(!options.is_summary_due(morning.timestamp_millis(), morning));
+        let disabled = HeartbeatOptions::default();
+        assert!(!disabled.is_summary_due(0, morning));
+    }
+
+    #[test]
+    fn test_pings() {
+        let server = StubServer::start(vec![(200, "OK"), (200, "OK"), (404, "not found")]);
+        let options = HeartbeatOptions {
+            ping_url: format!("{}/ping/abc/", server.url),
+            ..Default::default()
+        };
+        options.ping_success("2 hosts checked").unwrap();
+        let request = server.next_request();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/ping/abc/");
+        assert_eq!(request.body, "2 hosts checked");
+        options.ping_failure(&eyre::eyre!("lookup failed")).unwrap();
+        let request = server.next_request();
+        assert_eq!(request.path, "/ping/abc/fail");
+        assert_eq!(request.body, "lookup failed");
+        let options = HeartbeatOptions {
+            ping_url: server.url.clone(),
+            fail_url: format!("{}/down", server.url),
+            ..Default::default()
+        };
+        let err = options
+            .ping_failure(&eyre::eyre!("lookup failed"))
+            .expect_err("ping accepted by a missing URL");
+        assert!(err.to_string().starts_with("Ping of"));
+        assert_eq!(server.next_request().path, "/down");
+        HeartbeatOptions::default()
+            .ping_success("nothing to ping")
+            .unwrap();
+    }
+}
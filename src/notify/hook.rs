@@ -0,0 +1,268 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::logging;
+
+use super::Event;
+
+/// How long we wait for a hook's output once it has exited.
+const OUTPUT_WAIT: Duration = Duration::from_secs(1);
+
+/// An executable that is run on every event.
+///
+/// The event is passed both in `DDNS_*` environment variables
+/// and as the JSON webhook payload on standard input.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandHook {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "CommandHook::default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// If true, a failed run fails (and retries) the notification;
+    /// otherwise it's only logged.
+    #[serde(default)]
+    pub must_succeed: bool,
+    #[serde(default = "CommandHook::default_retries")]
+    pub retries: u32,
+    #[serde(default = "CommandHook::default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+}
+
+impl CommandHook {
+    fn default_timeout_secs() -> u64 {
+        30
+    }
+
+    fn default_retries() -> u32 {
+        2
+    }
+
+    fn default_retry_delay_secs() -> u64 {
+        5
+    }
+
+    pub fn new(program: &str, args: Vec<String>) -> Self {
+        Self {
+            program: program.to_string(),
+            args,
+            timeout_secs: Self::default_timeout_secs(),
+            must_succeed: false,
+            retries: Self::default_retries(),
+            retry_delay_secs: Self::default_retry_delay_secs(),
+        }
+    }
+
+    pub fn run(&self, event: &Event) -> Result<()> {
+        if !self.must_succeed {
+            if let Err(err) = self.run_once(event) {
+                logging::warning(&format!(
+                    "Ignoring failure of hook {}: {err:#}",
+                    self.program
+                ));
+            }
+            return Ok(());
+        }
+        let mut delay = self.retry_delay_secs;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let err = match self.run_once(event) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if attempt > self.retries {
+                return Err(err.wrap_err(format!("Hook failed after {attempt} attempts")));
+            }
+            logging::warning(&format!("Will retry hook {}: {err:#}", self.program));
+            std::thread::sleep(Duration::from_secs(delay));
+            delay *= 2;
+        }
+    }
+
+    fn run_once(&self, event: &Event) -> Result<()> {
+        let payload = serde_json::to_string(event).wrap_err("Event cannot be serialized")?;
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env("DDNS_EVENT", event.event.as_str())
+            .env("DDNS_HOST", optional(&event.host))
+            .env("DDNS_OLD_ADDRESS", optional(&event.old_address))
+            .env("DDNS_NEW_ADDRESS", optional(&event.new_address))
+            .env("DDNS_MONITOR_HOST", &event.monitor_host)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err(format!("Couldn't run hook {}", self.program))?;
+        // feed and drain the pipes on other threads, so a chatty hook can't block
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        std::thread::spawn(move || {
+            // a hook that doesn't read its input will close the pipe early
+            let _ = stdin.write_all(payload.as_bytes());
+        });
+        let stdout = drain(child.stdout.take().expect("piped stdout"));
+        let stderr = drain(child.stderr.take().expect("piped stderr"));
+        let deadline = Instant::now() + Duration::from_secs(self.timeout_secs);
+        let status: Option<ExitStatus> = loop {
+            if let Some(status) = child.try_wait().wrap_err("Couldn't wait for hook")? {
+                break Some(status);
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        for (name, output) in [("stdout", stdout), ("stderr", stderr)] {
+            // background processes started by the hook may hold its pipes open
+            let output = output.recv_timeout(OUTPUT_WAIT).unwrap_or_default();
+            for line in output.lines() {
+                logging::info(&format!("Hook {} {name}: {line}", self.program));
+            }
+        }
+        match status {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => Err(eyre!("Hook {} failed: {status}", self.program)),
+            None => Err(eyre!(
+                "Hook {} timed out after {} seconds",
+                self.program,
+                self.timeout_secs
+            )),
+        }
+    }
+
+    pub fn interview() -> Result<Self> {
+        let program: String = dialoguer::Input::new()
+            .with_prompt("Path of the executable to run")
+            .allow_empty(false)
+            .validate_with(|path: &String| -> std::result::Result<(), String> {
+                if std::path::Path::new(path.trim()).is_file() {
+                    Ok(())
+                } else {
+                    Err(format!("{path} is not a file"))
+                }
+            })
+            .interact_text()
+            .wrap_err("Input error")?;
+        let args: String = dialoguer::Input::new()
+            .with_prompt("Arguments, separated by spaces (leave empty for none)")
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let mut hook = Self::new(
+            program.trim(),
+            args.split_whitespace().map(String::from).collect(),
+        );
+        hook.timeout_secs = dialoguer::Input::new()
+            .with_prompt("Timeout in seconds")
+            .default(Self::default_timeout_secs())
+            .interact_text()
+            .wrap_err("Input error")?;
+        hook.must_succeed = dialoguer::Confirm::new()
+            .with_prompt("Must the executable succeed for the notification to succeed?")
+            .default(false)
+            .interact()
+            .wrap_err("Input error")?;
+        Ok(hook)
+    }
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> Receiver<String> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let mut output = vec![];
+        let _ = pipe.read_to_end(&mut output);
+        let _ = sender.send(String::from_utf8_lossy(&output).to_string());
+    });
+    receiver
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell_hook(script: &str) -> CommandHook {
+        let mut hook = CommandHook::new("/bin/sh", vec![String::from("-c"), script.to_string()]);
+        hook.retry_delay_secs = 0;
+        hook
+    }
+
+    #[test]
+    fn test_hook_environment_and_input() {
+        let dir = std::env::temp_dir().join(format!("ddns-hook-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("output");
+        let script = format!(
+            "echo \"$DDNS_EVENT $DDNS_HOST $DDNS_OLD_ADDRESS $DDNS_NEW_ADDRESS $DDNS_MONITOR_HOST\" > {0}; cat >> {0}",
+            output.display()
+        );
+        let mut hook = shell_hook(&script);
+        hook.must_succeed = true;
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        hook.run(&event).expect("hook failed");
+        let output = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (variables, input) = output.split_once('\n').unwrap();
+        assert_eq!(
+            variables,
+            format!("change some.host 1.2.3.4 5.6.7.8 {}", event.monitor_host)
+        );
+        let payload: serde_json::Value = serde_json::from_str(input).unwrap();
+        assert_eq!(payload["newAddress"], "5.6.7.8");
+    }
+
+    #[test]
+    fn test_hook_failure() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let mut hook = shell_hook("echo failing; exit 3");
+        hook.run(&event)
+            .expect("optional hook failure was not ignored");
+        hook.must_succeed = true;
+        let err = hook
+            .run(&event)
+            .expect_err("required hook failure was ignored");
+        assert!(err.to_string().contains("after 3 attempts"));
+    }
+
+    #[test]
+    fn test_hook_timeout() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let mut hook = shell_hook("sleep 10");
+        hook.timeout_secs = 1;
+        let start = Instant::now();
+        let err = hook.run_once(&event).expect_err("hook didn't time out");
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,650 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::fmt::{Display, Formatter};
+
+use chrono::{DateTime, Datelike, Duration, Local};
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use super::EventKind;
+
+/// A language that notifications can be sent in.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "es")]
+    Spanish,
+    #[serde(rename = "fr")]
+    French,
+}
+
+/// The subject and body of a notification in one language.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Translation {
+    pub subject: String,
+    pub body: Vec<String>,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 3] = [Locale::English, Locale::Spanish, Locale::French];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::Spanish => "es",
+            Locale::French => "fr",
+        }
+    }
+
+    pub fn catalog(&self) -> &'static Catalog {
+        match self {
+            Locale::English => &ENGLISH,
+            Locale::Spanish => &SPANISH,
+            Locale::French => &FRENCH,
+        }
+    }
+
+    /// A date and time as it's written in this language.
+    ///
+    /// English uses RFC 2822, as notifications always have.
+    pub fn format_time(&self, time: DateTime<Local>) -> String {
+        let catalog = self.catalog();
+        let weekday = catalog.weekdays[time.weekday().num_days_from_monday() as usize];
+        let month = catalog.months[time.month0() as usize];
+        let clock = time.format("%H:%M:%S %z");
+        match self {
+            Locale::English => time.to_rfc2822(),
+            Locale::Spanish => format!(
+                "{weekday}, {} de {month} de {}, {clock}",
+                time.day(),
+                time.year()
+            ),
+            Locale::French => format!("{weekday} {} {month} {} à {clock}", time.day(), time.year()),
+        }
+    }
+
+    /// A duration in words, to the minute, such as "1 day, 2 hours and 1 minute".
+    pub fn describe_duration(&self, duration: Duration) -> String {
+        let catalog = self.catalog();
+        let minutes = duration.num_minutes();
+        if minutes < 1 {
+            return catalog.less_than_a_minute.to_string();
+        }
+        let mut parts = vec![];
+        for (count, (one, many)) in [
+            (minutes / (24 * 60), catalog.days),
+            (minutes / 60 % 24, catalog.hours),
+            (minutes % 60, catalog.minutes),
+        ] {
+            match count {
+                0 => {}
+                1 => parts.push(format!("1 {one}")),
+                n => parts.push(format!("{n} {many}")),
+            }
+        }
+        match parts.split_last() {
+            Some((last, rest)) if !rest.is_empty() => {
+                format!("{} {} {last}", rest.join(", "), catalog.and)
+            }
+            _ => parts.join(""),
+        }
+    }
+
+    pub fn interview(&self, prompt: &str) -> Result<Self> {
+        let choice = dialoguer::Select::new()
+            .with_prompt(prompt)
+            .items(&Self::ALL)
+            .default(Self::ALL.iter().position(|l| l == self).unwrap_or(0))
+            .interact()
+            .wrap_err("Input error")?;
+        Ok(Self::ALL[choice])
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Locale::English => f.write_str("English"),
+            Locale::Spanish => f.write_str("Español"),
+            Locale::French => f.write_str("Français"),
+        }
+    }
+}
+
+/// Replace each `{name}` in the template with the value of that name.
+/// Names without values are left as they are.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
+        let name = &after[..end];
+        match values.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The text of notifications in one language.
+///
+/// Lines that start with `-- ` are details, which are shown as a list in HTML.
+pub struct Catalog {
+    pub initial_subject: &'static str,
+    pub initial_starting: &'static str,
+    pub initial_restarting: &'static str,
+    pub initial_address: &'static str,
+    pub known_address: &'static str,
+    pub last_check: &'static str,
+    pub initial_outro: &'static str,
+    pub heartbeat_subject: &'static str,
+    pub heartbeat_intro: &'static str,
+    pub heartbeat_address: &'static str,
+    pub never_changed: &'static str,
+    pub unknown_time: &'static str,
+    pub heartbeat_outro: &'static str,
+    pub change_subject: &'static str,
+    pub change_intro: &'static str,
+    pub change_old: &'static str,
+    pub change_new: &'static str,
+    pub change_outro: &'static str,
+    pub error_subject: &'static str,
+    pub error_intro: &'static str,
+    pub error_outro: &'static str,
+    pub recovery_subject: &'static str,
+    pub recovery_host_subject: &'static str,
+    pub recovery_intro: &'static str,
+    pub recovery_started: &'static str,
+    pub recovery_lasted: &'static str,
+    pub digest_subject: &'static str,
+    pub digest_intro: &'static str,
+    pub digest_item: &'static str,
+    /// The singular and plural names of the kinds of event counted in a digest subject.
+    pub counts: [(EventKind, &'static str, &'static str); 4],
+    pub escalated_subject: &'static str,
+    pub escalated_failures: &'static str,
+    pub unacknowledged_subject: &'static str,
+    pub unacknowledged_detected: &'static str,
+    pub unacknowledged_how: &'static str,
+    pub reminder_subject: &'static str,
+    pub reminder_started: &'static str,
+    pub report_weekly_subject: &'static str,
+    pub report_monthly_subject: &'static str,
+    pub report_intro: &'static str,
+    pub report_host: &'static str,
+    pub report_failures: &'static str,
+    pub report_host_without_times: &'static str,
+    pub report_error: &'static str,
+    pub report_no_errors: &'static str,
+    pub report_outro: &'static str,
+    pub encrypted_subject: &'static str,
+    pub status_title: &'static str,
+    pub status_address: &'static str,
+    pub status_no_check: &'static str,
+    pub check_failed: &'static str,
+    pub host_label: &'static str,
+    pub old_address_label: &'static str,
+    pub new_address_label: &'static str,
+    pub footer: &'static str,
+    pub less_than_a_minute: &'static str,
+    pub days: (&'static str, &'static str),
+    pub hours: (&'static str, &'static str),
+    pub minutes: (&'static str, &'static str),
+    /// How many times something happened.
+    pub times: (&'static str, &'static str),
+    pub and: &'static str,
+    /// From Monday.
+    pub weekdays: [&'static str; 7],
+    pub months: [&'static str; 12],
+}
+
+static ENGLISH: Catalog = Catalog {
+    initial_subject: "Dynamic DNS monitoring status",
+    initial_starting: "Dynamic DNS monitoring from {monitor} is starting for the following hosts:",
+    initial_restarting:
+        "Dynamic DNS monitoring from {monitor} is restarting for the following hosts:",
+    initial_address: "-- Host: {host}, Initial address: {address}",
+    known_address: "-- Host: {host}, Last known address: {address}",
+    last_check: "Last check was at {time}",
+    initial_outro: "You will be notified if any of these addresses change.",
+    heartbeat_subject: "Dynamic DNS monitoring daily summary",
+    heartbeat_intro: "Dynamic DNS monitoring from {monitor} is running for the following hosts:",
+    heartbeat_address: "-- Host: {host}, Address: {address}, Last changed: {changed}",
+    never_changed: "not since monitoring started",
+    unknown_time: "unknown",
+    heartbeat_outro: "You will be sent this summary once a day.",
+    change_subject: "DNS change for {host}",
+    change_intro: "The IP address of {host} has changed.",
+    change_old: "-- The old IP address was: {address}.",
+    change_new: "-- The new IP address is: {address}.",
+    change_outro: "You must reconfigure any services that had the old IP address.",
+    error_subject: "DNS monitoring temporary failure",
+    error_intro: "DNS monitoring reported an error: {error}",
+    error_outro: "A retry will be performed on the normal schedule.",
+    recovery_subject: "DNS monitoring recovered",
+    recovery_host_subject: "DNS monitoring of {host} recovered",
+    recovery_intro: "DNS monitoring has recovered from an error: {error}",
+    recovery_started: "-- The error started at {time}.",
+    recovery_lasted: "-- It lasted {duration}, over {count} failed checks.",
+    digest_subject: "DNS monitoring digest: {counts}",
+    digest_intro: "Dynamic DNS monitoring from {monitor} reported {count} events:",
+    digest_item: "{subject} (at {time})",
+    counts: [
+        (EventKind::Change, "address change", "address changes"),
+        (EventKind::Error, "error", "errors"),
+        (EventKind::Recovery, "recovery", "recoveries"),
+        (EventKind::Initial, "status report", "status reports"),
+    ],
+    escalated_subject: "Escalated: {subject}",
+    escalated_failures: "Monitoring has failed {count} checks in a row, starting at {time}.",
+    unacknowledged_subject: "Unacknowledged: {subject}",
+    unacknowledged_detected: "This change was detected at {time} and hasn't been acknowledged.",
+    unacknowledged_how: "To acknowledge it, run: ddns-monitor acknowledge {host}",
+    reminder_subject: "Reminder: {subject}",
+    reminder_started: "This error started at {time} and has failed {count} checks.",
+    report_weekly_subject: "Dynamic DNS monitoring weekly report",
+    report_monthly_subject: "Dynamic DNS monitoring monthly report",
+    report_intro: "Dynamic DNS monitoring from {monitor} between {start} and {end}:",
+    report_host: "-- {host}: address changes: {changes}, longest time at one address: {longest}, shortest: {shortest}, failed lookups: {failures}",
+    report_failures: "Monitoring failed {count} checks in this period.",
+    report_host_without_times: "-- {host}: address changes: {changes}, failed lookups: {failures}",
+    report_error: "-- {error} ({count}, first at {time})",
+    report_no_errors: "There were no errors in this period.",
+    report_outro: "The events of the period are attached.",
+    encrypted_subject: "Dynamic DNS monitoring notification",
+    status_title: "Dynamic DNS monitoring from {monitor}",
+    status_address: "-- Host: {host}, address: {address}",
+    status_no_check: "No check has been done yet",
+    check_failed: "Check failed: {error}",
+    host_label: "Host",
+    old_address_label: "Old address",
+    new_address_label: "New address",
+    footer: "ddns-monitor on {monitor} at {time}",
+    less_than_a_minute: "less than a minute",
+    days: ("day", "days"),
+    hours: ("hour", "hours"),
+    minutes: ("minute", "minutes"),
+    times: ("time", "times"),
+    and: "and",
+    weekdays: [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ],
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+};
+
+static SPANISH: Catalog = Catalog {
+    initial_subject: "Estado de la supervisión de DNS dinámico",
+    initial_starting:
+        "La supervisión de DNS dinámico desde {monitor} se está iniciando para los siguientes hosts:",
+    initial_restarting:
+        "La supervisión de DNS dinámico desde {monitor} se está reiniciando para los siguientes hosts:",
+    initial_address: "-- Host: {host}, dirección inicial: {address}",
+    known_address: "-- Host: {host}, última dirección conocida: {address}",
+    last_check: "La última comprobación fue el {time}",
+    initial_outro: "Se le avisará si alguna de estas direcciones cambia.",
+    heartbeat_subject: "Resumen diario de la supervisión de DNS dinámico",
+    heartbeat_intro:
+        "La supervisión de DNS dinámico desde {monitor} está funcionando para los siguientes hosts:",
+    heartbeat_address: "-- Host: {host}, dirección: {address}, último cambio: {changed}",
+    never_changed: "ninguno desde el inicio de la supervisión",
+    unknown_time: "desconocido",
+    heartbeat_outro: "Recibirá este resumen una vez al día.",
+    change_subject: "Cambio de DNS para {host}",
+    change_intro: "La dirección IP de {host} ha cambiado.",
+    change_old: "-- La dirección IP anterior era: {address}.",
+    change_new: "-- La nueva dirección IP es: {address}.",
+    change_outro: "Debe reconfigurar todos los servicios que usaban la dirección IP anterior.",
+    error_subject: "Fallo temporal de la supervisión de DNS",
+    error_intro: "La supervisión de DNS informó de un error: {error}",
+    error_outro: "Se volverá a intentar según el calendario habitual.",
+    recovery_subject: "La supervisión de DNS se ha recuperado",
+    recovery_host_subject: "La supervisión de DNS de {host} se ha recuperado",
+    recovery_intro: "La supervisión de DNS se ha recuperado de un error: {error}",
+    recovery_started: "-- El error comenzó el {time}.",
+    recovery_lasted: "-- Duró {duration}, a lo largo de {count} comprobaciones fallidas.",
+    digest_subject: "Resumen de la supervisión de DNS: {counts}",
+    digest_intro: "La supervisión de DNS dinámico desde {monitor} informó de {count} eventos:",
+    digest_item: "{subject} (el {time})",
+    counts: [
+        (EventKind::Change, "cambio de dirección", "cambios de dirección"),
+        (EventKind::Error, "error", "errores"),
+        (EventKind::Recovery, "recuperación", "recuperaciones"),
+        (EventKind::Initial, "informe de estado", "informes de estado"),
+    ],
+    escalated_subject: "Escalado: {subject}",
+    escalated_failures:
+        "La supervisión ha fallado {count} comprobaciones seguidas, desde el {time}.",
+    unacknowledged_subject: "Sin confirmar: {subject}",
+    unacknowledged_detected: "Este cambio se detectó el {time} y no se ha confirmado.",
+    unacknowledged_how: "Para confirmarlo, ejecute: ddns-monitor acknowledge {host}",
+    reminder_subject: "Recordatorio: {subject}",
+    reminder_started: "Este error comenzó el {time} y ha fallado {count} comprobaciones.",
+    report_weekly_subject: "Informe semanal de la supervisión de DNS dinámico",
+    report_monthly_subject: "Informe mensual de la supervisión de DNS dinámico",
+    report_intro: "Supervisión de DNS dinámico desde {monitor} entre el {start} y el {end}:",
+    report_host: "-- {host}: cambios de dirección: {changes}, tiempo más largo con una dirección: {longest}, más corto: {shortest}, búsquedas fallidas: {failures}",
+    report_failures: "La supervisión falló en {count} comprobaciones durante este período.",
+    report_host_without_times: "-- {host}: cambios de dirección: {changes}, búsquedas fallidas: {failures}",
+    report_error: "-- {error} ({count}, la primera el {time})",
+    report_no_errors: "No hubo errores en este período.",
+    report_outro: "Los eventos del período van adjuntos.",
+    encrypted_subject: "Notificación de la supervisión de DNS dinámico",
+    status_title: "Supervisión de DNS dinámico desde {monitor}",
+    status_address: "-- Host: {host}, dirección: {address}",
+    status_no_check: "Todavía no se ha hecho ninguna comprobación",
+    check_failed: "La comprobación falló: {error}",
+    host_label: "Host",
+    old_address_label: "Dirección anterior",
+    new_address_label: "Dirección nueva",
+    footer: "ddns-monitor en {monitor} el {time}",
+    less_than_a_minute: "menos de un minuto",
+    days: ("día", "días"),
+    hours: ("hora", "horas"),
+    minutes: ("minuto", "minutos"),
+    times: ("vez", "veces"),
+    and: "y",
+    weekdays: [
+        "lunes",
+        "martes",
+        "miércoles",
+        "jueves",
+        "viernes",
+        "sábado",
+        "domingo",
+    ],
+    months: [
+        "enero",
+        "febrero",
+        "marzo",
+        "abril",
+        "mayo",
+        "junio",
+        "julio",
+        "agosto",
+        "septiembre",
+        "octubre",
+        "noviembre",
+        "diciembre",
+    ],
+};
+
+static FRENCH: Catalog = Catalog {
+    initial_subject: "État de la surveillance du DNS dynamique",
+    initial_starting:
+        "La surveillance du DNS dynamique depuis {monitor} démarre pour les hôtes suivants :",
+    initial_restarting:
+        "La surveillance du DNS dynamique depuis {monitor} redémarre pour les hôtes suivants :",
+    initial_address: "-- Hôte : {host}, adresse initiale : {address}",
+    known_address: "-- Hôte : {host}, dernière adresse connue : {address}",
+    last_check: "La dernière vérification a eu lieu le {time}",
+    initial_outro: "Vous serez averti si l’une de ces adresses change.",
+    heartbeat_subject: "Résumé quotidien de la surveillance du DNS dynamique",
+    heartbeat_intro:
+        "La surveillance du DNS dynamique depuis {monitor} fonctionne pour les hôtes suivants :",
+    heartbeat_address: "-- Hôte : {host}, adresse : {address}, dernier changement : {changed}",
+    never_changed: "aucun depuis le début de la surveillance",
+    unknown_time: "inconnu",
+    heartbeat_outro: "Vous recevrez ce résumé une fois par jour.",
+    change_subject: "Changement DNS pour {host}",
+    change_intro: "L’adresse IP de {host} a changé.",
+    change_old: "-- L’ancienne adresse IP était : {address}.",
+    change_new: "-- La nouvelle adresse IP est : {address}.",
+    change_outro:
+        "Vous devez reconfigurer tous les services qui utilisaient l’ancienne adresse IP.",
+    error_subject: "Échec temporaire de la surveillance DNS",
+    error_intro: "La surveillance DNS a signalé une erreur : {error}",
+    error_outro: "Une nouvelle tentative aura lieu selon le calendrier habituel.",
+    recovery_subject: "La surveillance DNS est rétablie",
+    recovery_host_subject: "La surveillance DNS de {host} est rétablie",
+    recovery_intro: "La surveillance DNS s’est rétablie après une erreur : {error}",
+    recovery_started: "-- L’erreur a commencé le {time}.",
+    recovery_lasted: "-- Elle a duré {duration}, sur {count} vérifications échouées.",
+    digest_subject: "Résumé de la surveillance DNS : {counts}",
+    digest_intro:
+        "La surveillance du DNS dynamique depuis {monitor} a signalé {count} événements :",
+    digest_item: "{subject} (le {time})",
+    counts: [
+        (
+            EventKind::Change,
+            "changement d’adresse",
+            "changements d’adresse",
+        ),
+        (EventKind::Error, "erreur", "erreurs"),
+        (EventKind::Recovery, "rétablissement", "rétablissements"),
+        (EventKind::Initial, "rapport d’état", "rapports d’état"),
+    ],
+    escalated_subject: "Escalade : {subject}",
+    escalated_failures:
+        "La surveillance a échoué à {count} vérifications de suite, depuis le {time}.",
+    unacknowledged_subject: "Non confirmé : {subject}",
+    unacknowledged_detected: "Ce changement a été détecté le {time} et n’a pas été confirmé.",
+    unacknowledged_how: "Pour le confirmer, exécutez : ddns-monitor acknowledge {host}",
+    reminder_subject: "Rappel : {subject}",
+    reminder_started: "Cette erreur a commencé le {time} et a fait échouer {count} vérifications.",
+    report_weekly_subject: "Rapport hebdomadaire de la surveillance du DNS dynamique",
+    report_monthly_subject: "Rapport mensuel de la surveillance du DNS dynamique",
+    report_intro: "Surveillance du DNS dynamique depuis {monitor} entre le {start} et le {end} :",
+    report_host: "-- {host} : changements d’adresse : {changes}, durée la plus longue à une adresse : {longest}, la plus courte : {shortest}, recherches échouées : {failures}",
+    report_failures: "La surveillance a échoué lors de {count} vérifications pendant cette période.",
+    report_host_without_times: "-- {host} : changements d’adresse : {changes}, recherches échouées : {failures}",
+    report_error: "-- {error} ({count}, la première le {time})",
+    report_no_errors: "Il n’y a eu aucune erreur pendant cette période.",
+    report_outro: "Les événements de la période sont joints.",
+    encrypted_subject: "Notification de la surveillance du DNS dynamique",
+    status_title: "Surveillance du DNS dynamique depuis {monitor}",
+    status_address: "-- Hôte : {host}, adresse : {address}",
+    status_no_check: "Aucune vérification n’a encore été faite",
+    check_failed: "La vérification a échoué : {error}",
+    host_label: "Hôte",
+    old_address_label: "Ancienne adresse",
+    new_address_label: "Nouvelle adresse",
+    footer: "ddns-monitor sur {monitor} le {time}",
+    less_than_a_minute: "moins d’une minute",
+    days: ("jour", "jours"),
+    hours: ("heure", "heures"),
+    minutes: ("minute", "minutes"),
+    times: ("fois", "fois"),
+    and: "et",
+    weekdays: [
+        "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+    ],
+    months: [
+        "janvier",
+        "février",
+        "mars",
+        "avril",
+        "mai",
+        "juin",
+        "juillet",
+        "août",
+        "septembre",
+        "octobre",
+        "novembre",
+        "décembre",
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// The templates of a catalog, for checking that translations have the same placeholders.
+    fn templates(catalog: &Catalog) -> Vec<&'static str> {
+        vec![
+            catalog.initial_subject,
+            catalog.initial_starting,
+            catalog.initial_restarting,
+            catalog.initial_address,
+            catalog.known_address,
+            catalog.last_check,
+            catalog.initial_outro,
+            catalog.heartbeat_subject,
+            catalog.heartbeat_intro,
+            catalog.heartbeat_address,
+            catalog.heartbeat_outro,
+            catalog.change_subject,
+            catalog.change_intro,
+            catalog.change_old,
+            catalog.change_new,
+            catalog.change_outro,
+            catalog.error_subject,
+            catalog.error_intro,
+            catalog.error_outro,
+            catalog.recovery_subject,
+            catalog.recovery_host_subject,
+            catalog.recovery_intro,
+            catalog.recovery_started,
+            catalog.recovery_lasted,
+            catalog.digest_subject,
+            catalog.digest_intro,
+            catalog.digest_item,
+            catalog.escalated_subject,
+            catalog.escalated_failures,
+            catalog.unacknowledged_subject,
+            catalog.unacknowledged_detected,
+            catalog.unacknowledged_how,
+            catalog.reminder_subject,
+            catalog.reminder_started,
+            catalog.report_weekly_subject,
+            catalog.report_monthly_subject,
+            catalog.report_intro,
+            catalog.report_host,
+            catalog.report_failures,
+            catalog.report_host_without_times,
+            catalog.report_error,
+            catalog.report_no_errors,
+            catalog.report_outro,
+            catalog.encrypted_subject,
+            catalog.status_title,
+            catalog.status_address,
+            catalog.status_no_check,
+            catalog.check_failed,
+            catalog.footer,
+        ]
+    }
+
+    fn placeholders(template: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = template
+            .split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_catalogs_match() {
+        let english = templates(&ENGLISH);
+        for locale in [Locale::Spanish, Locale::French] {
+            for (english, translated) in english.iter().zip(templates(locale.catalog())) {
+                assert_eq!(
+                    placeholders(english),
+                    placeholders(translated),
+                    "{locale} translation of \"{english}\""
+                );
+                assert_eq!(english.starts_with("-- "), translated.starts_with("-- "));
+            }
+            for ((kind, _, _), (translated, _, _)) in
+                ENGLISH.counts.iter().zip(locale.catalog().counts.iter())
+            {
+                assert_eq!(kind, translated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(
+            fill(
+                "{host} is {address} {unknown} {",
+                &[("host", "a.host"), ("address", "{host}")]
+            ),
+            "a.host is {host} {unknown} {"
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let time = Local.with_ymd_and_hms(2024, 3, 5, 9, 30, 0).unwrap();
+        assert_eq!(Locale::English.format_time(time), time.to_rfc2822());
+        assert!(Locale::Spanish
+            .format_time(time)
+            .starts_with("martes, 5 de marzo de 2024, 09:30:00 "));
+        assert!(Locale::French
+            .format_time(time)
+            .starts_with("mardi 5 mars 2024 à 09:30:00 "));
+        let duration = Duration::minutes(3 * 24 * 60 + 125);
+        assert_eq!(
+            Locale::English.describe_duration(duration),
+            "3 days, 2 hours and 5 minutes"
+        );
+        assert_eq!(
+            Locale::Spanish.describe_duration(duration),
+            "3 días, 2 horas y 5 minutos"
+        );
+        assert_eq!(
+            Locale::French.describe_duration(Duration::minutes(61)),
+            "1 heure et 1 minute"
+        );
+        assert_eq!(
+            Locale::English.describe_duration(Duration::seconds(59)),
+            "less than a minute"
+        );
+        assert_eq!(serde_json::to_string(&Locale::French).unwrap(), "\"fr\"");
+    }
+}
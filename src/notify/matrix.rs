@@ -0,0 +1,223 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::time::Duration;
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::{
+    decrypt_secret, encrypt_secret, escape_html, http_agent, percent_encode, send_json, Event,
+};
+
+/// Matrix rooms on a homeserver, posted to by an existing user or bot account.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Matrix {
+    pub homeserver_url: String,
+    encrypted_access_token: String,
+    pub room_ids: Vec<String>,
+    #[serde(default = "Matrix::default_retries")]
+    pub retries: u32,
+    #[serde(default = "Matrix::default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+}
+
+impl Matrix {
+    fn default_retries() -> u32 {
+        3
+    }
+
+    fn default_retry_delay_secs() -> u64 {
+        2
+    }
+
+    pub fn new(homeserver_url: &str, access_token: &str, room_ids: Vec<String>) -> Result<Self> {
+        Ok(Self {
+            homeserver_url: homeserver_url.trim_end_matches('/').to_string(),
+            encrypted_access_token: encrypt_secret(access_token)?,
+            room_ids,
+            retries: Self::default_retries(),
+            retry_delay_secs: Self::default_retry_delay_secs(),
+        })
+    }
+
+    pub fn send(&self, event: &Event) -> Result<()> {
+        let token = decrypt_secret(&self.encrypted_access_token)?;
+        let content = message_content(event);
+        for room_id in self.room_ids.iter() {
+            let url = format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                self.homeserver_url,
+                percent_encode(room_id),
+                transaction_id(event, room_id),
+            );
+            // the homeserver ignores a repeated transaction ID, so retries can't duplicate
+            let mut delay = self.retry_delay_secs;
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let request = http_agent()
+                    .put(&url)
+                    .set("Authorization", &format!("Bearer {token}"));
+                let err = match send_json(request, &content) {
+                    Ok(()) => break,
+                    Err(err) => err,
+                };
+                if attempt > self.retries {
+                    return Err(err.wrap_err(format!(
+                        "Matrix post to {room_id} failed after {attempt} attempts"
+                    )));
+                }
+                std::thread::sleep(Duration::from_secs(delay));
+                delay *= 2;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn interview() -> Result<Self> {
+        let homeserver_url: String = dialoguer::Input::new()
+            .with_prompt("Matrix homeserver URL")
+            .with_initial_text("https://")
+            .allow_empty(false)
+            .validate_with(|url: &String| -> std::result::Result<(), String> {
+                if url.starts_with("https://") || url.starts_with("http://") {
+                    Ok(())
+                } else {
+                    Err(format!("{url} is not an http or https URL"))
+                }
+            })
+            .interact_text()
+            .wrap_err("Input error")?;
+        let token: String = dialoguer::Input::new()
+            .with_prompt("Matrix access token")
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let rooms: String = dialoguer::Input::new()
+            .with_prompt("Room IDs to notify (such as !abcdef:example.org)")
+            .allow_empty(false)
+            .validate_with(|rooms: &String| -> std::result::Result<(), String> {
+                match rooms.split([',', ' ']).find(|room| !is_room_id(room)) {
+                    Some(room) => Err(format!("{room} is not a room ID")),
+                    None => Ok(()),
+                }
+            })
+            .interact_text()
+            .wrap_err("Input error")?;
+        let room_ids = rooms
+            .split([',', ' '])
+            .filter(|room| !room.is_empty())
+            .map(String::from)
+            .collect();
+        Self::new(homeserver_url.trim(), token.trim(), room_ids)
+    }
+}
+
+fn is_room_id(room: &str) -> bool {
+    room.is_empty() || (room.starts_with('!') && room.contains(':'))
+}
+
+/// The content of a formatted notice for the event.
+fn message_content(event: &Event) -> String {
+    let mut body = vec![event.subject.clone()];
+    body.extend(event.body.iter().cloned());
+    let mut html = format!("<strong>{}</strong>", escape_html(&event.subject));
+    for line in event.body.iter() {
+        html.push_str("<br>");
+        html.push_str(&escape_html(line));
+    }
+    json!({
+        "msgtype": "m.notice",
+        "body": body.join("\n"),
+        "format": "org.matrix.custom.html",
+        "formatted_body": html,
+    })
+    .to_string()
+}
+
+/// A transaction ID that is the same every time a given event is sent to a given room.
+fn transaction_id(event: &Event, room_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_string(event).unwrap_or_default());
+    hasher.update(room_id);
+    format!("ddns-{}", &hex::encode(hasher.finalize())[..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::super::stub::StubServer;
+    use super::*;
+
+    #[test]
+    fn test_send_retries_with_same_transaction() {
+        let server = StubServer::start(vec![(502, ""), (200, "{\"event_id\":\"$1\"}")]);
+        let mut matrix = Matrix::new(
+            &server.url,
+            "syt_token",
+            vec!["!room:example.org".to_string()],
+        )
+        .unwrap();
+        matrix.retry_delay_secs = 0;
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        matrix.send(&event).expect("Matrix send failed");
+        let first = server.next_request();
+        let second = server.next_request();
+        assert_eq!(first.method, "PUT");
+        assert!(first.path.starts_with(
+            "/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/ddns-"
+        ));
+        assert_eq!(first.path, second.path);
+        assert_eq!(second.headers["authorization"], "Bearer syt_token");
+        let content: Value = serde_json::from_str(&second.body).unwrap();
+        assert_eq!(content["msgtype"], "m.notice");
+        assert!(content["formatted_body"]
+            .as_str()
+            .unwrap()
+            .starts_with("<strong>DNS change for some.host</strong><br>"));
+    }
+
+    #[test]
+    fn test_transaction_ids() {
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let mut other = event.clone();
+        other.timestamp += chrono::Duration::seconds(1);
+        assert_eq!(
+            transaction_id(&event, "!a:x"),
+            transaction_id(&event, "!a:x")
+        );
+        assert_ne!(
+            transaction_id(&event, "!a:x"),
+            transaction_id(&event, "!b:x")
+        );
+        assert_ne!(
+            transaction_id(&event, "!a:x"),
+            transaction_id(&other, "!a:x")
+        );
+    }
+}
@@ -0,0 +1,319 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::time::{Duration, Instant};
+
+use eyre::{eyre, Result, WrapErr};
+use rumqttc::{Client, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{decrypt_secret, encrypt_secret, Event, EventKind};
+
+/// How long we wait for the broker to acknowledge our messages.
+const BROKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An MQTT broker that receives host addresses and events.
+///
+/// Each host's address is published, retained, to
+/// `<prefix>/<monitor host>/hosts/<host>/address`, and each event is
+/// published as JSON to `<prefix>/<monitor host>/events/<event type>`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mqtt {
+    pub host: String,
+    pub port: u16,
+    #[serde(default)]
+    pub use_tls: bool,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    encrypted_password: String,
+    #[serde(default = "Mqtt::default_topic_prefix")]
+    pub topic_prefix: String,
+    /// If not empty, Home Assistant discovery configs are published under this prefix.
+    #[serde(default)]
+    pub discovery_prefix: String,
+}
+
+/// A message to be published: its topic, whether it's retained, and its payload.
+type Message = (String, bool, String);
+
+impl Mqtt {
+    pub fn default_topic_prefix() -> String {
+        String::from("ddns-monitor")
+    }
+
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            use_tls: false,
+            username: String::new(),
+            encrypted_password: String::new(),
+            topic_prefix: Self::default_topic_prefix(),
+            discovery_prefix: String::new(),
+        }
+    }
+
+    fn base_topic(&self, event: &Event) -> String {
+        format!("{}/{}", self.topic_prefix, topic_level(&event.monitor_host))
+    }
+
+    fn address_topic(&self, event: &Event, host: &str) -> String {
+        format!(
+            "{}/hosts/{}/address",
+            self.base_topic(event),
+            topic_level(host)
+        )
+    }
+
+    /// The messages that publish the given event.
+    fn messages(&self, event: &Event) -> Result<Vec<Message>> {
+        if event.event == EventKind::Digest {
+            // subscribers see the parts of a digest as separate events
+            let mut messages = vec![];
+            for part in event.parts() {
+                messages.extend(self.messages(part)?);
+            }
+            return Ok(messages);
+        }
+        let mut addresses: Vec<(&String, &String)> = event.addresses.iter().collect();
+        if let (Some(host), Some(address)) = (&event.host, &event.new_address) {
+            addresses.push((host, address));
+        }
+        let mut messages = vec![];
+        for (host, address) in addresses {
+            if !self.discovery_prefix.is_empty() {
+                messages.push(self.discovery_message(event, host));
+            }
+            messages.push((self.address_topic(event, host), true, address.clone()));
+        }
+        let payload = serde_json::to_string(event).wrap_err("Event cannot be serialized")?;
+        let topic = format!("{}/events/{}", self.base_topic(event), event.event);
+        messages.push((topic, false, payload));
+        Ok(messages)
+    }
+
+    /// A Home Assistant discovery config for a sensor with the host's address.
+    fn discovery_message(&self, event: &Event, host: &str) -> Message {
+        let device_id = format!("ddns_monitor_{}", object_id(&event.monitor_host));
+        let object_id = format!("{device_id}_{}", object_id(host));
+        let topic = format!("{}/sensor/{object_id}/config", self.discovery_prefix);
+        let config = json!({
+            "name": format!("{host} address"),
+            "unique_id": object_id,
+            "state_topic": self.address_topic(event, host),
+            "icon": "mdi:ip-network",
+            "device": {
+                "identifiers": [device_id],
+                "name": format!("DDNS Monitor on {}", event.monitor_host),
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        (topic, true, config.to_string())
+    }
+
+    pub fn publish(&self, event: &Event) -> Result<()> {
+        let messages = self.messages(event)?;
+        let client_id = format!("ddns-monitor-{}", std::process::id());
+        let mut options = MqttOptions::new(client_id, &self.host, self.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !self.username.is_empty() {
+            options.set_credentials(&self.username, decrypt_secret(&self.encrypted_password)?);
+        }
+        if self.use_tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, mut connection) = Client::new(options, messages.len() + 1);
+        for (topic, retain, payload) in messages.iter() {
+            client
+                .publish(topic, QoS::AtLeastOnce, *retain, payload.as_bytes())
+                .wrap_err("MQTT publish failed")?;
+        }
+        let deadline = Instant::now() + BROKER_TIMEOUT;
+        let mut acknowledged = 0;
+        while acknowledged < messages.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match connection.recv_timeout(remaining) {
+                Ok(Ok(rumqttc::Event::Incoming(Packet::PubAck(_)))) => acknowledged += 1,
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => {
+                    return Err(err).wrap_err(format!("MQTT connection to {} failed", self.host))
+                }
+                Err(_) => return Err(eyre!("MQTT broker {} did not respond", self.host)),
+            }
+        }
+        client.disconnect().wrap_err("MQTT disconnect failed")?;
+        // the disconnect is only sent as the connection is driven
+        while let Ok(Ok(event)) = connection.recv_timeout(Duration::from_secs(5)) {
+            if let rumqttc::Event::Outgoing(Outgoing::Disconnect) = event {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn interview() -> Result<Self> {
+        let host: String = dialoguer::Input::new()
+            .with_prompt("MQTT broker host")
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let use_tls = dialoguer::Confirm::new()
+            .with_prompt("Connect with TLS?")
+            .default(false)
+            .interact()
+            .wrap_err("Input error")?;
+        let port: u16 = dialoguer::Input::new()
+            .with_prompt("MQTT broker port")
+            .default(if use_tls { 8883 } else { 1883 })
+            .interact_text()
+            .wrap_err("Input error")?;
+        let mut mqtt = Self::new(host.trim(), port);
+        mqtt.use_tls = use_tls;
+        mqtt.username = dialoguer::Input::<String>::new()
+            .with_prompt("Username (leave empty for none)")
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Input error")?
+            .trim()
+            .to_string();
+        if !mqtt.username.is_empty() {
+            let password: String = dialoguer::Input::new()
+                .with_prompt("Password")
+                .allow_empty(true)
+                .interact_text()
+                .wrap_err("Input error")?;
+            mqtt.encrypted_password = encrypt_secret(&password)?;
+        }
+        mqtt.topic_prefix = dialoguer::Input::new()
+            .with_prompt("Topic prefix")
+            .with_initial_text(Self::default_topic_prefix())
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?;
+        if dialoguer::Confirm::new()
+            .with_prompt("Publish Home Assistant discovery configs?")
+            .default(false)
+            .interact()
+            .wrap_err("Input error")?
+        {
+            mqtt.discovery_prefix = String::from("homeassistant");
+        }
+        Ok(mqtt)
+    }
+}
+
+/// MQTT topic levels can't contain wildcards or separators.
+fn topic_level(name: &str) -> String {
+    name.replace(['/', '+', '#'], "_")
+}
+
+/// Home Assistant object IDs may only contain letters, digits, underscores and hyphens.
+fn object_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notify::monitor_host;
+    use crate::{Configuration, State};
+
+    use super::super::stub::{MqttStub, StubPublish};
+    use super::*;
+
+    #[test]
+    fn test_publish_change() {
+        let broker = MqttStub::start();
+        let mqtt = Mqtt::new("127.0.0.1", broker.port);
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        mqtt.publish(&event).expect("MQTT publish failed");
+        let publishes = broker.publishes();
+        let base = format!("ddns-monitor/{}", topic_level(&monitor_host()));
+        assert_eq!(publishes.len(), 2);
+        assert_eq!(
+            publishes[0],
+            StubPublish {
+                topic: format!("{base}/hosts/some.host/address"),
+                retain: true,
+                payload: String::from("5.6.7.8"),
+            }
+        );
+        assert_eq!(publishes[1].topic, format!("{base}/events/change"));
+        assert!(!publishes[1].retain);
+        let payload: serde_json::Value = serde_json::from_str(&publishes[1].payload).unwrap();
+        assert_eq!(payload["oldAddress"], "1.2.3.4");
+    }
+
+    #[test]
+    fn test_publish_initial_with_discovery() {
+        let broker = MqttStub::start();
+        let mut mqtt = Mqtt::new("127.0.0.1", broker.port);
+        mqtt.discovery_prefix = String::from("homeassistant");
+        let mut config = Configuration::default();
+        config.state = State::from([
+            ("a.example".to_string(), "1.2.3.4".to_string()),
+            ("b.example".to_string(), "5.6.7.8".to_string()),
+        ]);
+        let event = Event::initial(&config);
+        mqtt.publish(&event).expect("MQTT publish failed");
+        let publishes = broker.publishes();
+        assert_eq!(publishes.len(), 5);
+        let discovery = &publishes[2];
+        let device_id = format!("ddns_monitor_{}", object_id(&monitor_host()));
+        assert_eq!(
+            discovery.topic,
+            format!("homeassistant/sensor/{device_id}_b_example/config")
+        );
+        assert!(discovery.retain);
+        let config: serde_json::Value = serde_json::from_str(&discovery.payload).unwrap();
+        assert_eq!(config["state_topic"], publishes[3].topic);
+        assert_eq!(publishes[3].payload, "5.6.7.8");
+        assert!(publishes[4].topic.ends_with("/events/initial"));
+    }
+
+    #[test]
+    fn test_publish_broker_hangs_up() {
+        // a listener that closes every connection without answering
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                drop(stream);
+            }
+        });
+        let mqtt = Mqtt::new("127.0.0.1", port);
+        let event = Event::error(&eyre!("some error"));
+        let err = mqtt.publish(&event).expect_err("MQTT publish succeeded");
+        assert!(err.to_string().contains("MQTT connection"));
+    }
+}
@@ -0,0 +1,764 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use eyre::{eyre, Result, WrapErr};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::{decrypt_secret, encrypt_secret, http_agent, percent_encode};
+
+/// How the user grants ddns-monitor access to their mail account.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OAuth2Flow {
+    /// The user signs in with a browser on this machine, which is
+    /// then redirected to a temporary listener on the loopback interface.
+    #[default]
+    Loopback,
+    /// The user signs in on any device by entering a code that we display.
+    DeviceCode,
+}
+
+impl OAuth2Flow {
+    pub const ALL: [OAuth2Flow; 2] = [OAuth2Flow::Loopback, OAuth2Flow::DeviceCode];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            OAuth2Flow::Loopback => "Browser on this machine (loopback redirect)",
+            OAuth2Flow::DeviceCode => "Browser on any device (device code)",
+        }
+    }
+}
+
+/// Mail providers whose OAuth2 endpoints we know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Google,
+    Microsoft,
+    Other,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 3] = [Provider::Google, Provider::Microsoft, Provider::Other];
+
+    /// The provider that most likely runs the given SMTP server.
+    pub fn guess(server: &str) -> Self {
+        let server = server.to_ascii_lowercase();
+        if server.ends_with("gmail.com") || server.ends_with("google.com") {
+            Provider::Google
+        } else if server.ends_with("office365.com") || server.ends_with("outlook.com") {
+            Provider::Microsoft
+        } else {
+            Provider::Other
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Provider::Google => "Google (Gmail and Google Workspace)",
+            Provider::Microsoft => "Microsoft 365 (Outlook and Exchange Online)",
+            Provider::Other => "Other (enter the endpoints yourself)",
+        }
+    }
+}
+
+/// An OAuth2 client registration and the refresh token it was granted.
+///
+/// Access tokens are short-lived, so they are never saved. Instead they
+/// are obtained with the refresh token when needed and cached in memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuth2 {
+    pub flow: OAuth2Flow,
+    /// Where the browser is sent to sign in (loopback flow only).
+    #[serde(default)]
+    pub authorization_url: String,
+    /// Where device codes are requested (device code flow only).
+    #[serde(default)]
+    pub device_authorization_url: String,
+    pub token_url: String,
+    pub scope: String,
+    pub client_id: String,
+    #[serde(default)]
+    encrypted_client_secret: String,
+    encrypted_refresh_token: String,
+}
+
+const GOOGLE_AUTHORIZATION_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_SCOPE: &str = "https://mail.google.com/";
+const MICROSOFT_LOGIN_URL: &str = "https://login.microsoftonline.com";
+const MICROSOFT_SCOPE: &str = "https://outlook.office.com/SMTP.Send offline_access";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Access tokens this close to expiry are refreshed rather than used.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+struct CachedTokens {
+    refresh_token: String,
+    access_token: String,
+    expires_at: Instant,
+}
+
+/// Cached tokens, keyed by the encrypted refresh token in the configuration.
+static TOKENS: Mutex<BTreeMap<String, CachedTokens>> = Mutex::new(BTreeMap::new());
+
+/// What the token endpoint granted.
+struct Tokens {
+    access_token: String,
+    expires_in: u64,
+    refresh_token: Option<String>,
+}
+
+/// The token endpoint's answer to a request.
+enum TokenReply {
+    Granted(Tokens),
+    Refused { error: String, reason: String },
+}
+
+impl OAuth2 {
+    pub fn new(flow: OAuth2Flow, token_url: &str, scope: &str, client_id: &str) -> Self {
+        Self {
+            flow,
+            token_url: token_url.to_string(),
+            scope: scope.to_string(),
+            client_id: client_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_client_secret(&mut self, secret: &str) -> Result<()> {
+        self.encrypted_client_secret = encrypt_secret(secret)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn set_refresh_token(&mut self, token: &str) -> Result<()> {
+        self.encrypted_refresh_token = encrypt_secret(token)?;
+        Ok(())
+    }
+
+    pub fn is_authorized(&self) -> bool {
+        !self.encrypted_refresh_token.is_empty()
+    }
+
+    /// A current access token, refreshed if the cached one is missing or expiring.
+    pub fn access_token(&self) -> Result<String> {
+        let mut cache = TOKENS.lock().unwrap_or_else(|err| err.into_inner());
+        let refresh_token = match cache.get(&self.encrypted_refresh_token) {
+            Some(cached) if cached.expires_at > Instant::now() + EXPIRY_MARGIN => {
+                return Ok(cached.access_token.clone());
+            }
+            Some(cached) => cached.refresh_token.clone(),
+            None => decrypt_secret(&self.encrypted_refresh_token)?,
+        };
+        if refresh_token.is_empty() {
+            return Err(eyre!(
+                "OAuth2 access has not been authorized; run the configure command"
+            ));
+        }
+        let grant = [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ];
+        match self.token_request(&grant)? {
+            TokenReply::Granted(tokens) => {
+                let access_token = tokens.access_token.clone();
+                let cached = CachedTokens::new(tokens, &refresh_token);
+                cache.insert(self.encrypted_refresh_token.clone(), cached);
+                Ok(access_token)
+            }
+            TokenReply::Refused { reason, .. } => Err(eyre!(
+                "OAuth2 token refresh was refused ({reason}); run the configure command to authorize again"
+            )),
+        }
+    }
+
+    /// Providers may issue a new refresh token when an access token is
+    /// refreshed. If that has happened, store the new one in place of the
+    /// old one so that it is saved with the configuration.
+    ///
+    /// Returns whether the stored token changed.
+    pub fn update_refresh_token(&mut self) -> Result<bool> {
+        let mut cache = TOKENS.lock().unwrap_or_else(|err| err.into_inner());
+        let Some(cached) = cache.get(&self.encrypted_refresh_token) else {
+            return Ok(false);
+        };
+        if cached.refresh_token == decrypt_secret(&self.encrypted_refresh_token)? {
+            return Ok(false);
+        }
+        let encrypted = encrypt_secret(&cached.refresh_token)?;
+        if let Some(cached) = cache.remove(&self.encrypted_refresh_token) {
+            cache.insert(encrypted.clone(), cached);
+        }
+        self.encrypted_refresh_token = encrypted;
+        Ok(true)
+    }
+
+    /// Run the authorization flow and store the refresh token it grants.
+    pub fn authorize(&mut self) -> Result<()> {
+        let tokens = match self.flow {
+            OAuth2Flow::Loopback => self.loopback_flow()?,
+            OAuth2Flow::DeviceCode => self.device_code_flow()?,
+        };
+        let Some(refresh_token) = tokens.refresh_token.clone() else {
+            return Err(eyre!(
+                "The authorization server didn't grant a refresh token"
+            ));
+        };
+        self.encrypted_refresh_token = encrypt_secret(&refresh_token)?;
+        let cached = CachedTokens::new(tokens, &refresh_token);
+        let mut cache = TOKENS.lock().unwrap_or_else(|err| err.into_inner());
+        cache.insert(self.encrypted_refresh_token.clone(), cached);
+        Ok(())
+    }
+
+    fn loopback_flow(&self) -> Result<Tokens> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .wrap_err("Can't listen for the authorization redirect")?;
+        let port = listener.local_addr()?.port();
+        let redirect_uri = format!("http://127.0.0.1:{port}");
+        let verifier = random_string(64);
+        let state = random_string(24);
+        let query = form_encode(&[
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("redirect_uri", &redirect_uri),
+            ("scope", &self.scope),
+            ("state", &state),
+            ("code_challenge", &pkce_challenge(&verifier)),
+            ("code_challenge_method", "S256"),
+            // Google only grants refresh tokens for offline access
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+        ]);
+        eprintln!("To authorize sending mail, open this URL in a browser on this machine:");
+        eprintln!("{}?{query}", self.authorization_url);
+        eprintln!("(On a machine without a browser, first forward the port with `ssh -L {port}:127.0.0.1:{port}`.)");
+        let code = receive_authorization_code(&listener, &state)?;
+        self.grant(&[
+            ("grant_type", "authorization_code"),
+            ("code", &code),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", &verifier),
+        ])
+    }
+
+    fn device_code_flow(&self) -> Result<Tokens> {
+        let url = self.device_authorization_url.as_str();
+        let reply = post_form(
+            url,
+            &[("client_id", &self.client_id), ("scope", &self.scope)],
+        )?;
+        if let Some((_, reason)) = refusal(&reply) {
+            return Err(eyre!("Device authorization failed: {reason}"));
+        }
+        let device_code = reply["device_code"]
+            .as_str()
+            .ok_or_else(|| eyre!("No device code in the response from {url}"))?;
+        let user_code = reply["user_code"].as_str().unwrap_or_default();
+        // the standard says verification_uri, but Google says verification_url
+        let verification_uri = reply["verification_uri"]
+            .as_str()
+            .or(reply["verification_url"].as_str())
+            .unwrap_or_default();
+        eprintln!(
+            "To authorize sending mail, visit {verification_uri} and enter the code {user_code}"
+        );
+        eprintln!("Waiting for authorization...");
+        let mut interval = reply["interval"].as_u64().unwrap_or(5);
+        let expires_in = reply["expires_in"].as_u64().unwrap_or(900);
+        let deadline = Instant::now() + Duration::from_secs(expires_in);
+        while Instant::now() < deadline {
+            std::thread::sleep(Duration::from_secs(interval));
+            let grant = [
+                ("grant_type", DEVICE_CODE_GRANT),
+                ("device_code", device_code),
+            ];
+            match self.token_request(&grant)? {
+                TokenReply::Granted(tokens) => return Ok(tokens),
+                TokenReply::Refused { error, .. } if error == "authorization_pending" => {}
+                TokenReply::Refused { error, .. } if error == "slow_down" => interval += 5,
+                TokenReply::Refused { reason, .. } => {
+                    return Err(eyre!("Authorization failed: {reason}"))
+                }
+            }
+        }
+        Err(eyre!("Authorization was not completed in time"))
+    }
+
+    /// Request tokens, treating a refusal as an error.
+    fn grant(&self, params: &[(&str, &str)]) -> Result<Tokens> {
+        match self.token_request(params)? {
+            TokenReply::Granted(tokens) => Ok(tokens),
+            TokenReply::Refused { reason, .. } => Err(eyre!("Authorization failed: {reason}")),
+        }
+    }
+
+    fn token_request(&self, grant: &[(&str, &str)]) -> Result<TokenReply> {
+        let secret = decrypt_secret(&self.encrypted_client_secret)?;
+        let mut params = vec![("client_id", self.client_id.as_str())];
+        if !secret.is_empty() {
+            params.push(("client_secret", secret.as_str()));
+        }
+        params.extend_from_slice(grant);
+        let reply = post_form(&self.token_url, &params)?;
+        if let Some((error, reason)) = refusal(&reply) {
+            return Ok(TokenReply::Refused { error, reason });
+        }
+        let access_token = reply["access_token"]
+            .as_str()
+            .ok_or_else(|| eyre!("No access token in the response from {}", self.token_url))?;
+        Ok(TokenReply::Granted(Tokens {
+            access_token: access_token.to_string(),
+            expires_in: reply["expires_in"].as_u64().unwrap_or(3600),
+            refresh_token: reply["refresh_token"].as_str().map(String::from),
+        }))
+    }
+
+    /// Ask which provider to use, and run its authorization flow.
+    ///
+    /// An existing authorization can be kept rather than repeated.
+    pub fn interview(server: &str, existing: Option<OAuth2>) -> Result<Self> {
+        if let Some(existing) = existing.filter(|existing| existing.is_authorized()) {
+            let keep = dialoguer::Confirm::new()
+                .with_prompt("Keep the existing OAuth2 authorization")
+                .default(true)
+                .interact()
+                .wrap_err("Input error")?;
+            if keep {
+                return Ok(existing);
+            }
+        }
+        let providers: Vec<&str> = Provider::ALL.iter().map(|p| p.description()).collect();
+        let guess = Provider::guess(server);
+        let provider = dialoguer::Select::new()
+            .with_prompt("OAuth2 provider")
+            .items(&providers)
+            .default(Provider::ALL.iter().position(|p| *p == guess).unwrap_or(0))
+            .interact()
+            .wrap_err("Input error")?;
+        let mut oauth2 = match Provider::ALL[provider] {
+            Provider::Google => {
+                eprintln!("Google requires an OAuth client of type \"Desktop app\" from the Google Cloud console.");
+                let mut oauth2 =
+                    OAuth2::new(OAuth2Flow::Loopback, GOOGLE_TOKEN_URL, GOOGLE_SCOPE, "");
+                oauth2.authorization_url = GOOGLE_AUTHORIZATION_URL.to_string();
+                oauth2
+            }
+            Provider::Microsoft => {
+                eprintln!(
+                    "Microsoft requires an app registration that allows public client flows."
+                );
+                let tenant: String = dialoguer::Input::new()
+                    .with_prompt("Microsoft Entra tenant (domain or ID)")
+                    .default(String::from("common"))
+                    .interact_text()
+                    .wrap_err("Input error")?;
+                let endpoint = format!("{MICROSOFT_LOGIN_URL}/{}/oauth2/v2.0", tenant.trim());
+                let token_url = format!("{endpoint}/token");
+                let mut oauth2 =
+                    OAuth2::new(OAuth2Flow::DeviceCode, &token_url, MICROSOFT_SCOPE, "");
+                oauth2.device_authorization_url = format!("{endpoint}/devicecode");
+                oauth2
+            }
+            Provider::Other => Self::interview_endpoints()?,
+        };
+        oauth2.client_id = dialoguer::Input::<String>::new()
+            .with_prompt("OAuth2 client ID")
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?
+            .trim()
+            .to_string();
+        let secret: String = dialoguer::Input::new()
+            .with_prompt("OAuth2 client secret (leave empty for none)")
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Input error")?;
+        oauth2.set_client_secret(secret.trim())?;
+        oauth2.authorize()?;
+        Ok(oauth2)
+    }
+
+    fn interview_endpoints() -> Result<Self> {
+        let flows: Vec<&str> = OAuth2Flow::ALL.iter().map(|f| f.description()).collect();
+        let flow = dialoguer::Select::new()
+            .with_prompt("How will you sign in")
+            .items(&flows)
+            .default(0)
+            .interact()
+            .wrap_err("Input error")?;
+        let flow = OAuth2Flow::ALL[flow];
+        let url_prompt = match flow {
+            OAuth2Flow::Loopback => "Authorization endpoint URL",
+            OAuth2Flow::DeviceCode => "Device authorization endpoint URL",
+        };
+        let url = interview_url(url_prompt)?;
+        let token_url = interview_url("Token endpoint URL")?;
+        let scope: String = dialoguer::Input::new()
+            .with_prompt("Scopes to request (separated by spaces)")
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let mut oauth2 = OAuth2::new(flow, &token_url, scope.trim(), "");
+        match flow {
+            OAuth2Flow::Loopback => oauth2.authorization_url = url,
+            OAuth2Flow::DeviceCode => oauth2.device_authorization_url = url,
+        }
+        Ok(oauth2)
+    }
+}
+
+impl CachedTokens {
+    fn new(tokens: Tokens, refresh_token: &str) -> Self {
+        Self {
+            refresh_token: tokens
+                .refresh_token
+                .unwrap_or_else(|| refresh_token.to_string()),
+            access_token: tokens.access_token,
+            expires_at: Instant::now() + Duration::from_secs(tokens.expires_in),
+        }
+    }
+}
+
+fn interview_url(prompt: &str) -> Result<String> {
+    let url: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .with_initial_text("https://")
+        .allow_empty(false)
+        .validate_with(|url: &String| -> std::result::Result<(), String> {
+            if url.starts_with("https://") || url.starts_with("http://") {
+                Ok(())
+            } else {
+                Err(format!("{url} is not an http or https URL"))
+            }
+        })
+        .interact_text()
+        .wrap_err("Input error")?;
+    Ok(url.trim().to_string())
+}
+
+/// Post a form and return the JSON reply, whatever its status,
+/// because OAuth2 endpoints report errors in the body of 400 responses.
+fn post_form(url: &str, params: &[(&str, &str)]) -> Result<Value> {
+    let result = http_agent()
+        .post(url)
+        .set("Accept", "application/json")
+        .send_form(params);
+    let response = match result {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => return Err(err).wrap_err(format!("Can't reach {url}")),
+    };
+    response
+        .into_json()
+        .wrap_err(format!("Invalid response from {url}"))
+}
+
+/// The error code and a description of it, if the reply is an OAuth2 error.
+fn refusal(reply: &Value) -> Option<(String, String)> {
+    let error = reply["error"].as_str()?;
+    let reason = match reply["error_description"].as_str() {
+        Some(description) => format!("{error}: {description}"),
+        None => error.to_string(),
+    };
+    Some((error.to_string(), reason))
+}
+
+/// Wait for the browser to be redirected back to us, and return the authorization code.
+fn receive_authorization_code(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut stream, _) = listener
+            .accept()
+            .wrap_err("Failed waiting for the authorization redirect")?;
+        let mut line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut line)
+            .wrap_err("Failed reading the authorization redirect")?;
+        // the request line looks like: GET /?code=...&state=... HTTP/1.1
+        let target = line.split_whitespace().nth(1).unwrap_or_default();
+        let params = parse_query(target.split_once('?').unwrap_or_default().1);
+        let result = if let Some(error) = params.get("error") {
+            Err(eyre!("Authorization was denied: {error}"))
+        } else if let Some(code) = params.get("code") {
+            if params.get("state").map(String::as_str) == Some(state) {
+                Ok(code.clone())
+            } else {
+                Err(eyre!(
+                    "The authorization redirect was for a different request"
+                ))
+            }
+        } else {
+            // something else, such as a request for a favicon
+            let _ = stream.write_all(
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+            continue;
+        };
+        let message = match result {
+            Ok(_) => "ddns-monitor is now authorized. You can close this window.",
+            Err(_) => "ddns-monitor was not authorized. Please return to the terminal.",
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{message}",
+            message.len()
+        );
+        let _ = stream.write_all(response.as_bytes());
+        return result;
+    }
+}
+
+/// The S256 code challenge for a PKCE code verifier (RFC 7636).
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+fn form_encode(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (percent_decode(name), percent_decode(value)))
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match text
+                .get(i + 1..i + 3)
+                .map(|hex| u8::from_str_radix(hex, 16))
+            {
+                Some(Ok(byte)) => {
+                    decoded.push(byte);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    use super::super::stub::StubServer;
+    use super::*;
+
+    fn test_oauth2(url: &str, refresh_token: &str) -> OAuth2 {
+        let mut oauth2 = OAuth2::new(
+            OAuth2Flow::DeviceCode,
+            &format!("{url}/token"),
+            "smtp",
+            "client",
+        );
+        oauth2.device_authorization_url = format!("{url}/device");
+        oauth2.set_client_secret("secret").unwrap();
+        oauth2.encrypted_refresh_token = encrypt_secret(refresh_token).unwrap();
+        oauth2
+    }
+
+    #[test]
+    fn test_refresh_is_cached() {
+        let server = StubServer::start(vec![(
+            200,
+            r#"{"access_token":"access-1","expires_in":3600}"#,
+        )]);
+        let mut oauth2 = test_oauth2(&server.url, "cached-refresh");
+        assert_eq!(oauth2.access_token().unwrap(), "access-1");
+        let request = server.next_request();
+        assert_eq!(request.path, "/token");
+        assert!(request.body.contains("client_id=client"));
+        assert!(request.body.contains("client_secret=secret"));
+        assert!(request.body.contains("grant_type=refresh_token"));
+        assert!(request.body.contains("refresh_token=cached-refresh"));
+        // the stub has stopped listening, so this must come from the cache
+        assert_eq!(oauth2.access_token().unwrap(), "access-1");
+        assert!(!oauth2.update_refresh_token().unwrap());
+    }
+
+    #[test]
+    fn test_rotated_refresh_token() {
+        let server = StubServer::start(vec![
+            (
+                200,
+                r#"{"access_token":"access-1","expires_in":0,"refresh_token":"rotated-2"}"#,
+            ),
+            (200, r#"{"access_token":"access-2","expires_in":3600}"#),
+        ]);
+        let mut oauth2 = test_oauth2(&server.url, "rotated-1");
+        assert_eq!(oauth2.access_token().unwrap(), "access-1");
+        assert!(server
+            .next_request()
+            .body
+            .contains("refresh_token=rotated-1"));
+        assert!(oauth2.update_refresh_token().unwrap());
+        assert_eq!(
+            decrypt_secret(&oauth2.encrypted_refresh_token).unwrap(),
+            "rotated-2"
+        );
+        // the first access token has expired, so the new refresh token is used
+        assert_eq!(oauth2.access_token().unwrap(), "access-2");
+        assert!(server
+            .next_request()
+            .body
+            .contains("refresh_token=rotated-2"));
+    }
+
+    #[test]
+    fn test_rotated_token_is_kept_by_configuration() {
+        let server = StubServer::start(vec![(
+            200,
+            r#"{"access_token":"access-1","expires_in":3600,"refresh_token":"kept-2"}"#,
+        )]);
+        let mut config = crate::Configuration::default();
+        config.smtp.oauth2 = Some(test_oauth2(&server.url, "kept-1"));
+        let oauth2 = config.smtp.oauth2.as_ref().unwrap();
+        assert_eq!(oauth2.access_token().unwrap(), "access-1");
+        // a configuration that isn't file-based is updated but not saved
+        config.save_refresh_token().unwrap();
+        let oauth2 = config.smtp.oauth2.as_ref().unwrap();
+        assert_eq!(
+            decrypt_secret(&oauth2.encrypted_refresh_token).unwrap(),
+            "kept-2"
+        );
+    }
+
+    #[test]
+    fn test_refused_refresh() {
+        let server = StubServer::start(vec![(
+            400,
+            r#"{"error":"invalid_grant","error_description":"Token has been revoked."}"#,
+        )]);
+        let oauth2 = test_oauth2(&server.url, "revoked-refresh");
+        let err = oauth2
+            .access_token()
+            .expect_err("Revoked token was refreshed");
+        assert!(err
+            .to_string()
+            .contains("invalid_grant: Token has been revoked."));
+        let unauthorized = OAuth2::new(OAuth2Flow::Loopback, &server.url, "smtp", "client");
+        let err = unauthorized
+            .access_token()
+            .expect_err("Unauthorized client got a token");
+        assert!(err.to_string().contains("not been authorized"));
+    }
+
+    #[test]
+    fn test_device_code_flow() {
+        let server = StubServer::start(vec![
+            (
+                200,
+                r#"{"device_code":"device","user_code":"ABCD-EFGH","verification_uri":"https://example.com/device","expires_in":60,"interval":0}"#,
+            ),
+            (400, r#"{"error":"authorization_pending"}"#),
+            (
+                200,
+                r#"{"access_token":"device-access","expires_in":3600,"refresh_token":"device-refresh"}"#,
+            ),
+        ]);
+        let mut oauth2 = test_oauth2(&server.url, "");
+        oauth2.authorize().expect("Device code flow failed");
+        let request = server.next_request();
+        assert_eq!(request.path, "/device");
+        assert!(request.body.contains("scope=smtp"));
+        for _ in 0..2 {
+            let request = server.next_request();
+            assert_eq!(request.path, "/token");
+            assert!(request.body.contains("device_code=device"));
+        }
+        assert!(oauth2.is_authorized());
+        assert_eq!(
+            decrypt_secret(&oauth2.encrypted_refresh_token).unwrap(),
+            "device-refresh"
+        );
+        // the access token from the flow is cached
+        assert_eq!(oauth2.access_token().unwrap(), "device-access");
+    }
+
+    #[test]
+    fn test_loopback_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let waiter = std::thread::spawn(move || receive_authorization_code(&listener, "xyz"));
+        let mut responses = vec![];
+        for target in ["/favicon.ico", "/?state=xyz&code=a%2Fb+c"] {
+            let mut browser = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            browser
+                .write_all(format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+                .unwrap();
+            let mut response = String::new();
+            browser.read_to_string(&mut response).unwrap();
+            responses.push(response);
+        }
+        assert!(responses[0].starts_with("HTTP/1.1 404"));
+        assert!(responses[1].contains("now authorized"));
+        assert_eq!(waiter.join().unwrap().unwrap(), "a/b c");
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // the example from RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
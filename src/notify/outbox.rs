@@ -0,0 +1,280 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Local};
+use eyre::{Report, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use super::{state_file, Event};
+
+/// How long undelivered notifications are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxOptions {
+    /// Deliveries that have been failing for this long are marked stale and no longer retried.
+    #[serde(default = "OutboxOptions::default_stale_after_hours")]
+    pub stale_after_hours: u64,
+}
+
+impl Default for OutboxOptions {
+    fn default() -> Self {
+        Self {
+            stale_after_hours: Self::default_stale_after_hours(),
+        }
+    }
+}
+
+impl OutboxOptions {
+    fn default_stale_after_hours() -> u64 {
+        24
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        Duration::hours(self.stale_after_hours.try_into().unwrap_or(i64::MAX))
+    }
+
+    pub fn interview(&self) -> Result<Self> {
+        let stale_after_hours = dialoguer::Input::new()
+            .with_prompt("Hours to keep retrying notifications that can't be delivered")
+            .default(self.stale_after_hours)
+            .interact_text()
+            .wrap_err("Input error")?;
+        Ok(Self { stale_after_hours })
+    }
+}
+
+/// The first retry waits this long, and each one after that waits twice as long...
+const FIRST_RETRY_SECS: i64 = 60;
+/// ... up to this long.
+const MAX_RETRY_SECS: i64 = 3600;
+
+/// A notification that couldn't be delivered to one of its destinations.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingDelivery {
+    /// The email or channel destination, as named in the log.
+    pub destination: String,
+    /// What the destination is found by when retrying, which (unlike its name)
+    /// tells destinations apart and doesn't change with their other settings.
+    #[serde(default)]
+    pub destination_id: String,
+    pub event: Event,
+    pub attempts: u32,
+    pub next_attempt: DateTime<Local>,
+    pub last_error: String,
+    #[serde(default)]
+    pub stale: bool,
+}
+
+impl PendingDelivery {
+    pub fn is_due(&self, now: DateTime<Local>) -> bool {
+        !self.stale && self.next_attempt <= now
+    }
+
+    /// Record a failed attempt, and schedule the next one.
+    pub fn failed(&mut self, err: &Report, now: DateTime<Local>) {
+        self.attempts += 1;
+        self.last_error = format!("{err:#}");
+        let delay = FIRST_RETRY_SECS
+            .saturating_mul(1 << self.attempts.saturating_sub(1).min(16))
+            .min(MAX_RETRY_SECS);
+        self.next_attempt = now + Duration::seconds(delay);
+    }
+}
+
+impl Display for PendingDelivery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = if self.stale {
+            String::from("stale, no longer retried")
+        } else {
+            format!("next attempt at {}", self.next_attempt.to_rfc2822())
+        };
+        write!(
+            f,
+            "{}: \"{}\" from {} ({} failed attempts, {status})\n    Last error: {}",
+            self.destination,
+            self.event.subject,
+            self.event.timestamp.to_rfc2822(),
+            self.attempts,
+            self.last_error,
+        )
+    }
+}
+
+/// Notifications waiting to be retried, kept in a file next to the configuration
+/// so that they survive restarts.
+///
+/// The file is locked from loading until the outbox is dropped.
+#[derive(Debug)]
+pub struct Outbox {
+    path: PathBuf,
+    deliveries: Vec<PendingDelivery>,
+    _lock: state_file::Lock,
+}
+
+impl Outbox {
+    pub fn path() -> Result<PathBuf> {
+        state_file::path("outbox.json")
+    }
+
+    /// The outbox stored at the given path, which is empty if there's no file there.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let lock = state_file::lock(&path, "Outbox")?;
+        let deliveries = state_file::load(&path, "Outbox")?.unwrap_or_default();
+        Ok(Self {
+            path,
+            deliveries,
+            _lock: lock,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        state_file::save_atomic(&self.path, &self.deliveries, "Outbox")
+    }
+
+    pub fn deliveries(&self) -> &[PendingDelivery] {
+        &self.deliveries
+    }
+
+    pub fn deliveries_mut(&mut self) -> &mut Vec<PendingDelivery> {
+        &mut self.deliveries
+    }
+
+    pub fn add(&mut self, destination: String, destination_id: String, event: Event, err: &Report) {
+        let now = Local::now();
+        let mut delivery = PendingDelivery {
+            destination,
+            destination_id,
+            event,
+            attempts: 0,
+            next_attempt: now,
+            last_error: String::new(),
+            stale: false,
+        };
+        delivery.failed(err, now);
+        self.deliveries.push(delivery);
+    }
+
+    /// Mark deliveries of events older than the given age as stale,
+    /// and return the ones that were newly marked.
+    pub fn mark_stale(&mut self, age: Duration, now: DateTime<Local>) -> Vec<PendingDelivery> {
+        let mut marked = vec![];
+        for delivery in self.deliveries.iter_mut() {
+            if !delivery.stale && delivery.event.timestamp + age <= now {
+                delivery.stale = true;
+                marked.push(delivery.clone());
+            }
+        }
+        marked
+    }
+
+    /// Remove the stale deliveries, and return how many there were.
+    pub fn purge_stale(&mut self) -> usize {
+        let count = self.deliveries.len();
+        self.deliveries.retain(|delivery| !delivery.stale);
+        count - self.deliveries.len()
+    }
+
+    /// When the next retry is due, if there is one.
+    pub fn next_attempt(&self) -> Option<DateTime<Local>> {
+        self.deliveries
+            .iter()
+            .filter(|delivery| !delivery.stale)
+            .map(|delivery| delivery.next_attempt)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::state_file::tests::TempFile;
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let now = Local::now();
+        let file = TempFile::new("outbox-backoff");
+        let mut outbox = Outbox::load(file.path()).unwrap();
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        outbox.add(
+            String::from("email to a@example.com"),
+            String::from("email:a@example.com"),
+            event,
+            &eyre::eyre!("refused"),
+        );
+        let delivery = &mut outbox.deliveries_mut()[0];
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.last_error, "refused");
+        assert!(!delivery.is_due(now));
+        assert!(delivery.is_due(now + Duration::seconds(61)));
+        let mut delays = vec![];
+        for _ in 0..8 {
+            delivery.failed(&eyre::eyre!("refused again"), now);
+            delays.push((delivery.next_attempt - now).num_seconds());
+        }
+        assert_eq!(delays, vec![120, 240, 480, 960, 1920, 3600, 3600, 3600]);
+    }
+
+    #[test]
+    fn test_save_load_and_staleness() {
+        let now = Local::now();
+        let file = TempFile::new("outbox-stale");
+        let mut outbox = Outbox::load(file.path()).unwrap();
+        let mut old = Event::change("old.host", "1.2.3.4", "5.6.7.8");
+        old.timestamp = now - Duration::hours(30);
+        let new = Event::error(&eyre::eyre!("lookup failed"));
+        outbox.add(
+            String::from("email to a@example.com"),
+            String::from("email:a@example.com"),
+            old,
+            &eyre::eyre!("refused"),
+        );
+        outbox.add(
+            String::from("command hook /bin/false"),
+            String::from("command:0123456789abcdef"),
+            new,
+            &eyre::eyre!("exit 1"),
+        );
+        outbox.save().unwrap();
+        let saved = outbox.deliveries().to_vec();
+        drop(outbox);
+        let mut loaded = Outbox::load(file.path()).unwrap();
+        assert_eq!(loaded.deliveries(), saved);
+        let stale = loaded.mark_stale(OutboxOptions::default().stale_after(), now);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].event.host.as_deref(), Some("old.host"));
+        assert!(loaded.mark_stale(Duration::hours(24), now).is_empty());
+        assert_eq!(
+            loaded.next_attempt(),
+            Some(loaded.deliveries()[1].next_attempt)
+        );
+        assert!(loaded.deliveries()[0]
+            .to_string()
+            .contains("stale, no longer retried"));
+        assert_eq!(loaded.purge_stale(), 1);
+        assert_eq!(loaded.deliveries().len(), 1);
+    }
+}
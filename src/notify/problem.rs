@@ -0,0 +1,249 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Local};
+use eyre::{Report, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::LookupFailure;
+
+use super::locale::fill;
+use super::{state_file, Event};
+
+/// How repeated errors and their ends are notified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorOptions {
+    /// Remind of an error that is still happening after this many hours (0 for never).
+    #[serde(default = "ErrorOptions::default_reminder_hours")]
+    pub reminder_hours: u64,
+    /// Notify when an error stops happening.
+    #[serde(default = "serde_aux::field_attributes::bool_true")]
+    pub notify_recovery: bool,
+}
+
+impl Default for ErrorOptions {
+    fn default() -> Self {
+        Self {
+            reminder_hours: Self::default_reminder_hours(),
+            notify_recovery: true,
+        }
+    }
+}
+
+impl ErrorOptions {
+    fn default_reminder_hours() -> u64 {
+        24
+    }
+
+    pub fn reminder_interval(&self) -> Option<Duration> {
+        match self.reminder_hours {
+            0 => None,
+            hours => Some(Duration::hours(hours.try_into().unwrap_or(i64::MAX))),
+        }
+    }
+
+    pub fn interview(&self) -> Result<Self> {
+        eprintln!("An error is notified once, rather than on every check that fails.");
+        let reminder_hours = dialoguer::Input::new()
+            .with_prompt("Hours between reminders of an error that continues (0 for none)")
+            .default(self.reminder_hours)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let notify_recovery = dialoguer::Confirm::new()
+            .with_prompt("Notify when monitoring recovers from an error?")
+            .default(self.notify_recovery)
+            .interact()
+            .wrap_err("Input error")?;
+        Ok(Self {
+            reminder_hours,
+            notify_recovery,
+        })
+    }
+}
+
+/// An error that has happened on one or more checks in a row.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Problem {
+    /// The type of error, which together with the host identifies repeats of it.
+    pub kind: String,
+    pub host: Option<String>,
+    /// The most recent message of the error.
+    pub error: String,
+    pub started: DateTime<Local>,
+    pub last_notified: DateTime<Local>,
+    pub failed_checks: u32,
+}
+
+impl Problem {
+    /// The type and host of an error.
+    fn identify(err: &Report) -> (String, Option<String>) {
+        match err.downcast_ref::<LookupFailure>() {
+            Some(failure) => (failure.kind().to_string(), Some(failure.host().to_string())),
+            None => (err.to_string(), None),
+        }
+    }
+}
+
+/// The errors that are still happening, kept in a file next to the
+/// configuration so that they are recognized across restarts.
+#[derive(Debug)]
+pub struct Problems {
+    path: PathBuf,
+    problems: Vec<Problem>,
+}
+
+impl Problems {
+    pub fn path() -> Result<PathBuf> {
+        state_file::path("problems.json")
+    }
+
+    /// The problems stored at the given path, which are none if there's no file there.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let problems = state_file::load(&path, "Problems")?.unwrap_or_default();
+        Ok(Self { path, problems })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        state_file::save_atomic(&self.path, &self.problems, "Problems")
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Record a failed check, and return the event to notify of it:
+    /// an error that isn't already happening, or a reminder of one that is.
+    pub fn failed(
+        &mut self,
+        options: &ErrorOptions,
+        err: &Report,
+        now: DateTime<Local>,
+    ) -> Option<Event> {
+        let (kind, host) = Problem::identify(err);
+        let Some(problem) = self
+            .problems
+            .iter_mut()
+            .find(|problem| problem.kind == kind && problem.host == host)
+        else {
+            self.problems.push(Problem {
+                kind,
+                host,
+                error: err.to_string(),
+                started: now,
+                last_notified: now,
+                failed_checks: 1,
+            });
+            return Some(Event::error(err));
+        };
+        problem.failed_checks += 1;
+        problem.error = err.to_string();
+        let interval = options.reminder_interval()?;
+        if problem.last_notified + interval > now {
+            return None;
+        }
+        problem.last_notified = now;
+        let mut event = Event::error(err);
+        let started = problem.started;
+        let count = problem.failed_checks.to_string();
+        event.edit_text(|locale, catalog, text| {
+            text.subject = fill(catalog.reminder_subject, &[("subject", &text.subject)]);
+            let time = locale.format_time(started);
+            let line = fill(
+                catalog.reminder_started,
+                &[("time", &time), ("count", &count)],
+            );
+            text.body.insert(0, line);
+        });
+        Some(event)
+    }
+
+    /// Record a successful check, which ends every problem,
+    /// and return the events to notify of their ends.
+    pub fn succeeded(&mut self, options: &ErrorOptions) -> Vec<Event> {
+        let ended = std::mem::take(&mut self.problems);
+        if !options.notify_recovery {
+            return vec![];
+        }
+        ended
+            .iter()
+            .map(|problem| {
+                Event::recovery(
+                    &problem.error,
+                    problem.host.as_deref(),
+                    problem.started,
+                    problem.failed_checks,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::state_file::tests::TempFile;
+    use super::*;
+
+    use eyre::eyre;
+
+    #[test]
+    fn test_repeats_reminders_and_recovery() {
+        let file = TempFile::new("problems");
+        let path = file.path();
+        let options = ErrorOptions::default();
+        let mut problems = Problems::load(path.clone()).unwrap();
+        let now = Local::now();
+        let lookup = |host: &str| eyre!(LookupFailure::Failed(host.to_string()));
+        assert!(problems.failed(&options, &lookup("a.host"), now).is_some());
+        assert!(problems.failed(&options, &lookup("a.host"), now).is_none());
+        assert!(problems.failed(&options, &lookup("b.host"), now).is_some());
+        assert!(problems
+            .failed(&options, &eyre!("Disk full"), now)
+            .is_some());
+        problems.save().unwrap();
+        let mut problems = Problems::load(path.clone()).unwrap();
+        assert_eq!(problems.problems().len(), 3);
+        let later = now + Duration::hours(25);
+        let reminder = problems
+            .failed(&options, &lookup("a.host"), later)
+            .expect("no reminder");
+        assert_eq!(
+            reminder.subject,
+            "Reminder: DNS monitoring temporary failure"
+        );
+        assert!(reminder.body[0].ends_with("has failed 3 checks."));
+        assert!(problems
+            .failed(&options, &lookup("a.host"), later)
+            .is_none());
+        let recoveries = problems.succeeded(&options);
+        assert_eq!(recoveries.len(), 3);
+        assert_eq!(recoveries[0].subject, "DNS monitoring of a.host recovered");
+        assert!(recoveries[0].body[2].contains("over 4 failed checks"));
+        assert_eq!(recoveries[2].subject, "DNS monitoring recovered");
+        assert!(problems.problems().is_empty());
+        assert!(problems.succeeded(&options).is_empty());
+    }
+}
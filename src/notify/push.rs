@@ -0,0 +1,400 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::ops::RangeInclusive;
+
+use eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{check_response, decrypt_secret, encrypt_secret, http_agent, send_json};
+use super::{Event, EventKind};
+
+/// The priority of a push notification for each type of event.
+///
+/// The meaning of the numbers depends on the push service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Priorities {
+    pub initial: i8,
+    pub change: i8,
+    pub error: i8,
+}
+
+impl Priorities {
+    pub fn get(&self, kind: EventKind) -> i8 {
+        match kind {
+            EventKind::Initial | EventKind::Recovery | EventKind::Heartbeat | EventKind::Report => {
+                self.initial
+            }
+            EventKind::Change | EventKind::Digest => self.change,
+            EventKind::Error => self.error,
+        }
+    }
+
+    /// The priority of an event, which for a digest is that of its most urgent part.
+    pub fn for_event(&self, event: &Event) -> i8 {
+        event
+            .parts()
+            .iter()
+            .map(|part| self.get(part.event))
+            .max()
+            .unwrap_or(self.change)
+    }
+
+    fn interview(&self, range: RangeInclusive<i8>) -> Result<Self> {
+        let ask = |kind: &str, initial: i8| -> Result<i8> {
+            dialoguer::Input::new()
+                .with_prompt(format!(
+                    "Priority of {kind} ({} to {})",
+                    range.start(),
+                    range.end()
+                ))
+                .with_initial_text(initial.to_string())
+                .validate_with(|priority: &i8| -> std::result::Result<(), String> {
+                    if range.contains(priority) {
+                        Ok(())
+                    } else {
+                        Err(format!("{priority} is not a valid priority"))
+                    }
+                })
+                .interact_text()
+                .wrap_err("Input error")
+        };
+        Ok(Self {
+            initial: ask("monitoring status messages", self.initial)?,
+            change: ask("address changes", self.change)?,
+            error: ask("monitoring errors", self.error)?,
+        })
+    }
+}
+
+fn interview_url(prompt: &str, initial: &str) -> Result<String> {
+    let url: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .with_initial_text(initial)
+        .allow_empty(false)
+        .validate_with(|url: &String| -> std::result::Result<(), String> {
+            if url.starts_with("https://") || url.starts_with("http://") {
+                Ok(())
+            } else {
+                Err(format!("{url} is not an http or https URL"))
+            }
+        })
+        .interact_text()
+        .wrap_err("Input error")?;
+    Ok(url.trim().trim_end_matches('/').to_string())
+}
+
+fn interview_secret(prompt: &str, allow_empty: bool) -> Result<String> {
+    let secret: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .allow_empty(allow_empty)
+        .interact_text()
+        .wrap_err("Input error")?;
+    encrypt_secret(secret.trim())
+}
+
+/// A topic on an ntfy server, which may be self-hosted.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ntfy {
+    pub topic_url: String,
+    #[serde(default)]
+    encrypted_token: String,
+    #[serde(default = "Ntfy::default_priorities")]
+    pub priorities: Priorities,
+}
+
+impl Ntfy {
+    /// ntfy priorities run from 1 (min) through 3 (default) to 5 (max).
+    pub fn default_priorities() -> Priorities {
+        Priorities {
+            initial: 2,
+            change: 5,
+            error: 3,
+        }
+    }
+
+    pub fn new(topic_url: &str, token: &str) -> Result<Self> {
+        Ok(Self {
+            topic_url: topic_url.to_string(),
+            encrypted_token: encrypt_secret(token)?,
+            priorities: Self::default_priorities(),
+        })
+    }
+
+    pub fn post(&self, event: &Event) -> Result<()> {
+        let tags = match event.severity() {
+            EventKind::Initial | EventKind::Heartbeat | EventKind::Report => "information_source",
+            EventKind::Change | EventKind::Digest => "warning",
+            EventKind::Error => "rotating_light",
+            EventKind::Recovery => "white_check_mark",
+        };
+        // publish as JSON to the server, because HTTP headers can't carry
+        // the accented letters of subjects in other languages
+        let (server_url, topic) = self.server_and_topic()?;
+        let message = json!({
+            "topic": topic,
+            "title": event.subject,
+            "message": event.body.join("\n"),
+            "priority": self.priorities.for_event(event),
+            "tags": [tags],
+        });
+        let mut request = http_agent().post(server_url);
+        let token = decrypt_secret(&self.encrypted_token)?;
+        if !token.is_empty() {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        send_json(request, &message.to_string()).wrap_err("ntfy post failed")
+    }
+
+    /// The topic URL split into the URL of the server and the name of the topic.
+    fn server_and_topic(&self) -> Result<(&str, &str)> {
+        match self.topic_url.trim_end_matches('/').rsplit_once('/') {
+            Some((server_url, topic)) if server_url.contains("://") && !topic.is_empty() => {
+                Ok((server_url, topic))
+            }
+            _ => Err(eyre!("ntfy topic URL ({}) has no topic", self.topic_url)),
+        }
+    }
+
+    pub fn interview() -> Result<Self> {
+        let topic_url = interview_url("ntfy topic URL", "https://ntfy.sh/")?;
+        let encrypted_token = interview_secret("Access token (leave empty if none)", true)?;
+        let priorities = Self::default_priorities().interview(1..=5)?;
+        Ok(Self {
+            topic_url,
+            encrypted_token,
+            priorities,
+        })
+    }
+}
+
+/// An application on a Gotify server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gotify {
+    pub server_url: String,
+    encrypted_app_token: String,
+    #[serde(default = "Gotify::default_priorities")]
+    pub priorities: Priorities,
+}
+
+impl Gotify {
+    /// Gotify priorities run from 0 (silent) to 10, and clients
+    /// typically only alert audibly at 8 and above.
+    pub fn default_priorities() -> Priorities {
+        Priorities {
+            initial: 2,
+            change: 8,
+            error: 5,
+        }
+    }
+
+    pub fn new(server_url: &str, app_token: &str) -> Result<Self> {
+        Ok(Self {
+            server_url: server_url.to_string(),
+            encrypted_app_token: encrypt_secret(app_token)?,
+            priorities: Self::default_priorities(),
+        })
+    }
+
+    pub fn post(&self, event: &Event) -> Result<()> {
+        let token = decrypt_secret(&self.encrypted_app_token)?;
+        let message = json!({
+            "title": event.subject,
+            "message": event.body.join("\n"),
+            "priority": self.priorities.for_event(event),
+        });
+        let request = http_agent()
+            .post(&format!("{}/message", self.server_url))
+            .set("X-Gotify-Key", &token);
+        send_json(request, &message.to_string()).wrap_err("Gotify post failed")
+    }
+
+    pub fn interview() -> Result<Self> {
+        let server_url = interview_url("Gotify server URL", "https://")?;
+        let encrypted_app_token = interview_secret("Gotify application token", false)?;
+        let priorities = Self::default_priorities().interview(0..=10)?;
+        Ok(Self {
+            server_url,
+            encrypted_app_token,
+            priorities,
+        })
+    }
+}
+
+/// A Pushover user or group, notified through a Pushover application.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pushover {
+    encrypted_user_key: String,
+    encrypted_app_token: String,
+    #[serde(default = "Pushover::default_priorities")]
+    pub priorities: Priorities,
+    #[serde(default = "Pushover::default_api_url")]
+    pub api_url: String,
+}
+
+impl Pushover {
+    /// The user key as it's stored, which tells users apart without giving the key away.
+    pub fn encrypted_user_key(&self) -> &str {
+        &self.encrypted_user_key
+    }
+
+    /// Pushover priorities run from -2 (no alert) to 2 (emergency),
+    /// and emergency messages are repeated until acknowledged.
+    pub fn default_priorities() -> Priorities {
+        Priorities {
+            initial: -1,
+            change: 1,
+            error: 0,
+        }
+    }
+
+    pub fn default_api_url() -> String {
+        String::from("https://api.pushover.net/1/messages.json")
+    }
+
+    pub fn new(user_key: &str, app_token: &str) -> Result<Self> {
+        Ok(Self {
+            encrypted_user_key: encrypt_secret(user_key)?,
+            encrypted_app_token: encrypt_secret(app_token)?,
+            priorities: Self::default_priorities(),
+            api_url: Self::default_api_url(),
+        })
+    }
+
+    pub fn post(&self, event: &Event) -> Result<()> {
+        let user = decrypt_secret(&self.encrypted_user_key)?;
+        let token = decrypt_secret(&self.encrypted_app_token)?;
+        let priority = self.priorities.for_event(event).to_string();
+        let timestamp = event.timestamp.timestamp().to_string();
+        let message = event.body.join("\n");
+        let mut form = vec![
+            ("token", token.as_str()),
+            ("user", user.as_str()),
+            ("title", event.subject.as_str()),
+            ("message", message.as_str()),
+            ("priority", priority.as_str()),
+            ("timestamp", timestamp.as_str()),
+        ];
+        if self.priorities.for_event(event) >= 2 {
+            // emergency messages must say how often and how long to repeat
+            form.push(("retry", "300"));
+            form.push(("expire", "3600"));
+        }
+        let request = http_agent().post(&self.api_url);
+        check_response(request.send_form(&form)).wrap_err("Pushover post failed")
+    }
+
+    pub fn interview() -> Result<Self> {
+        let encrypted_user_key = interview_secret("Pushover user or group key", false)?;
+        let encrypted_app_token = interview_secret("Pushover application token", false)?;
+        let priorities = Self::default_priorities().interview(-2..=2)?;
+        Ok(Self {
+            encrypted_user_key,
+            encrypted_app_token,
+            priorities,
+            api_url: Self::default_api_url(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::locale::Locale;
+    use super::super::stub::StubServer;
+    use super::*;
+
+    #[test]
+    fn test_ntfy_post() {
+        let server = StubServer::start(vec![(200, "{}")]);
+        let ntfy = Ntfy::new(&format!("{}/ddns", server.url), "tk_secret").unwrap();
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        ntfy.post(&event).expect("ntfy post failed");
+        let request = server.next_request();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.headers["authorization"], "Bearer tk_secret");
+        let message: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(message["topic"], "ddns");
+        assert_eq!(message["title"], "DNS change for some.host");
+        assert_eq!(message["priority"], 5);
+        assert_eq!(message["tags"][0], "warning");
+        assert!(message["message"].as_str().unwrap().contains("5.6.7.8"));
+    }
+
+    #[test]
+    fn test_ntfy_localized_post() {
+        let server = StubServer::start(vec![(200, "{}")]);
+        let ntfy = Ntfy::new(&format!("{}/alerts/ddns/", server.url), "").unwrap();
+        let event = Event::error(&eyre::eyre!("Lookup failed")).localized(Locale::Spanish);
+        ntfy.post(&event).expect("ntfy post failed");
+        let request = server.next_request();
+        assert_eq!(request.path, "/alerts");
+        assert!(!request.headers.contains_key("authorization"));
+        let message: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(message["topic"], "ddns");
+        assert_eq!(message["title"], event.subject);
+        assert!(message["title"].as_str().unwrap().contains('ó'));
+        let ntfy = Ntfy::new("https://ntfy.sh/", "").unwrap();
+        assert!(ntfy.post(&event).is_err());
+    }
+
+    #[test]
+    fn test_gotify_post() {
+        let server = StubServer::start(vec![(200, "{}")]);
+        let mut gotify = Gotify::new(&server.url, "app_token").unwrap();
+        gotify.priorities.error = 7;
+        let event = Event::error(&eyre::eyre!("Lookup failed"));
+        gotify.post(&event).expect("Gotify post failed");
+        let request = server.next_request();
+        assert_eq!(request.path, "/message");
+        assert_eq!(request.headers["x-gotify-key"], "app_token");
+        let message: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(message["priority"], 7);
+        assert_eq!(message["title"], "DNS monitoring temporary failure");
+    }
+
+    #[test]
+    fn test_pushover_post() {
+        let server = StubServer::start(vec![(200, "{\"status\":1}"), (400, "{}")]);
+        let mut pushover = Pushover::new("user_key", "app_token").unwrap();
+        pushover.api_url = format!("{}/1/messages.json", server.url);
+        pushover.priorities.change = 2;
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        pushover.post(&event).expect("Pushover post failed");
+        let request = server.next_request();
+        assert_eq!(
+            request.headers["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+        assert!(request.body.contains("user=user_key"));
+        assert!(request.body.contains("token=app_token"));
+        assert!(request.body.contains("priority=2"));
+        assert!(request.body.contains("expire=3600"));
+        let err = pushover.post(&event).expect_err("Pushover post succeeded");
+        assert!(err.to_string().contains("Pushover"));
+    }
+}
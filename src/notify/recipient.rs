@@ -0,0 +1,460 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use eyre::{Result, WrapErr};
+use lettre::message::Mailbox;
+use serde::{Deserialize, Serialize};
+
+use crate::Configuration;
+
+use super::locale::Locale;
+use super::{Event, EventKind};
+
+/// The host metadata entry that holds a host's tags, separated by commas.
+pub const TAGS_KEY: &str = "tags";
+
+/// The header that an email recipient is listed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Header {
+    #[default]
+    To,
+    Cc,
+    /// Not listed at all, so hidden from the other recipients.
+    Bcc,
+}
+
+impl Header {
+    pub const ALL: [Header; 3] = [Header::To, Header::Cc, Header::Bcc];
+
+    pub fn is_to(&self) -> bool {
+        *self == Header::To
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Header::To => "To",
+            Header::Cc => "Cc",
+            Header::Bcc => "Bcc (hidden from the other recipients)",
+        }
+    }
+}
+
+impl Display for Header {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Header::To => f.write_str("To"),
+            Header::Cc => f.write_str("Cc"),
+            Header::Bcc => f.write_str("Bcc"),
+        }
+    }
+}
+
+/// An email recipient, and which notifications they want.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", from = "RecipientEntry")]
+pub struct Recipient {
+    /// The address, possibly with a display name, as in `Jane Doe <jane@example.com>`.
+    pub address: String,
+    #[serde(skip_serializing_if = "Header::is_to")]
+    pub header: Header,
+    /// Only notify of these hosts and of hosts with these tags,
+    /// or of all hosts if there are neither.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Only notify of these kinds of event, or of all kinds if there are none.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+    /// The language of this recipient's notifications, if not the configured one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+}
+
+/// Recipients used to be just addresses, so either form is accepted.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecipientEntry {
+    Address(String),
+    #[serde(rename_all = "camelCase")]
+    Subscription {
+        address: String,
+        #[serde(default)]
+        header: Header,
+        #[serde(default)]
+        hosts: Vec<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        events: Vec<EventKind>,
+        #[serde(default)]
+        locale: Option<Locale>,
+    },
+}
+
+impl From<RecipientEntry> for Recipient {
+    fn from(entry: RecipientEntry) -> Self {
+        match entry {
+            RecipientEntry::Address(address) => Recipient::new(&address),
+            RecipientEntry::Subscription {
+                address,
+                header,
+                hosts,
+                tags,
+                events,
+                locale,
+            } => Recipient {
+                address,
+                header,
+                hosts,
+                tags,
+                events,
+                locale,
+            },
+        }
+    }
+}
+
+/// The kinds of event that recipients can choose, with descriptions.
+const SUBSCRIBABLE: [(EventKind, &str); 6] = [
+    (EventKind::Initial, "monitoring status"),
+    (EventKind::Change, "address changes"),
+    (EventKind::Error, "monitoring errors"),
+    (EventKind::Recovery, "recoveries from errors"),
+    (EventKind::Heartbeat, "daily summaries"),
+    (EventKind::Report, "stability reports"),
+];
+
+impl Recipient {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn mailbox(&self) -> Result<Mailbox> {
+        let address = &self.address;
+        address
+            .parse()
+            .wrap_err(format!("Illegal recipient address: {address}"))
+    }
+
+    pub fn wants_kind(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    pub fn wants_host(&self, config: &Configuration, host: &str) -> bool {
+        if self.hosts.is_empty() && self.tags.is_empty() {
+            return true;
+        }
+        self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            || host_tags(config, host)
+                .iter()
+                .any(|tag| self.tags.contains(tag))
+    }
+
+    /// The part of the event that this recipient wants, if any.
+    ///
+    /// A digest keeps only the wanted parts, and a status
+    /// notification only lists the wanted hosts.
+    pub fn filter(&self, config: &Configuration, event: &Event) -> Option<Event> {
+        match event.event {
+            EventKind::Digest => {
+                let mut parts: Vec<Event> = event
+                    .parts()
+                    .iter()
+                    .filter_map(|part| self.filter(config, part))
+                    .collect();
+                match parts.len() {
+                    0 => None,
+                    1 => parts.pop(),
+                    _ if parts == event.events => Some(event.clone()),
+                    _ => {
+                        let mut digest = Event::digest(parts);
+                        digest.timestamp = event.timestamp;
+                        digest.diagnostics = event.diagnostics.clone();
+                        Some(digest)
+                    }
+                }
+            }
+            kind if !self.wants_kind(kind) => None,
+            EventKind::Initial | EventKind::Heartbeat => {
+                let addresses: BTreeMap<String, String> = event
+                    .addresses
+                    .iter()
+                    .filter(|(host, _)| self.wants_host(config, host))
+                    .map(|(host, address)| (host.clone(), address.clone()))
+                    .collect();
+                if addresses.len() == event.addresses.len() {
+                    Some(event.clone())
+                } else if addresses.is_empty() {
+                    None
+                } else {
+                    let mut status = if event.event == EventKind::Initial {
+                        Event::initial_for(addresses, config.last_update)
+                    } else {
+                        Event::heartbeat(addresses, &config.last_changed, config.last_update)
+                    };
+                    status.timestamp = event.timestamp;
+                    status.diagnostics = event.diagnostics.clone();
+                    Some(status)
+                }
+            }
+            _ => match &event.host {
+                Some(host) if !self.wants_host(config, host) => None,
+                _ => Some(event.clone()),
+            },
+        }
+    }
+
+    /// Ask which notifications this recipient wants.
+    pub fn interview(&mut self) -> Result<()> {
+        let labels: Vec<&str> = SUBSCRIBABLE.iter().map(|(_, label)| *label).collect();
+        let defaults: Vec<bool> = SUBSCRIBABLE
+            .iter()
+            .map(|(kind, _)| self.wants_kind(*kind))
+            .collect();
+        let chosen = loop {
+            let chosen = dialoguer::MultiSelect::new()
+                .with_prompt(format!(
+                    "Notifications for {} (space to select, enter when done)",
+                    self.address
+                ))
+                .items(&labels)
+                .defaults(&defaults)
+                .interact()
+                .wrap_err("Input error")?;
+            if !chosen.is_empty() {
+                break chosen;
+            }
+            eprintln!("You must choose at least one kind of notification");
+        };
+        self.events = if chosen.len() == SUBSCRIBABLE.len() {
+            vec![]
+        } else {
+            chosen.iter().map(|i| SUBSCRIBABLE[*i].0).collect()
+        };
+        self.hosts = interview_list(
+            "Only notify about these hosts (separated by commas, or empty for all)",
+            &self.hosts,
+        )?;
+        self.tags = interview_list(
+            "Only notify about hosts with these tags (separated by commas, or empty for all)",
+            &self.tags,
+        )?;
+        let headers: Vec<&str> = Header::ALL.iter().map(Header::description).collect();
+        let current = Header::ALL.iter().position(|h| *h == self.header);
+        let choice = dialoguer::Select::new()
+            .with_prompt(format!("Send notifications for {} as", self.address))
+            .items(&headers)
+            .default(current.unwrap_or(0))
+            .interact()
+            .wrap_err("Input error")?;
+        self.header = Header::ALL[choice];
+        let mut languages = vec![String::from("The same as other notifications")];
+        languages.extend(Locale::ALL.iter().map(Locale::to_string));
+        let current = match self.locale {
+            Some(locale) => Locale::ALL.iter().position(|l| *l == locale).unwrap_or(0) + 1,
+            None => 0,
+        };
+        let choice = dialoguer::Select::new()
+            .with_prompt(format!("Language of notifications for {}", self.address))
+            .items(&languages)
+            .default(current)
+            .interact()
+            .wrap_err("Input error")?;
+        self.locale = choice.checked_sub(1).map(|i| Locale::ALL[i]);
+        Ok(())
+    }
+}
+
+impl Display for Recipient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.address)?;
+        let mut limits = vec![];
+        if !self.header.is_to() {
+            limits.push(format!("as {}", self.header));
+        }
+        if !self.events.is_empty() {
+            let kinds: Vec<&str> = SUBSCRIBABLE
+                .iter()
+                .filter(|(kind, _)| self.events.contains(kind))
+                .map(|(_, label)| *label)
+                .collect();
+            limits.push(format!("only {}", kinds.join(" and ")));
+        }
+        if !self.hosts.is_empty() {
+            limits.push(format!("hosts {}", self.hosts.join(", ")));
+        }
+        if !self.tags.is_empty() {
+            limits.push(format!("hosts tagged {}", self.tags.join(", ")));
+        }
+        if let Some(locale) = self.locale {
+            limits.push(format!("in {locale}"));
+        }
+        if !limits.is_empty() {
+            write!(f, " ({})", limits.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The tags of a host, from its metadata.
+pub fn host_tags(config: &Configuration, host: &str) -> Vec<String> {
+    match config.host_metadata.get(host).and_then(|m| m.get(TAGS_KEY)) {
+        Some(tags) => split_list(tags),
+        None => vec![],
+    }
+}
+
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub fn interview_list(prompt: &str, current: &[String]) -> Result<Vec<String>> {
+    let list: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .with_initial_text(current.join(", "))
+        .allow_empty(true)
+        .interact_text()
+        .wrap_err("Input error")?;
+    Ok(split_list(&list))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> Configuration {
+        let mut config = Configuration::default();
+        config.last_update = 1;
+        for (host, address) in [
+            ("edge1", "1.1.1.1"),
+            ("edge2", "2.2.2.2"),
+            ("side", "3.3.3.3"),
+        ] {
+            config.state.insert(host.to_string(), address.to_string());
+        }
+        for host in ["edge1", "edge2"] {
+            config.host_metadata.insert(
+                host.to_string(),
+                BTreeMap::from([(TAGS_KEY.to_string(), String::from("edge, prod"))]),
+            );
+        }
+        config
+    }
+
+    #[test]
+    fn test_old_and_new_forms() {
+        let recipients: Vec<Recipient> = serde_json::from_str(
+            r#"["all@example.com", {"address": "net@example.com", "tags": ["edge"], "events": ["change"]}]"#,
+        )
+        .unwrap();
+        assert_eq!(recipients[0], Recipient::new("all@example.com"));
+        assert_eq!(recipients[1].tags, vec!["edge"]);
+        assert_eq!(recipients[1].events, vec![EventKind::Change]);
+        assert_eq!(
+            serde_json::to_string(&recipients[0]).unwrap(),
+            r#"{"address":"all@example.com"}"#
+        );
+        assert_eq!(
+            recipients[1].to_string(),
+            "net@example.com (only address changes; hosts tagged edge)"
+        );
+        let recipient: Recipient =
+            serde_json::from_str(r#"{"address": "Net Ops <net@example.com>", "header": "bcc"}"#)
+                .unwrap();
+        assert_eq!(recipient.header, Header::Bcc);
+        assert_eq!(
+            recipient.mailbox().unwrap().email.to_string(),
+            "net@example.com"
+        );
+        assert_eq!(recipient.to_string(), "Net Ops <net@example.com> (as Bcc)");
+    }
+
+    #[test]
+    fn test_filters() {
+        let config = test_config();
+        let everything = Recipient::new("all@example.com");
+        let network = Recipient {
+            address: String::from("net@example.com"),
+            tags: vec![String::from("edge")],
+            events: vec![EventKind::Change],
+            ..Default::default()
+        };
+        let side = Recipient {
+            address: String::from("side@example.com"),
+            hosts: vec![String::from("side")],
+            ..Default::default()
+        };
+        let edge_change = Event::change("edge1", "1.1.1.1", "4.4.4.4");
+        let side_change = Event::change("side", "3.3.3.3", "5.5.5.5");
+        let error = Event::error(&eyre::eyre!("lookup failed"));
+        assert_eq!(
+            everything.filter(&config, &edge_change),
+            Some(edge_change.clone())
+        );
+        assert_eq!(
+            network.filter(&config, &edge_change),
+            Some(edge_change.clone())
+        );
+        assert_eq!(network.filter(&config, &side_change), None);
+        assert_eq!(network.filter(&config, &error), None);
+        assert_eq!(side.filter(&config, &edge_change), None);
+        assert_eq!(side.filter(&config, &error), Some(error.clone()));
+        let initial = Event::initial(&config);
+        let side_initial = side.filter(&config, &initial).unwrap();
+        assert_eq!(side_initial.addresses.len(), 1);
+        assert!(side_initial
+            .body
+            .iter()
+            .any(|line| line.contains("Host: side,")));
+        assert!(!side_initial.body.iter().any(|line| line.contains("edge1")));
+        let mut last_changed = BTreeMap::new();
+        last_changed.insert(String::from("side"), 1);
+        let heartbeat = Event::heartbeat(
+            config.state.clone().into_iter().collect(),
+            &last_changed,
+            config.last_update,
+        );
+        let side_heartbeat = side.filter(&config, &heartbeat).unwrap();
+        assert_eq!(side_heartbeat.event, EventKind::Heartbeat);
+        assert_eq!(side_heartbeat.addresses.len(), 1);
+        assert_eq!(network.filter(&config, &heartbeat), None);
+        let digest = Event::digest(vec![edge_change.clone(), side_change, error]);
+        assert_eq!(everything.filter(&config, &digest), Some(digest.clone()));
+        assert_eq!(network.filter(&config, &digest), Some(edge_change));
+        let side_digest = side.filter(&config, &digest).unwrap();
+        assert_eq!(side_digest.events.len(), 2);
+        assert_eq!(side_digest.timestamp, digest.timestamp);
+    }
+}
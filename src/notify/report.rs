@@ -0,0 +1,454 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone};
+use eyre::{Report, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::LookupFailure;
+
+use super::{state_file, EventKind};
+
+/// How often stability reports are sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportPeriod {
+    #[default]
+    Never,
+    /// Weeks start on Monday.
+    Weekly,
+    Monthly,
+}
+
+impl ReportPeriod {
+    const ALL: [ReportPeriod; 3] = [
+        ReportPeriod::Never,
+        ReportPeriod::Weekly,
+        ReportPeriod::Monthly,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReportPeriod::Never => "Never",
+            ReportPeriod::Weekly => "Weekly, on Mondays",
+            ReportPeriod::Monthly => "Monthly, on the first of the month",
+        }
+    }
+
+    /// The start of the period that contains the given time.
+    pub fn start_of(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let date = time.date_naive();
+        let first = match self {
+            ReportPeriod::Never => return None,
+            ReportPeriod::Weekly => {
+                date - Duration::days(date.weekday().num_days_from_monday().into())
+            }
+            ReportPeriod::Monthly => date.with_day(1)?,
+        };
+        midnight(first)
+    }
+
+    /// The start of the period before the one that starts at the given time.
+    pub fn previous_start(&self, start: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.start_of(start - Duration::days(1))
+    }
+}
+
+fn midnight(date: NaiveDate) -> Option<DateTime<Local>> {
+    // a daylight saving change can make midnight ambiguous
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+}
+
+/// When stability reports are sent. They go to the recipients who want them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportOptions {
+    #[serde(default)]
+    pub period: ReportPeriod,
+}
+
+impl ReportOptions {
+    /// The start and end of the period that a report is due for,
+    /// given when the last report was sent (in millis).
+    pub fn due_period(
+        &self,
+        last_report: i64,
+        now: DateTime<Local>,
+    ) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        let end = self.period.start_of(now)?;
+        if last_report >= end.timestamp_millis() {
+            return None;
+        }
+        Some((self.period.previous_start(end)?, end))
+    }
+
+    pub fn interview(&self) -> Result<Self> {
+        eprintln!("A stability report summarizes each host's changes and failures over a period.");
+        let periods: Vec<&str> = ReportPeriod::ALL.iter().map(|p| p.description()).collect();
+        let current = ReportPeriod::ALL.iter().position(|p| *p == self.period);
+        let choice = dialoguer::Select::new()
+            .with_prompt("How often to send a stability report")
+            .items(&periods)
+            .default(current.unwrap_or(0))
+            .interact()
+            .wrap_err("Input error")?;
+        let period = ReportPeriod::ALL[choice];
+        if period != ReportPeriod::Never {
+            eprintln!(
+                "The report is sent to the email recipients who subscribe to stability reports."
+            );
+        }
+        Ok(Self { period })
+    }
+}
+
+/// An address change or a failed check, as remembered for stability reports.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub timestamp: DateTime<Local>,
+    pub event: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The recent changes and failures, kept in a file next to the configuration.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct History {
+    #[serde(skip)]
+    path: PathBuf,
+    /// When the history began, so reports don't count time before it.
+    started: DateTime<Local>,
+    records: Vec<Record>,
+}
+
+impl History {
+    /// How long records are kept, which is long enough for a monthly report.
+    const KEEP_DAYS: i64 = 70;
+
+    pub fn path() -> Result<PathBuf> {
+        state_file::path("history.json")
+    }
+
+    /// The history stored at the given path, which starts now if there's no file there.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut history = state_file::load(&path, "History")?.unwrap_or_else(|| Self {
+            path: PathBuf::new(),
+            started: Local::now(),
+            records: vec![],
+        });
+        history.path = path;
+        Ok(history)
+    }
+
+    /// Save the history, forgetting records that are too old to be reported.
+    pub fn save(&mut self) -> Result<()> {
+        let cutoff = Local::now() - Duration::days(Self::KEEP_DAYS);
+        self.records.retain(|record| record.timestamp >= cutoff);
+        state_file::save_atomic(&self.path, self, "History")
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    pub fn changed(
+        &mut self,
+        host: &str,
+        old_address: &str,
+        new_address: &str,
+        now: DateTime<Local>,
+    ) {
+        self.records.push(Record {
+            timestamp: now,
+            event: EventKind::Change,
+            host: Some(host.to_string()),
+            old_address: Some(old_address.to_string()),
+            new_address: Some(new_address.to_string()),
+            error: None,
+        });
+    }
+
+    pub fn failed(&mut self, err: &Report, now: DateTime<Local>) {
+        let host = err
+            .downcast_ref::<LookupFailure>()
+            .map(|failure| failure.host().to_string());
+        self.records.push(Record {
+            timestamp: now,
+            event: EventKind::Error,
+            host,
+            old_address: None,
+            new_address: None,
+            error: Some(err.to_string()),
+        });
+    }
+}
+
+/// How stable a host's address was over a report's period.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostStability {
+    pub host: String,
+    pub changes: usize,
+    /// The longest and shortest times between two changes, counting only the part of each
+    /// time that falls in the period. There are none unless the host changed at least once
+    /// in the period and once before or after that.
+    pub longest: Option<Duration>,
+    pub shortest: Option<Duration>,
+    pub failed_lookups: usize,
+}
+
+/// An error that happened during a report's period, however many times it happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeriodError {
+    pub error: String,
+    pub first: DateTime<Local>,
+    pub count: usize,
+}
+
+/// What happened to the monitored hosts over a period.
+#[derive(Clone, Debug)]
+pub struct StabilityReport {
+    pub period: ReportPeriod,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub hosts: Vec<HostStability>,
+    pub failed_checks: usize,
+    pub errors: Vec<PeriodError>,
+    pub records: Vec<Record>,
+}
+
+impl StabilityReport {
+    pub fn new(
+        history: &History,
+        state: &HashMap<String, String>,
+        period: ReportPeriod,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Self {
+        let records: Vec<Record> = history
+            .records()
+            .iter()
+            .filter(|record| record.timestamp >= start && record.timestamp < end)
+            .cloned()
+            .collect();
+        let mut names: Vec<&String> = state.keys().collect();
+        names.sort();
+        let hosts = names
+            .into_iter()
+            .map(|host| {
+                let is_about_host = |record: &&Record| record.host.as_ref() == Some(host);
+                // changes outside the period bound the times at one address that overlap it
+                let mut changes: Vec<DateTime<Local>> = history
+                    .records()
+                    .iter()
+                    .filter(is_about_host)
+                    .filter(|record| record.event == EventKind::Change)
+                    .map(|record| record.timestamp)
+                    .collect();
+                changes.sort();
+                let stretches: Vec<Duration> = changes
+                    .windows(2)
+                    .filter(|w| w[1] > start && w[0] < end)
+                    .map(|w| w[1].min(end) - w[0].max(start))
+                    .collect();
+                HostStability {
+                    host: host.clone(),
+                    changes: changes
+                        .iter()
+                        .filter(|changed| **changed >= start && **changed < end)
+                        .count(),
+                    longest: stretches.iter().max().copied(),
+                    shortest: stretches.iter().min().copied(),
+                    failed_lookups: records
+                        .iter()
+                        .filter(is_about_host)
+                        .filter(|record| record.event == EventKind::Error)
+                        .count(),
+                }
+            })
+            .collect();
+        let mut errors: Vec<PeriodError> = vec![];
+        let failures = records
+            .iter()
+            .filter(|record| record.event == EventKind::Error);
+        for record in failures.clone() {
+            let error = record.error.clone().unwrap_or_default();
+            match errors.iter_mut().find(|e| e.error == error) {
+                Some(existing) => existing.count += 1,
+                None => errors.push(PeriodError {
+                    error,
+                    first: record.timestamp,
+                    count: 1,
+                }),
+            }
+        }
+        Self {
+            period,
+            start,
+            end,
+            hosts,
+            failed_checks: failures.count(),
+            errors,
+            records,
+        }
+    }
+
+    pub fn csv_name(&self) -> String {
+        format!("ddns-monitor-{}.csv", self.start.format("%Y-%m-%d"))
+    }
+
+    /// The records of the period as a CSV file.
+    pub fn csv(&self) -> String {
+        let mut csv = String::from("timestamp,event,host,old_address,new_address,error\r\n");
+        for record in self.records.iter() {
+            let fields = [
+                record.timestamp.to_rfc3339(),
+                record.event.to_string(),
+                record.host.clone().unwrap_or_default(),
+                record.old_address.clone().unwrap_or_default(),
+                record.new_address.clone().unwrap_or_default(),
+                record.error.clone().unwrap_or_default(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push_str("\r\n");
+        }
+        csv
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::eyre;
+
+    use super::super::locale::Locale;
+    use super::super::state_file::tests::TempFile;
+    use super::super::Event;
+    use super::*;
+
+    #[test]
+    fn test_periods() {
+        let now = Local.with_ymd_and_hms(2024, 3, 6, 9, 30, 0).unwrap();
+        let monday = Local.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap();
+        let first = Local.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(ReportPeriod::Weekly.start_of(now), Some(monday));
+        assert_eq!(ReportPeriod::Monthly.start_of(now), Some(first));
+        assert_eq!(ReportPeriod::Never.start_of(now), None);
+        assert_eq!(
+            ReportPeriod::Monthly.previous_start(first),
+            Local.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).single()
+        );
+        let options = ReportOptions {
+            period: ReportPeriod::Weekly,
+        };
+        let last_week = (monday - Duration::days(3)).timestamp_millis();
+        assert_eq!(
+            options.due_period(last_week, now),
+            Some((monday - Duration::days(7), monday))
+        );
+        assert_eq!(options.due_period(monday.timestamp_millis(), now), None);
+    }
+
+    #[test]
+    fn test_history_and_report() {
+        let file = TempFile::new("history");
+        let path = file.path();
+        let mut history = History::load(path.clone()).unwrap();
+        let end = Local::now();
+        let start = end - Duration::days(7);
+        history.started = start - Duration::days(30);
+        history.changed("a.host", "1.1.1.1", "2.2.2.2", start + Duration::days(1));
+        history.changed("a.host", "2.2.2.2", "3.3.3.3", start + Duration::days(3));
+        history.failed(
+            &eyre!(LookupFailure::Failed(String::from("b.host"))),
+            start + Duration::days(2),
+        );
+        history.failed(&eyre!("Disk full, \"really\""), start + Duration::days(4));
+        history.changed("a.host", "9.9.9.9", "1.1.1.1", start - Duration::days(1));
+        history.save().unwrap();
+        let history = History::load(path).unwrap();
+        assert_eq!(history.records().len(), 5);
+        let state = HashMap::from([
+            (String::from("a.host"), String::from("3.3.3.3")),
+            (String::from("b.host"), String::from("4.4.4.4")),
+        ]);
+        let report = StabilityReport::new(&history, &state, ReportPeriod::Weekly, start, end);
+        assert_eq!(report.records.len(), 4);
+        assert_eq!(report.failed_checks, 2);
+        let a = &report.hosts[0];
+        assert_eq!(
+            (a.host.as_str(), a.changes, a.failed_lookups),
+            ("a.host", 2, 0)
+        );
+        // the change before the period bounds the first time at one address
+        assert_eq!(a.longest, Some(Duration::days(2)));
+        assert_eq!(a.shortest, Some(Duration::days(1)));
+        let b = &report.hosts[1];
+        assert_eq!((b.changes, b.failed_lookups), (0, 1));
+        assert_eq!(b.longest, None);
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[1].error, "Disk full, \"really\"");
+        assert_eq!(report.errors[1].first, start + Duration::days(4));
+        let csv = report.csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].ends_with(",change,a.host,1.1.1.1,2.2.2.2,"));
+        assert!(lines[4].ends_with(",error,,,,\"Disk full, \"\"really\"\"\""));
+        let event = Event::report(&report);
+        assert_eq!(event.subject, "Dynamic DNS monitoring weekly report");
+        assert!(event.body[1]
+            .starts_with("-- a.host: address changes: 2, longest time at one address: 2 days,"));
+        assert_eq!(
+            event.body[2],
+            "-- b.host: address changes: 0, failed lookups: 1"
+        );
+        assert_eq!(event.body[3], "Monitoring failed 2 checks in this period.");
+        assert!(event.body[5].starts_with("-- Disk full, \"really\" (1 time, first at "));
+        assert_eq!(event.attachments[0].content, csv);
+        let spanish = event.localized(Locale::Spanish);
+        assert_eq!(
+            spanish.subject,
+            "Informe semanal de la supervisión de DNS dinámico"
+        );
+    }
+}
@@ -0,0 +1,656 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use eyre::{eyre, Result, WrapErr};
+use lettre::message::header::{ContentDisposition, ContentType};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use serde::{Deserialize, Serialize};
+
+use super::recipient::Recipient;
+
+/// The standard used to sign and encrypt notification emails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Method {
+    #[default]
+    None,
+    /// PGP/MIME (RFC 3156), using GnuPG.
+    OpenPgp,
+    /// S/MIME (RFC 8551), using OpenSSL.
+    Smime,
+}
+
+impl Method {
+    const ALL: [Method; 3] = [Method::None, Method::OpenPgp, Method::Smime];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Method::None => "Neither",
+            Method::OpenPgp => "OpenPGP (with GnuPG)",
+            Method::Smime => "S/MIME (with OpenSSL)",
+        }
+    }
+}
+
+/// How notification emails are signed and encrypted.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityOptions {
+    #[serde(default)]
+    pub method: Method,
+    #[serde(default)]
+    pub sign: bool,
+    #[serde(default)]
+    pub encrypt: bool,
+    /// For OpenPGP, the ID of the signing key in the GnuPG keyring.
+    #[serde(default)]
+    pub signing_key: String,
+    /// For OpenPGP, the GnuPG home directory, or empty for the default.
+    #[serde(default)]
+    pub gnupg_home: String,
+    /// For S/MIME, the PEM files of the signing certificate and its unencrypted private key.
+    #[serde(default)]
+    pub certificate: String,
+    #[serde(default)]
+    pub private_key: String,
+    /// The public key (for OpenPGP, armored) or certificate (for S/MIME, PEM)
+    /// that emails to each address are encrypted for.
+    #[serde(default)]
+    pub public_keys: BTreeMap<String, String>,
+}
+
+/// The body of an email, once it has been protected.
+pub enum SecureBody {
+    Multi(MultiPart),
+    Single(SinglePart),
+}
+
+impl SecureBody {
+    fn formatted(&self) -> Vec<u8> {
+        match self {
+            SecureBody::Multi(part) => part.formatted(),
+            SecureBody::Single(part) => part.formatted(),
+        }
+    }
+
+    /// The content as a verifier sees it inside a multipart/signed entity: the final
+    /// line break belongs to the boundary that follows it, so it isn't signed.
+    fn signed_content(&self) -> Vec<u8> {
+        let mut content = self.formatted();
+        if content.ends_with(b"\r\n") {
+            content.truncate(content.len() - 2);
+        }
+        content
+    }
+}
+
+impl SecurityOptions {
+    pub fn is_encrypting(&self) -> bool {
+        self.method != Method::None && self.encrypt
+    }
+
+    fn is_signing(&self) -> bool {
+        self.method != Method::None && self.sign
+    }
+
+    /// The content of an email, signed and then encrypted as configured.
+    pub fn protect(&self, content: MultiPart, recipients: &[Recipient]) -> Result<SecureBody> {
+        let mut body = SecureBody::Multi(content);
+        if self.is_signing() {
+            body = match self.method {
+                Method::OpenPgp => self.pgp_sign(body)?,
+                _ => self.smime_sign(body)?,
+            };
+        }
+        if self.is_encrypting() {
+            let keys = self.key_files(recipients)?;
+            body = match self.method {
+                Method::OpenPgp => self.pgp_encrypt(body, &keys)?,
+                _ => self.smime_encrypt(body, &keys)?,
+            };
+        }
+        Ok(body)
+    }
+
+    fn pgp_sign(&self, body: SecureBody) -> Result<SecureBody> {
+        let args = [
+            "--detach-sign",
+            "--armor",
+            "--digest-algo",
+            "SHA256",
+            "--local-user",
+            &self.signing_key,
+        ];
+        let signature = self.gpg(&args, &body.signed_content())?;
+        let signature = SinglePart::builder()
+            .header(content_type(
+                "application/pgp-signature; name=\"signature.asc\"",
+            )?)
+            .body(String::from_utf8_lossy(&signature).to_string());
+        let signed = MultiPart::signed(
+            String::from("application/pgp-signature"),
+            String::from("pgp-sha256"),
+        );
+        Ok(SecureBody::Multi(match body {
+            SecureBody::Multi(part) => signed.multipart(part).singlepart(signature),
+            SecureBody::Single(part) => signed.singlepart(part).singlepart(signature),
+        }))
+    }
+
+    fn pgp_encrypt(&self, body: SecureBody, keys: &[KeyFile]) -> Result<SecureBody> {
+        let mut args = vec!["--encrypt", "--armor", "--trust-model", "always"];
+        for key in keys.iter() {
+            args.extend(["--recipient-file", key.path()?]);
+        }
+        let encrypted = self.gpg(&args, &body.formatted())?;
+        let version = SinglePart::builder()
+            .header(content_type("application/pgp-encrypted")?)
+            .body(String::from("Version: 1\r\n"));
+        let encrypted = SinglePart::builder()
+            .header(content_type(
+                "application/octet-stream; name=\"encrypted.asc\"",
+            )?)
+            .header(ContentDisposition::inline_with_name("encrypted.asc"))
+            .body(String::from_utf8_lossy(&encrypted).to_string());
+        Ok(SecureBody::Multi(
+            MultiPart::encrypted(String::from("application/pgp-encrypted"))
+                .singlepart(version)
+                .singlepart(encrypted),
+        ))
+    }
+
+    fn smime_sign(&self, body: SecureBody) -> Result<SecureBody> {
+        let args = [
+            "cms",
+            "-sign",
+            "-binary",
+            "-md",
+            "sha256",
+            "-outform",
+            "DER",
+            "-signer",
+            &self.certificate,
+            "-inkey",
+            &self.private_key,
+        ];
+        let signature = run("openssl", &args, &body.signed_content())?;
+        let signature = SinglePart::builder()
+            .header(content_type(
+                "application/pkcs7-signature; name=\"smime.p7s\"",
+            )?)
+            .header(ContentDisposition::attachment("smime.p7s"))
+            .body(signature);
+        let signed = MultiPart::signed(
+            String::from("application/pkcs7-signature"),
+            String::from("sha-256"),
+        );
+        Ok(SecureBody::Multi(match body {
+            SecureBody::Multi(part) => signed.multipart(part).singlepart(signature),
+            SecureBody::Single(part) => signed.singlepart(part).singlepart(signature),
+        }))
+    }
+
+    fn smime_encrypt(&self, body: SecureBody, keys: &[KeyFile]) -> Result<SecureBody> {
+        let mut args = vec![
+            "cms",
+            "-encrypt",
+            "-binary",
+            "-aes-256-cbc",
+            "-outform",
+            "DER",
+        ];
+        for key in keys.iter() {
+            args.push(key.path()?);
+        }
+        let encrypted = run("openssl", &args, &body.formatted())?;
+        Ok(SecureBody::Single(
+            SinglePart::builder()
+                .header(content_type(
+                    "application/pkcs7-mime; smime-type=enveloped-data; name=\"smime.p7m\"",
+                )?)
+                .header(ContentDisposition::attachment("smime.p7m"))
+                .body(encrypted),
+        ))
+    }
+
+    fn gpg(&self, args: &[&str], input: &[u8]) -> Result<Vec<u8>> {
+        let mut all = vec!["--batch", "--yes", "--no-tty"];
+        if !self.gnupg_home.is_empty() {
+            all.extend(["--homedir", &self.gnupg_home]);
+        }
+        all.extend(args);
+        run("gpg", &all, input)
+    }
+
+    /// Files holding the public keys of the recipients, which every recipient must have.
+    fn key_files(&self, recipients: &[Recipient]) -> Result<Vec<KeyFile>> {
+        recipients
+            .iter()
+            .map(|recipient| {
+                let address = key_address(&recipient.address);
+                let key = self
+                    .public_keys
+                    .get(&address)
+                    .ok_or_else(|| eyre!("No public key for {address}, so can't encrypt"))?;
+                KeyFile::new(key)
+            })
+            .collect()
+    }
+
+    pub fn interview(&self, addresses: &[String]) -> Result<Self> {
+        eprintln!("Notification emails can be signed, so recipients can check they came from the monitor,");
+        eprintln!("and encrypted, so only the recipients can read them.");
+        let methods: Vec<&str> = Method::ALL.iter().map(|m| m.description()).collect();
+        let current = Method::ALL.iter().position(|m| *m == self.method);
+        let choice = dialoguer::Select::new()
+            .with_prompt("Sign or encrypt emails with")
+            .items(&methods)
+            .default(current.unwrap_or(0))
+            .interact()
+            .wrap_err("Input error")?;
+        let method = Method::ALL[choice];
+        if method == Method::None {
+            return Ok(Self::default());
+        }
+        let mut new = Self {
+            method,
+            ..self.clone()
+        };
+        loop {
+            new.sign = dialoguer::Confirm::new()
+                .with_prompt("Sign emails?")
+                .default(self.sign)
+                .interact()
+                .wrap_err("Input error")?;
+            new.encrypt = dialoguer::Confirm::new()
+                .with_prompt("Encrypt emails?")
+                .default(self.encrypt)
+                .interact()
+                .wrap_err("Input error")?;
+            if new.sign || new.encrypt {
+                break;
+            }
+            eprintln!("Choose signing, encryption or both (or neither method)");
+        }
+        if new.sign && method == Method::OpenPgp {
+            new.gnupg_home = interview_text(
+                "GnuPG home directory (leave empty for the default)",
+                &self.gnupg_home,
+                true,
+            )?;
+            new.signing_key = interview_text("ID of the signing key", &self.signing_key, false)?;
+        } else if new.sign {
+            new.certificate = interview_file("Signing certificate file (PEM)", &self.certificate)?;
+            new.private_key = interview_file(
+                "Private key file (PEM, without a passphrase)",
+                &self.private_key,
+            )?;
+        }
+        new.public_keys
+            .retain(|address, _| addresses.contains(address));
+        if new.encrypt {
+            let kind = if method == Method::OpenPgp {
+                "OpenPGP public key"
+            } else {
+                "S/MIME certificate"
+            };
+            for address in addresses.iter() {
+                let prompt = if new.public_keys.contains_key(address) {
+                    format!(
+                        "File with the {kind} of {address} (leave empty to keep the current one)"
+                    )
+                } else {
+                    format!("File with the {kind} of {address}")
+                };
+                let path = loop {
+                    let path = interview_text(&prompt, "", new.public_keys.contains_key(address))?;
+                    if path.is_empty() || std::fs::metadata(&path).is_ok() {
+                        break path;
+                    }
+                    eprintln!("There is no file {path}; try again");
+                };
+                if !path.is_empty() {
+                    let key = std::fs::read_to_string(&path)
+                        .wrap_err(format!("Can't read the key file ({path})"))?;
+                    new.public_keys.insert(address.clone(), key);
+                }
+            }
+        }
+        Ok(new)
+    }
+}
+
+/// The bare email address that a recipient's public key is stored under.
+pub fn key_address(address: &str) -> String {
+    match address.parse::<Mailbox>() {
+        Ok(mailbox) => mailbox.email.to_string().to_lowercase(),
+        Err(_) => address.trim().to_lowercase(),
+    }
+}
+
+fn content_type(value: &str) -> Result<ContentType> {
+    ContentType::parse(value).wrap_err(format!("Illegal content type: {value}"))
+}
+
+fn interview_text(prompt: &str, current: &str, allow_empty: bool) -> Result<String> {
+    let text: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .with_initial_text(current)
+        .allow_empty(allow_empty)
+        .interact_text()
+        .wrap_err("Input error")?;
+    Ok(text.trim().to_string())
+}
+
+fn interview_file(prompt: &str, current: &str) -> Result<String> {
+    loop {
+        let path = interview_text(prompt, current, false)?;
+        if std::fs::metadata(&path).is_ok() {
+            return Ok(path);
+        }
+        eprintln!("There is no file {path}; try again");
+    }
+}
+
+/// Run a program on the input, and return its output.
+fn run(program: &str, args: &[&str], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err(format!("Can't run {program}"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    // write on another thread, so a program that writes as it reads can't block us
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .wrap_err(format!("Failed to run {program}"))?;
+    let written = writer.join().map_err(|_| eyre!("{program} input failed"))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!(
+            "{program} failed ({}): {}",
+            output.status,
+            error.trim()
+        ));
+    }
+    written.wrap_err(format!("Can't write input to {program}"))?;
+    Ok(output.stdout)
+}
+
+/// A temporary file holding a key, which is removed when it's dropped.
+struct KeyFile(PathBuf);
+
+impl KeyFile {
+    fn new(key: &str) -> Result<Self> {
+        let nonce: u64 = rand::random();
+        let path = std::env::temp_dir().join(format!("ddns-monitor-key-{nonce:016x}.pem"));
+        std::fs::write(&path, key)
+            .wrap_err(format!("Can't write key file ({})", path.display()))?;
+        Ok(Self(path))
+    }
+
+    fn path(&self) -> Result<&str> {
+        self.0
+            .to_str()
+            .ok_or_else(|| eyre!("Key file path isn't text"))
+    }
+}
+
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use base64::Engine;
+
+    use super::*;
+
+    fn test_content() -> MultiPart {
+        MultiPart::alternative_plain_html(
+            String::from("The IP address of some.host has changed."),
+            String::from("<p>The IP address of some.host has changed.</p>"),
+        )
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ddns-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(0o700);
+            std::fs::set_permissions(&dir, permissions).unwrap();
+        }
+        dir
+    }
+
+    /// Options that sign with a new self-signed certificate and encrypt to it
+    /// for admin@example.com, kept in a new directory; none if there's no openssl.
+    pub fn smime_options(name: &str) -> Option<(SecurityOptions, PathBuf)> {
+        run("openssl", &["version"], b"").ok()?;
+        let dir = temp_dir(name);
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+        run(
+            "openssl",
+            &[
+                "req",
+                "-x509",
+                "-newkey",
+                "rsa:2048",
+                "-nodes",
+                "-subj",
+                "/CN=monitor",
+                "-days",
+                "1",
+                "-keyout",
+                key.to_str().unwrap(),
+                "-out",
+                cert.to_str().unwrap(),
+            ],
+            b"",
+        )
+        .unwrap();
+        let options = SecurityOptions {
+            method: Method::Smime,
+            sign: true,
+            encrypt: true,
+            certificate: cert.to_str().unwrap().to_string(),
+            private_key: key.to_str().unwrap().to_string(),
+            public_keys: BTreeMap::from([(
+                String::from("admin@example.com"),
+                std::fs::read_to_string(&cert).unwrap(),
+            )]),
+            ..Default::default()
+        };
+        Some((options, dir))
+    }
+
+    /// The content of an S/MIME message for the certificate of the given options,
+    /// if it was encrypted for that certificate.
+    pub fn smime_decrypt(options: &SecurityOptions, encrypted: &[u8]) -> Option<String> {
+        let args = [
+            "cms",
+            "-decrypt",
+            "-inform",
+            "DER",
+            "-recip",
+            &options.certificate,
+            "-inkey",
+            &options.private_key,
+        ];
+        let decrypted = run("openssl", &args, encrypted).ok()?;
+        String::from_utf8(decrypted).ok()
+    }
+
+    /// The parts of a multipart entity, without their delimiters.
+    fn parts(entity: &str) -> Vec<&str> {
+        let boundary = entity
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .expect("no boundary");
+        let delimiter = format!("\r\n--{boundary}");
+        let (_, body) = entity.split_once(&format!("--{boundary}\r\n")).unwrap();
+        body.split(&delimiter)
+            .map(|part| part.trim_start_matches("\r\n"))
+            .filter(|part| !part.starts_with("--"))
+            .collect()
+    }
+
+    /// The body of a part, without its headers.
+    fn part_body(part: &str) -> &str {
+        part.split_once("\r\n\r\n").unwrap().1
+    }
+
+    #[test]
+    fn test_unprotected() {
+        let options = SecurityOptions::default();
+        let body = options.protect(test_content(), &[]).unwrap();
+        assert!(matches!(body, SecureBody::Multi(_)));
+        let options = SecurityOptions {
+            method: Method::OpenPgp,
+            encrypt: true,
+            ..Default::default()
+        };
+        let recipients = [Recipient::new("Jane Doe <Jane@example.com>")];
+        let err = options.protect(test_content(), &recipients).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "No public key for jane@example.com, so can't encrypt"
+        );
+    }
+
+    #[test]
+    fn test_openpgp() {
+        if run("gpg", &["--version"], b"").is_err() {
+            eprintln!("Skipping OpenPGP test: gpg isn't installed");
+            return;
+        }
+        let home = temp_dir("gnupg");
+        let home_path = home.to_str().unwrap();
+        let gpg = |args: &[&str], input: &[u8]| {
+            let mut all = vec!["--batch", "--homedir", home_path];
+            all.extend(args);
+            run("gpg", &all, input).unwrap()
+        };
+        gpg(
+            &[
+                "--passphrase",
+                "",
+                "--quick-gen-key",
+                "Monitor <monitor@example.com>",
+                "future-default",
+                "default",
+                "never",
+            ],
+            b"",
+        );
+        let public_key = gpg(&["--armor", "--export", "monitor@example.com"], b"");
+        let options = SecurityOptions {
+            method: Method::OpenPgp,
+            sign: true,
+            encrypt: true,
+            signing_key: String::from("monitor@example.com"),
+            gnupg_home: home_path.to_string(),
+            public_keys: BTreeMap::from([(
+                String::from("admin@example.com"),
+                String::from_utf8(public_key).unwrap(),
+            )]),
+            ..Default::default()
+        };
+        let recipients = [Recipient::new("Admin <admin@example.com>")];
+        let body = options.protect(test_content(), &recipients).unwrap();
+        let entity = String::from_utf8(body.formatted()).unwrap();
+        assert!(entity.starts_with("Content-Type: multipart/encrypted;"));
+        assert!(entity.contains("protocol=\"application/pgp-encrypted\""));
+        assert!(!entity.contains("some.host"));
+        let encrypted = parts(&entity);
+        assert_eq!(part_body(encrypted[0]), "Version: 1\r\n");
+        let decrypted = gpg(&["--decrypt"], part_body(encrypted[1]).as_bytes());
+        let decrypted = String::from_utf8(decrypted).unwrap();
+        assert!(decrypted.starts_with("Content-Type: multipart/signed;"));
+        let signed = parts(&decrypted);
+        assert!(signed[0].contains("The IP address of some.host has changed."));
+        let data = home.join("data.eml");
+        let signature = home.join("data.asc");
+        std::fs::write(&data, signed[0]).unwrap();
+        std::fs::write(&signature, part_body(signed[1])).unwrap();
+        gpg(
+            &[
+                "--verify",
+                signature.to_str().unwrap(),
+                data.to_str().unwrap(),
+            ],
+            b"",
+        );
+        let _ = run(
+            "gpgconf",
+            &["--homedir", home_path, "--kill", "gpg-agent"],
+            b"",
+        );
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn test_smime() {
+        let Some((options, dir)) = smime_options("smime") else {
+            eprintln!("Skipping S/MIME test: openssl isn't installed");
+            return;
+        };
+        let recipients = [Recipient::new("admin@example.com")];
+        let body = options.protect(test_content(), &recipients).unwrap();
+        assert!(matches!(body, SecureBody::Single(_)));
+        let entity = String::from_utf8(body.formatted()).unwrap();
+        assert!(entity.contains("application/pkcs7-mime; smime-type=enveloped-data"));
+        let encrypted: String = part_body(&entity).split_whitespace().collect();
+        let encrypted = base64::engine::general_purpose::STANDARD
+            .decode(encrypted)
+            .unwrap();
+        let decrypted = smime_decrypt(&options, &encrypted).expect("can't decrypt");
+        assert!(decrypted.starts_with("Content-Type: multipart/signed;"));
+        assert!(decrypted.contains("protocol=\"application/pkcs7-signature\""));
+        let verified = run(
+            "openssl",
+            &["cms", "-verify", "-noverify"],
+            decrypted.as_bytes(),
+        )
+        .expect("signature doesn't verify");
+        let verified = String::from_utf8(verified).unwrap();
+        assert!(verified.contains("The IP address of some.host has changed."));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The path of the state file with the given name, which is next to the configuration file.
+pub fn path(name: &str) -> Result<PathBuf> {
+    let config_path = crate::configure::config_path()?;
+    Ok(config_path.with_file_name(name))
+}
+
+/// The contents of a state file, which are none if there's no file there.
+///
+/// The description (such as `Outbox`) names the file in error messages.
+pub fn load<T: DeserializeOwned>(path: &Path, description: &str) -> Result<Option<T>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).map(Some).wrap_err(format!(
+            "{description} file ({}) is corrupt",
+            path.display()
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err(format!(
+            "{description} file ({}) cannot be read",
+            path.display()
+        )),
+    }
+}
+
+/// Replace the contents of a state file.
+pub fn save_atomic<T: Serialize + ?Sized>(path: &Path, value: &T, description: &str) -> Result<()> {
+    let text = serde_json::to_string_pretty(value)
+        .wrap_err(format!("{description} cannot be serialized"))?;
+    // write a new file and then replace the old one, so a crash can't leave half a file
+    let temp_path = path.with_extension("json.new");
+    std::fs::write(&temp_path, text).wrap_err(format!(
+        "{description} file ({}) cannot be written",
+        temp_path.display()
+    ))?;
+    std::fs::rename(&temp_path, path).wrap_err(format!(
+        "{description} file ({}) cannot be replaced",
+        path.display()
+    ))
+}
+
+/// An exclusive lock on a state file, which is released when it's dropped (or the process
+/// ends), so that the monitor and a command run alongside it don't undo each other's changes.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+/// Wait until no other process holds the lock on a state file, and take it.
+///
+/// The lock is on a file beside the state file, as the state file itself is replaced on saving.
+pub fn lock(path: &Path, description: &str) -> Result<Lock> {
+    let lock_path = path.with_extension("json.lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .wrap_err(format!(
+            "{description} lock file ({}) cannot be opened",
+            lock_path.display()
+        ))?;
+    file.lock().wrap_err(format!(
+        "{description} lock file ({}) cannot be locked",
+        lock_path.display()
+    ))?;
+    Ok(Lock { _file: file })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A state file path in the temp directory, which is removed (with any file
+    /// left beside it by an interrupted save or a lock) when dropped, even if a test panics.
+    pub struct TempFile(PathBuf);
+
+    impl TempFile {
+        pub fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("ddns-{name}-{}.json", std::process::id()));
+            let file = Self(path);
+            file.remove();
+            file
+        }
+
+        pub fn path(&self) -> PathBuf {
+            self.0.clone()
+        }
+
+        fn remove(&self) {
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(self.0.with_extension("json.new"));
+            let _ = std::fs::remove_file(self.0.with_extension("json.lock"));
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let file = TempFile::new("state-file");
+        let path = file.path();
+        let missing: Option<Vec<u32>> = load(&path, "Test").unwrap();
+        assert!(missing.is_none());
+        save_atomic(&path, &[1, 2, 3], "Test").unwrap();
+        assert!(!path.with_extension("json.new").exists());
+        let loaded: Option<Vec<u32>> = load(&path, "Test").unwrap();
+        assert_eq!(loaded, Some(vec![1, 2, 3]));
+        std::fs::write(&path, "not json").unwrap();
+        let err = load::<Vec<u32>>(&path, "Test").unwrap_err();
+        assert!(err.to_string().starts_with("Test file ("));
+        assert!(err.to_string().ends_with(") is corrupt"));
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_lock() {
+        let file = TempFile::new("state-lock");
+        let lock = lock(&file.path(), "Test").unwrap();
+        let other = File::open(file.path().with_extension("json.lock")).unwrap();
+        assert!(other.try_lock().is_err());
+        drop(lock);
+        assert!(other.try_lock().is_ok());
+    }
+}
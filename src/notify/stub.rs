@@ -0,0 +1,284 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default)]
+pub struct StubRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lower-cased.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// A minimal HTTP server that stands in for remote services in tests.
+pub struct StubServer {
+    pub url: String,
+    requests: Receiver<StubRequest>,
+}
+
+impl StubServer {
+    /// Start a server that answers one request with each of the given
+    /// (status, body) responses, in order, and then stops listening.
+    pub fn start(responses: Vec<(u16, &str)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can't bind stub server");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("no local address")
+        );
+        let responses: Vec<(u16, String)> = responses
+            .into_iter()
+            .map(|(status, body)| (status, body.to_string()))
+            .collect();
+        let (sender, requests) = channel();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let Some(request) = read_request(&mut stream) else {
+                    return;
+                };
+                let response = format!(
+                    "HTTP/1.1 {status} Stub\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes());
+                if sender.send(request).is_err() {
+                    return;
+                }
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub fn next_request(&self) -> StubRequest {
+        self.requests
+            .recv_timeout(Duration::from_secs(10))
+            .expect("stub server received no request")
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> Option<StubRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let mut request = StubRequest {
+        method: parts.next()?.to_string(),
+        path: parts.next()?.to_string(),
+        ..Default::default()
+    };
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        request
+            .headers
+            .insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+    let length: usize = match request.headers.get("content-length") {
+        Some(length) => length.parse().ok()?,
+        None => 0,
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    request.body = String::from_utf8_lossy(&body).to_string();
+    Some(request)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StubPublish {
+    pub topic: String,
+    pub retain: bool,
+    pub payload: String,
+}
+
+/// A minimal MQTT 3.1.1 broker that accepts one client session,
+/// acknowledges its publishes, and reports them when the client disconnects.
+pub struct MqttStub {
+    pub port: u16,
+    publishes: Receiver<Vec<StubPublish>>,
+}
+
+impl MqttStub {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can't bind stub broker");
+        let port = listener.local_addr().expect("no local address").port();
+        let (sender, publishes) = channel();
+        std::thread::spawn(move || {
+            let Ok((mut stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut received = vec![];
+            while let Some((header, body)) = read_packet(&mut stream) {
+                let reply: &[u8] = match header >> 4 {
+                    1 => &[0x20, 0x02, 0x00, 0x00], // CONNACK
+                    3 => {
+                        let qos = (header >> 1) & 0x03;
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).to_string();
+                        let mut rest = &body[2 + topic_len..];
+                        let mut ack = vec![];
+                        if qos > 0 {
+                            ack = vec![0x40, 0x02, rest[0], rest[1]]; // PUBACK
+                            rest = &rest[2..];
+                        }
+                        received.push(StubPublish {
+                            topic,
+                            retain: header & 0x01 == 1,
+                            payload: String::from_utf8_lossy(rest).to_string(),
+                        });
+                        let _ = stream.write_all(&ack);
+                        &[]
+                    }
+                    12 => &[0xD0, 0x00], // PINGRESP
+                    14 => break,         // DISCONNECT
+                    _ => &[],
+                };
+                let _ = stream.write_all(reply);
+            }
+            let _ = sender.send(received);
+        });
+        Self { port, publishes }
+    }
+
+    pub fn publishes(&self) -> Vec<StubPublish> {
+        self.publishes
+            .recv_timeout(Duration::from_secs(10))
+            .expect("stub broker session did not end")
+    }
+}
+
+fn read_packet(stream: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+    let mut byte = [0u8];
+    stream.read_exact(&mut byte).ok()?;
+    let header = byte[0];
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        stream.read_exact(&mut byte).ok()?;
+        length |= ((byte[0] & 0x7F) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).ok()?;
+    Some((header, body))
+}
+
+/// A minimal SMTP server that accepts messages in one session, without TLS,
+/// and reports the lines it received from the client.
+pub struct SmtpStub {
+    pub port: u16,
+    transcript: Receiver<Vec<String>>,
+}
+
+impl SmtpStub {
+    /// Start a server that accepts one message.
+    pub fn start() -> Self {
+        Self::start_for(1)
+    }
+
+    /// Start a server that accepts the given number of messages.
+    pub fn start_for(messages: usize) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can't bind stub SMTP server");
+        let port = listener.local_addr().expect("no local address").port();
+        let (sender, transcript) = channel();
+        std::thread::spawn(move || {
+            // lettre's connection pool may open an idle connection alongside the one
+            // it sends with, so every connection gets a session of its own
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    return;
+                };
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    if let Some(lines) = smtp_session(stream, messages) {
+                        let _ = sender.send(lines);
+                    }
+                });
+            }
+        });
+        Self { port, transcript }
+    }
+
+    pub fn transcript(&self) -> Vec<String> {
+        self.transcript
+            .recv_timeout(Duration::from_secs(10))
+            .expect("stub SMTP session did not end")
+    }
+}
+
+/// Serve one SMTP connection, returning the lines received if any message was sent.
+fn smtp_session(stream: TcpStream, messages: usize) -> Option<Vec<String>> {
+    let mut reader = BufReader::new(stream.try_clone().expect("can't clone stream"));
+    let mut writer = stream;
+    let mut lines = vec![];
+    let _ = writer.write_all(b"220 stub ESMTP\r\n");
+    let mut in_data = false;
+    let mut received = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        lines.push(line.trim_end_matches("\r\n").to_string());
+        let reply: &[u8] = if in_data {
+            if line != ".\r\n" {
+                continue;
+            }
+            received += 1;
+            in_data = false;
+            if received < messages {
+                b"250 queued\r\n"
+            } else {
+                // a pooled client won't quit until it's idle, so we stop here
+                let _ = writer.write_all(b"250 queued\r\n");
+                break;
+            }
+        } else if line.starts_with("EHLO") {
+            b"250-stub\r\n250 AUTH PLAIN LOGIN XOAUTH2\r\n"
+        } else if line.starts_with("AUTH") {
+            b"235 ok\r\n"
+        } else if line.starts_with("DATA") {
+            in_data = true;
+            b"354 go ahead\r\n"
+        } else {
+            b"250 ok\r\n"
+        };
+        let _ = writer.write_all(reply);
+    }
+    (received > 0).then_some(lines)
+}
@@ -0,0 +1,397 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use chrono::{Local, LocalResult, TimeZone};
+use eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{logging, Configuration};
+
+use super::locale::fill;
+use super::Event;
+use super::{decrypt_secret, encrypt_secret, escape_html, http_agent, monitor_host, send_json};
+
+/// The longest we will wait for a single long poll of the Bot API.
+pub const MAX_POLL_SECS: u64 = 25;
+
+/// The most characters that Telegram accepts in a message.
+const MAX_MESSAGE_CHARS: usize = 4096;
+
+/// The commands that allow-listed chats can send to the bot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BotCommand {
+    Status,
+    Check,
+}
+
+/// A Telegram bot that posts events to chats and answers status queries.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Telegram {
+    encrypted_bot_token: String,
+    pub chat_ids: Vec<i64>,
+    /// Chats whose commands are answered. If empty, the bot is never polled.
+    #[serde(default)]
+    pub allowed_chat_ids: Vec<i64>,
+    #[serde(default = "Telegram::default_api_url")]
+    pub api_url: String,
+    /// The id of the next update to fetch, so commands are answered only once.
+    #[serde(default)]
+    pub update_offset: i64,
+}
+
+impl Telegram {
+    pub fn default_api_url() -> String {
+        String::from("https://api.telegram.org")
+    }
+
+    pub fn new(bot_token: &str, chat_ids: Vec<i64>) -> Result<Self> {
+        Ok(Self {
+            encrypted_bot_token: encrypt_secret(bot_token)?,
+            chat_ids,
+            allowed_chat_ids: vec![],
+            api_url: Self::default_api_url(),
+            update_offset: 0,
+        })
+    }
+
+    fn method_url(&self, method: &str) -> Result<String> {
+        let token = decrypt_secret(&self.encrypted_bot_token)?;
+        Ok(format!("{}/bot{token}/{method}", self.api_url))
+    }
+
+    /// A copy of this bot that only notifies the given chat.
+    pub fn for_chat(&self, chat_id: i64) -> Self {
+        Self {
+            chat_ids: vec![chat_id],
+            ..self.clone()
+        }
+    }
+
+    /// Send the event to every chat, even if sending to an earlier one fails.
+    /// The first failure is returned and any others are logged.
+    pub fn send(&self, event: &Event) -> Result<()> {
+        let mut text = format!("<b>{}</b>", escape_html(&event.subject));
+        for line in event.body.iter() {
+            text.push('\n');
+            text.push_str(&escape_html(line));
+        }
+        let mut failures = self
+            .chat_ids
+            .iter()
+            .filter_map(|chat_id| self.send_message(*chat_id, &text).err());
+        match failures.next() {
+            None => Ok(()),
+            Some(first) => {
+                for err in failures {
+                    logging::error(&format!("{err:#}"));
+                }
+                Err(first)
+            }
+        }
+    }
+
+    /// Send the HTML to the chat, in as many messages as it takes.
+    pub fn send_message(&self, chat_id: i64, html: &str) -> Result<()> {
+        for text in split_message(html) {
+            let message = json!({"chat_id": chat_id, "text": text, "parse_mode": "HTML"});
+            let request = http_agent().post(&self.method_url("sendMessage")?);
+            send_json(request, &message.to_string())
+                .wrap_err(format!("Telegram message to chat {chat_id} failed"))?;
+        }
+        Ok(())
+    }
+
+    pub fn accepts_commands(&self) -> bool {
+        !self.allowed_chat_ids.is_empty()
+    }
+
+    /// Wait up to `timeout_secs` for commands from allow-listed chats.
+    ///
+    /// Commands from other chats, and messages that aren't commands, are ignored.
+    pub fn poll_commands(&mut self, timeout_secs: u64) -> Result<Vec<(i64, BotCommand)>> {
+        let timeout_secs = timeout_secs.min(MAX_POLL_SECS);
+        let query = json!({
+            "offset": self.update_offset,
+            "timeout": timeout_secs,
+            "allowed_updates": ["message"],
+        });
+        let request = http_agent()
+            .post(&self.method_url("getUpdates")?)
+            .set("Content-Type", "application/json");
+        let response: Value = request
+            .send_string(&query.to_string())
+            .wrap_err("Telegram update poll failed")?
+            .into_json()
+            .wrap_err("Telegram update poll returned invalid JSON")?;
+        let updates = response["result"]
+            .as_array()
+            .ok_or_else(|| eyre!("Telegram update poll failed: {response}"))?;
+        let mut commands = vec![];
+        for update in updates {
+            if let Some(id) = update["update_id"].as_i64() {
+                self.update_offset = self.update_offset.max(id + 1);
+            }
+            let message = &update["message"];
+            let (Some(chat_id), Some(text)) =
+                (message["chat"]["id"].as_i64(), message["text"].as_str())
+            else {
+                continue;
+            };
+            if !self.allowed_chat_ids.contains(&chat_id) {
+                continue;
+            }
+            // commands in group chats may be addressed as /status@SomeBot
+            let command = text.split_whitespace().next().unwrap_or_default();
+            match command.split('@').next().unwrap_or_default() {
+                "/status" => commands.push((chat_id, BotCommand::Status)),
+                "/check" => commands.push((chat_id, BotCommand::Check)),
+                _ => {}
+            }
+        }
+        Ok(commands)
+    }
+
+    pub fn interview() -> Result<Self> {
+        let token: String = dialoguer::Input::new()
+            .with_prompt("Telegram bot token")
+            .allow_empty(false)
+            .interact_text()
+            .wrap_err("Input error")?;
+        let chat_ids = interview_chat_ids("Chat IDs to notify", false)?;
+        let mut telegram = Self::new(token.trim(), chat_ids)?;
+        telegram.allowed_chat_ids = interview_chat_ids(
+            "Chat IDs allowed to query status (leave empty for none)",
+            true,
+        )?;
+        Ok(telegram)
+    }
+}
+
+fn interview_chat_ids(prompt: &str, allow_empty: bool) -> Result<Vec<i64>> {
+    let parse = |text: &str| -> std::result::Result<Vec<i64>, String> {
+        text.split([',', ' '])
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse().map_err(|_| format!("{id} is not a chat ID")))
+            .collect()
+    };
+    let text: String = dialoguer::Input::new()
+        .with_prompt(prompt)
+        .allow_empty(allow_empty)
+        .validate_with(|text: &String| parse(text).map(|_| ()))
+        .interact_text()
+        .wrap_err("Input error")?;
+    parse(&text).map_err(|err| eyre!(err))
+}
+
+/// The HTML split between lines into messages that Telegram accepts.
+/// A line that's too long for a message by itself is cut short.
+fn split_message(html: &str) -> Vec<String> {
+    let mut messages = vec![];
+    let mut lines: Vec<String> = vec![];
+    let mut length = 0;
+    for line in html.split('\n') {
+        let line = cut_line(line);
+        let line_length = line.chars().count();
+        // each line after the first also takes a newline
+        if !lines.is_empty() && length + 1 + line_length > MAX_MESSAGE_CHARS {
+            messages.push(lines.join("\n"));
+            lines.clear();
+        }
+        length = if lines.is_empty() {
+            line_length
+        } else {
+            length + 1 + line_length
+        };
+        lines.push(line);
+    }
+    messages.push(lines.join("\n"));
+    messages
+}
+
+/// The line of HTML, cut short with an ellipsis if it's too long for a message.
+fn cut_line(line: &str) -> String {
+    if line.chars().count() <= MAX_MESSAGE_CHARS {
+        return line.to_string();
+    }
+    let mut cut: String = line.chars().take(MAX_MESSAGE_CHARS - 1).collect();
+    // don't leave half an entity or tag at the end
+    for (start, end) in [('&', ';'), ('<', '>')] {
+        if let Some(index) = cut.rfind(start) {
+            if !cut[index..].contains(end) {
+                cut.truncate(index);
+            }
+        }
+    }
+    cut.push('…');
+    cut
+}
+
+/// A summary of the current monitoring state, in Telegram HTML, in the configured language.
+pub fn status_html(config: &Configuration) -> String {
+    let catalog = config.locale.catalog();
+    let mut lines = vec![format!(
+        "<b>{}</b>",
+        escape_html(&fill(catalog.status_title, &[("monitor", &monitor_host())]))
+    )];
+    let mut hosts: Vec<(&String, &String)> = config.state.iter().collect();
+    hosts.sort();
+    for (host, address) in hosts {
+        lines.push(fill(
+            &escape_html(catalog.status_address),
+            &[
+                ("host", &escape_html(host)),
+                ("address", &format!("<code>{}</code>", escape_html(address))),
+            ],
+        ));
+    }
+    match Local.timestamp_millis_opt(config.last_update) {
+        LocalResult::Single(last_check) if config.last_update > 0 => {
+            let time = config.locale.format_time(last_check);
+            lines.push(escape_html(&fill(catalog.last_check, &[("time", &time)])))
+        }
+        _ => lines.push(escape_html(catalog.status_no_check)),
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::State;
+
+    use super::super::locale::Locale;
+
+    use super::super::stub::StubServer;
+    use super::*;
+
+    fn test_bot(server: &StubServer) -> Telegram {
+        let mut telegram = Telegram::new("123:abc", vec![42, 43]).unwrap();
+        telegram.api_url = server.url.clone();
+        telegram
+    }
+
+    #[test]
+    fn test_send() {
+        let server = StubServer::start(vec![(200, "{\"ok\":true}"), (200, "{\"ok\":true}")]);
+        let telegram = test_bot(&server);
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        telegram.send(&event).expect("Telegram send failed");
+        for chat_id in [42, 43] {
+            let request = server.next_request();
+            assert_eq!(request.path, "/bot123:abc/sendMessage");
+            let message: Value = serde_json::from_str(&request.body).unwrap();
+            assert_eq!(message["chat_id"], chat_id);
+            assert_eq!(message["parse_mode"], "HTML");
+            assert!(message["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("<b>DNS change for some.host</b>\n"));
+        }
+    }
+
+    #[test]
+    fn test_send_to_every_chat() {
+        let server = StubServer::start(vec![
+            (400, "{\"ok\":false,\"description\":\"chat not found\"}"),
+            (200, "{\"ok\":true}"),
+        ]);
+        let telegram = test_bot(&server);
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let err = telegram.send(&event).expect_err("Telegram send succeeded");
+        assert!(err.to_string().contains("chat 42"));
+        server.next_request();
+        let message: Value = serde_json::from_str(&server.next_request().body).unwrap();
+        assert_eq!(message["chat_id"], 43);
+        assert_eq!(telegram.for_chat(43).chat_ids, vec![43]);
+    }
+
+    #[test]
+    fn test_poll_commands() {
+        let updates = r#"{"ok":true,"result":[
+            {"update_id":7,"message":{"chat":{"id":42},"text":"/status"}},
+            {"update_id":8,"message":{"chat":{"id":99},"text":"/status"}},
+            {"update_id":9,"message":{"chat":{"id":42},"text":"hello"}},
+            {"update_id":10,"message":{"chat":{"id":42},"text":"/check@DdnsBot now"}}
+        ]}"#;
+        let server = StubServer::start(vec![(200, updates)]);
+        let mut telegram = test_bot(&server);
+        assert!(!telegram.accepts_commands());
+        telegram.allowed_chat_ids = vec![42];
+        assert!(telegram.accepts_commands());
+        let commands = telegram.poll_commands(60).expect("poll failed");
+        assert_eq!(
+            commands,
+            vec![(42, BotCommand::Status), (42, BotCommand::Check)]
+        );
+        assert_eq!(telegram.update_offset, 11);
+        let request = server.next_request();
+        assert_eq!(request.path, "/bot123:abc/getUpdates");
+        let query: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(query["offset"], 0);
+        assert_eq!(query["timeout"], MAX_POLL_SECS);
+    }
+
+    #[test]
+    fn test_status_html() {
+        let mut config = Configuration::default();
+        config.state = State::from([
+            ("b.example".to_string(), "5.6.7.8".to_string()),
+            ("a.example".to_string(), "1.2.3.4".to_string()),
+        ]);
+        let status = status_html(&config);
+        assert!(status
+            .contains("-- Host: a.example, address: <code>1.2.3.4</code>\n-- Host: b.example"));
+        assert!(status.ends_with("No check has been done yet"));
+        config.last_update = Local::now().timestamp_millis();
+        assert!(status_html(&config).contains("Last check was at"));
+        config.locale = Locale::French;
+        let status = status_html(&config);
+        assert!(status.starts_with("<b>Surveillance du DNS dynamique depuis "));
+        assert!(status.contains("-- Hôte : a.example, adresse : <code>1.2.3.4</code>"));
+        assert!(status.contains("La dernière vérification a eu lieu le "));
+    }
+
+    #[test]
+    fn test_long_messages() {
+        let server = StubServer::start(vec![(200, "{\"ok\":true}"), (200, "{\"ok\":true}")]);
+        let telegram = test_bot(&server);
+        let lines: Vec<String> = (0..300)
+            .map(|i| format!("{i:04} {}", "x".repeat(15)))
+            .collect();
+        telegram
+            .send_message(42, &lines.join("\n"))
+            .expect("Telegram send failed");
+        let mut received = vec![];
+        for _ in 0..2 {
+            let message: Value = serde_json::from_str(&server.next_request().body).unwrap();
+            let text = message["text"].as_str().unwrap().to_string();
+            assert!(text.chars().count() <= MAX_MESSAGE_CHARS);
+            received.push(text);
+        }
+        assert_eq!(received.join("\n"), lines.join("\n"));
+        let long = format!("{}&amp;", "x".repeat(MAX_MESSAGE_CHARS - 3));
+        let cut = split_message(&long);
+        assert_eq!(cut, vec![format!("{}…", "x".repeat(MAX_MESSAGE_CHARS - 3))]);
+    }
+}
@@ -0,0 +1,294 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, LocalResult, TimeZone};
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+
+use crate::logging;
+use crate::Configuration;
+
+use super::diagnostics::Diagnostics;
+use super::{Event, EventKind};
+
+/// Per-host metadata, such as an owner or a site, that templates can refer to.
+pub type HostMetadata = BTreeMap<String, BTreeMap<String, String>>;
+
+/// User-defined subjects and bodies for notifications.
+///
+/// Templates are files in the template directory named `<event>-subject.txt`
+/// and `<event>-body.txt`, where `<event>` is the name of any kind of event:
+/// `initial`, `change`, `error`, `digest`, `recovery`, `heartbeat` or `report`.
+/// Each event in a digest is rendered with its own templates first.
+/// A subdirectory named for a channel type (such as `email` or `slack`) can
+/// hold templates that apply only to that type of channel. Any template that
+/// is missing, or that fails to render, falls back to the built-in text.
+pub struct Templates {
+    dir: Option<PathBuf>,
+}
+
+/// The variables available to templates.
+#[derive(Serialize)]
+struct Context<'a> {
+    event: &'a str,
+    channel: &'a str,
+    monitor_host: &'a str,
+    locale: &'static str,
+    timestamp: String,
+    local_timestamp: String,
+    timestamp_rfc3339: String,
+    first_check: bool,
+    last_check: Option<String>,
+    host: Option<&'a str>,
+    old_address: Option<&'a str>,
+    new_address: Option<&'a str>,
+    addresses: &'a BTreeMap<String, String>,
+    error: Option<&'a str>,
+    events: &'a [Event],
+    diagnostics: Option<&'a Diagnostics>,
+    host_metadata: BTreeMap<String, String>,
+    metadata: &'a HostMetadata,
+    default_subject: &'a str,
+    default_body: String,
+}
+
+impl Templates {
+    /// The templates for a configuration, which are in its `templateDir` if
+    /// that's set, and otherwise in the `templates` directory next to the
+    /// configuration file.
+    pub fn new(config: &Configuration) -> Self {
+        let dir = if !config.template_dir.is_empty() {
+            Some(PathBuf::from(&config.template_dir))
+        } else if config.is_file_based {
+            crate::configure::config_path()
+                .ok()
+                .and_then(|path| path.parent().map(|dir| dir.join("templates")))
+        } else {
+            None
+        };
+        Self { dir }
+    }
+
+    /// The event with its subject and body rendered for the given channel type.
+    ///
+    /// The parts of a digest are rendered first, and make up its default text.
+    pub fn render(&self, config: &Configuration, event: &Event, channel: &str) -> Event {
+        let Some(dir) = self.dir.as_ref().filter(|dir| dir.is_dir()) else {
+            return event.clone();
+        };
+        let mut rendered = if event.event == EventKind::Digest {
+            let parts = event
+                .parts()
+                .iter()
+                .map(|part| self.render(config, part, channel))
+                .collect();
+            let mut digest = Event::digest(parts);
+            digest.timestamp = event.timestamp;
+            digest.diagnostics = event.diagnostics.clone();
+            digest
+        } else {
+            event.clone()
+        };
+        let context = Context::new(config, &rendered, channel);
+        let kind = event.event.as_str();
+        let subject = render_file(dir, channel, &format!("{kind}-subject.txt"), &context);
+        let body = render_file(dir, channel, &format!("{kind}-body.txt"), &context);
+        if let Some(subject) = subject {
+            // a subject is a single line
+            rendered.subject = subject.lines().collect::<Vec<&str>>().join(" ");
+        }
+        if let Some(body) = body {
+            rendered.body = body.lines().map(String::from).collect();
+        }
+        rendered
+    }
+}
+
+impl<'a> Context<'a> {
+    fn new(config: &'a Configuration, event: &'a Event, channel: &'a str) -> Self {
+        let last_check = match Local.timestamp_millis_opt(config.last_update) {
+            LocalResult::Single(last_check) if config.last_update > 0 => {
+                Some(last_check.to_rfc2822())
+            }
+            _ => None,
+        };
+        let host_metadata = event
+            .host
+            .as_ref()
+            .and_then(|host| config.host_metadata.get(host))
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            event: event.event.as_str(),
+            channel,
+            monitor_host: &event.monitor_host,
+            locale: event.locale.code(),
+            timestamp: event.timestamp.to_rfc2822(),
+            local_timestamp: event.locale.format_time(event.timestamp),
+            timestamp_rfc3339: rfc3339(&event.timestamp),
+            first_check: config.last_update <= 0,
+            last_check,
+            host: event.host.as_deref(),
+            old_address: event.old_address.as_deref(),
+            new_address: event.new_address.as_deref(),
+            addresses: &event.addresses,
+            error: event.error.as_deref(),
+            events: &event.events,
+            diagnostics: event.diagnostics.as_deref(),
+            host_metadata,
+            metadata: &config.host_metadata,
+            default_subject: &event.subject,
+            default_body: event.body.join("\n"),
+        }
+    }
+}
+
+fn rfc3339(timestamp: &DateTime<Local>) -> String {
+    timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+/// Render the channel's template with the given name, or else the general one.
+fn render_file(dir: &Path, channel: &str, name: &str, context: &Context) -> Option<String> {
+    let path = [dir.join(channel).join(name), dir.join(name)]
+        .into_iter()
+        .find(|path| path.is_file())?;
+    match render_path(&path, context) {
+        Ok(text) => Some(text),
+        Err(err) => {
+            logging::warning(&format!("Using the built-in text instead: {err:#}"));
+            None
+        }
+    }
+}
+
+fn render_path(path: &Path, context: &Context) -> Result<String> {
+    let source = std::fs::read_to_string(path)
+        .wrap_err(format!("Can't read template {}", path.display()))?;
+    let text = minijinja::Environment::new()
+        .render_str(&source, context)
+        .wrap_err(format!("Can't render template {}", path.display()))?;
+    Ok(text.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TemplateDir(PathBuf);
+
+    impl TemplateDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ddns-templates-{name}-{}", std::process::id()));
+            for (path, text) in files {
+                let path = dir.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, text).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TemplateDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_config(dir: &TemplateDir) -> Configuration {
+        let mut config = Configuration::default();
+        config.template_dir = dir.0.display().to_string();
+        config.last_update = 1;
+        config.host_metadata.insert(
+            String::from("some.host"),
+            BTreeMap::from([(String::from("owner"), String::from("Pat"))]),
+        );
+        config
+    }
+
+    #[test]
+    fn test_overrides_and_fallbacks() {
+        let dir = TemplateDir::new(
+            "overrides",
+            &[
+                ("change-subject.txt", "{{ host }} moved to {{ new_address }}\n"),
+                (
+                    "change-body.txt",
+                    "{{ host }} ({{ host_metadata.owner }}) was {{ old_address }}.\nSent from {{ monitor_host }}.\n",
+                ),
+                ("slack/change-subject.txt", "[{{ channel }}] {{ default_subject }}"),
+            ],
+        );
+        let config = test_config(&dir);
+        let templates = Templates::new(&config);
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let email = templates.render(&config, &event, "email");
+        assert_eq!(email.subject, "some.host moved to 5.6.7.8");
+        assert_eq!(
+            email.body,
+            vec![
+                String::from("some.host (Pat) was 1.2.3.4."),
+                format!("Sent from {}.", event.monitor_host),
+            ]
+        );
+        let slack = templates.render(&config, &event, "slack");
+        assert_eq!(slack.subject, "[slack] DNS change for some.host");
+        assert_eq!(slack.body, email.body);
+        // there are no error templates, so the built-in text is used
+        let error = Event::error(&eyre::eyre!("lookup failed"));
+        assert_eq!(templates.render(&config, &error, "email"), error);
+    }
+
+    #[test]
+    fn test_broken_template_falls_back() {
+        let dir = TemplateDir::new("broken", &[("initial-subject.txt", "{% if %}")]);
+        let config = test_config(&dir);
+        let event = Event::initial(&config);
+        let rendered = Templates::new(&config).render(&config, &event, "email");
+        assert_eq!(rendered.subject, event.subject);
+    }
+
+    #[test]
+    fn test_initial_addresses() {
+        let dir = TemplateDir::new(
+            "initial",
+            &[(
+                "initial-body.txt",
+                "{% for host, address in addresses|items %}{{ host }}={{ address }}\n{% endfor %}",
+            )],
+        );
+        let mut config = test_config(&dir);
+        config
+            .state
+            .insert(String::from("a.host"), String::from("1.1.1.1"));
+        config
+            .state
+            .insert(String::from("b.host"), String::from("2.2.2.2"));
+        let event = Event::initial(&config);
+        let rendered = Templates::new(&config).render(&config, &event, "email");
+        assert_eq!(rendered.body, vec!["a.host=1.1.1.1", "b.host=2.2.2.2"]);
+    }
+}
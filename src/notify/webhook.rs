@@ -0,0 +1,194 @@
+/*
+MIT License
+
+Copyright (c) 2023 Daniel Brotsky
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+ */
+use std::time::Duration;
+
+use eyre::{Result, WrapErr};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::{decrypt_secret, encrypt_secret, http_agent, send_json, Event};
+
+pub const EVENT_HEADER: &str = "X-DDNS-Event";
+pub const SIGNATURE_HEADER: &str = "X-DDNS-Signature";
+
+/// A generic HTTP(S) endpoint that receives each event as a JSON payload.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    encrypted_secret: String,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay_secs() -> u64 {
+    2
+}
+
+impl Webhook {
+    pub fn new(url: &str, secret: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            encrypted_secret: encrypt_secret(secret)?,
+            retries: default_retries(),
+            retry_delay_secs: default_retry_delay_secs(),
+        })
+    }
+
+    pub fn secret(&self) -> Result<String> {
+        decrypt_secret(&self.encrypted_secret)
+    }
+
+    /// The value of the signature header for the given payload, if there is a secret.
+    pub fn signature(&self, payload: &str) -> Result<Option<String>> {
+        let secret = self.secret()?;
+        if secret.is_empty() {
+            return Ok(None);
+        }
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).wrap_err("Invalid webhook secret")?;
+        mac.update(payload.as_bytes());
+        let digest = hex::encode(mac.finalize().into_bytes());
+        Ok(Some(format!("sha256={digest}")))
+    }
+
+    /// Post the event, retrying with exponential backoff on failure.
+    pub fn post(&self, event: &Event) -> Result<()> {
+        let payload = serde_json::to_string(event).wrap_err("Event cannot be serialized")?;
+        let signature = self.signature(&payload)?;
+        let agent = http_agent();
+        let mut delay = self.retry_delay_secs;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut request = agent
+                .post(&self.url)
+                .set(EVENT_HEADER, event.event.as_str());
+            if let Some(signature) = &signature {
+                request = request.set(SIGNATURE_HEADER, signature);
+            }
+            let err = match send_json(request, &payload) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            if attempt > self.retries {
+                return Err(err.wrap_err(format!("Webhook post failed after {attempt} attempts")));
+            }
+            std::thread::sleep(Duration::from_secs(delay));
+            delay *= 2;
+        }
+    }
+
+    pub fn interview() -> Result<Self> {
+        let url: String = dialoguer::Input::new()
+            .with_prompt("Webhook URL")
+            .allow_empty(false)
+            .validate_with(|url: &String| -> std::result::Result<(), String> {
+                if url.starts_with("https://") || url.starts_with("http://") {
+                    Ok(())
+                } else {
+                    Err(format!("{url} is not an http or https URL"))
+                }
+            })
+            .interact()
+            .wrap_err("Input error")?;
+        let secret: String = dialoguer::Input::new()
+            .with_prompt("Signing secret (leave empty for unsigned posts)")
+            .allow_empty(true)
+            .interact()
+            .wrap_err("Input error")?;
+        Self::new(url.trim(), secret.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::stub::StubServer;
+    use super::*;
+
+    fn test_webhook(url: &str, secret: &str) -> Webhook {
+        let mut webhook = Webhook::new(url, secret).expect("can't create webhook");
+        webhook.retry_delay_secs = 0;
+        webhook
+    }
+
+    #[test]
+    fn test_signed_post() {
+        let server = StubServer::start(vec![(200, "{}")]);
+        let webhook = test_webhook(&format!("{}/hooks/ddns", server.url), "top secret");
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        webhook.post(&event).expect("webhook post failed");
+        let request = server.next_request();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/hooks/ddns");
+        assert_eq!(request.headers[&EVENT_HEADER.to_lowercase()], "change");
+        let expected = webhook.signature(&request.body).unwrap().unwrap();
+        assert_eq!(request.headers[&SIGNATURE_HEADER.to_lowercase()], expected);
+        let payload: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(payload["newAddress"], "5.6.7.8");
+    }
+
+    #[test]
+    fn test_unsigned_post_retries() {
+        let server = StubServer::start(vec![(503, ""), (500, ""), (204, "")]);
+        let webhook = test_webhook(&server.url, "");
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        webhook.post(&event).expect("webhook post failed");
+        for _ in 0..3 {
+            let request = server.next_request();
+            assert!(!request
+                .headers
+                .contains_key(&SIGNATURE_HEADER.to_lowercase()));
+        }
+    }
+
+    #[test]
+    fn test_post_gives_up() {
+        let server = StubServer::start(vec![(500, ""), (500, "")]);
+        let mut webhook = test_webhook(&server.url, "");
+        webhook.retries = 1;
+        let event = Event::change("some.host", "1.2.3.4", "5.6.7.8");
+        let err = webhook.post(&event).expect_err("webhook post succeeded");
+        assert!(err.to_string().contains("after 2 attempts"));
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231 test case 2
+        let webhook = test_webhook("http://localhost", "Jefe");
+        let signature = webhook.signature("what do ya want for nothing?").unwrap();
+        assert_eq!(
+            signature.unwrap(),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}